@@ -0,0 +1,161 @@
+// Compiles keymap.txt (or the file named by PADTARUST_KEYMAP) into the const expression that
+// src/default_keymap.rs includes as `Keymap::default()`. The parser is the firmware's own, so the
+// file is checked exactly as the configuration tools would check it.
+
+#[allow(dead_code)]
+#[path = "src/color.rs"]
+mod color;
+#[allow(dead_code)]
+#[path = "src/joystick.rs"]
+mod joystick;
+#[allow(dead_code)]
+#[path = "src/keymap_common.rs"]
+mod keymap_common;
+#[allow(dead_code)]
+#[path = "src/keymap_text.rs"]
+mod keymap_text;
+#[allow(dead_code)]
+#[path = "src/wasd.rs"]
+mod wasd;
+
+use keymap_common::{Keymap, Mapping};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+const DEFAULT_KEYMAP_FILE: &str = "keymap.txt";
+
+// The firmware's Keymap::default() is generated by this script, so the parser starts from
+// a blank keymap here instead.
+impl Keymap {
+    pub fn default() -> Keymap {
+        Keymap::BLANK
+    }
+}
+
+fn mapping(m: &Mapping) -> String {
+    format!(
+        "Mapping {{ action: KeyboardAction::{}, button: Keyboard::{}, consumer_button: Consumer::{} }}",
+        m.action, m.button, m.consumer_button
+    )
+}
+
+fn layers(mappings: &[Mapping; 4]) -> String {
+    let mappings: Vec<String> = mappings.iter().map(mapping).collect();
+    format!("[{}]", mappings.join(", "))
+}
+
+fn keymap_expression(keymap: &Keymap) -> String {
+    let mut out = String::new();
+    out.push_str("Keymap {\n    key_mappings: [\n");
+    for key in &keymap.key_mappings {
+        writeln!(out, "        {},", layers(key)).unwrap();
+    }
+    out.push_str("    ],\n");
+    writeln!(
+        out,
+        "    joy_button_mappings: {},",
+        layers(&keymap.joy_button_mappings)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    scroll_button_mappings: {},",
+        layers(&keymap.scroll_button_mappings)
+    )
+    .unwrap();
+    out.push_str("    wasd_mappings: [\n");
+    for direction in &keymap.wasd_mappings {
+        writeln!(out, "        {},", layers(direction)).unwrap();
+    }
+    out.push_str("    ],\n");
+    out.push_str("    wasd_run_mappings: [\n");
+    for direction in &keymap.wasd_run_mappings {
+        writeln!(out, "        {},", layers(direction)).unwrap();
+    }
+    out.push_str("    ],\n");
+    writeln!(out, "    joy_x_center: {},", keymap.joy_x_center).unwrap();
+    writeln!(out, "    joy_y_center: {},", keymap.joy_y_center).unwrap();
+    writeln!(out, "    joy_x_min: {},", keymap.joy_x_min).unwrap();
+    writeln!(out, "    joy_x_max: {},", keymap.joy_x_max).unwrap();
+    writeln!(out, "    joy_y_min: {},", keymap.joy_y_min).unwrap();
+    writeln!(out, "    joy_y_max: {},", keymap.joy_y_max).unwrap();
+    writeln!(out, "    joy_x_y_rotation: {},", keymap.joy_x_y_rotation).unwrap();
+    let stick = &keymap.joy_response;
+    out.push_str("    joy_response: StickResponse {\n");
+    writeln!(out, "        axial_deadzone: {},", stick.axial_deadzone).unwrap();
+    writeln!(out, "        radial_deadzone: {},", stick.radial_deadzone).unwrap();
+    writeln!(out, "        outer_deadzone: {},", stick.outer_deadzone).unwrap();
+    writeln!(out, "        anti_deadzone: {},", stick.anti_deadzone).unwrap();
+    writeln!(out, "        curve: ResponseCurve::{:?},", stick.curve).unwrap();
+    out.push_str("    },\n");
+    let wasd = &keymap.wasd;
+    out.push_str("    wasd: WasdConfig {\n");
+    writeln!(out, "        sectors: Sectors::{:?},", wasd.sectors).unwrap();
+    writeln!(out, "        engage: {},", wasd.engage).unwrap();
+    writeln!(out, "        release: {},", wasd.release).unwrap();
+    writeln!(out, "        diagonal_overlap: {},", wasd.diagonal_overlap).unwrap();
+    writeln!(out, "        hysteresis: {},", wasd.hysteresis).unwrap();
+    writeln!(out, "        pulse_period_ms: {},", wasd.pulse_period_ms).unwrap();
+    writeln!(out, "        min_pulse_ms: {},", wasd.min_pulse_ms).unwrap();
+    out.push_str("    },\n");
+    let runs: Vec<String> = keymap
+        .wasd_run
+        .iter()
+        .map(|r| {
+            format!(
+                "WasdRun {{ threshold: {}, mode: RunMode::{:?} }}",
+                r.threshold, r.mode
+            )
+        })
+        .collect();
+    writeln!(out, "    wasd_run: [{}],", runs.join(", ")).unwrap();
+    let colors: Vec<String> = keymap
+        .layer_colors
+        .iter()
+        .map(|c| format!("Hsv::new({}, {}, {})", c.h, c.s, c.v))
+        .collect();
+    writeln!(out, "    layer_colors: [{}],", colors.join(", ")).unwrap();
+    writeln!(out, "    version: {},", keymap.version).unwrap();
+    out.push('}');
+    out
+}
+
+/// Points at the offending spot the way rustc does, so the message reads well inside
+/// `compile_error!`.
+fn error_message(path: &str, text: &str, error: &keymap_text::ParseError) -> String {
+    let line = text.lines().nth(error.line as usize - 1).unwrap_or("");
+    format!(
+        "{}:{}:{}: {}\n  |\n  | {}\n  | {:>width$}",
+        path,
+        error.line,
+        error.column,
+        error.kind,
+        line,
+        "^",
+        width = error.column as usize
+    )
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/color.rs");
+    println!("cargo:rerun-if-changed=src/joystick.rs");
+    println!("cargo:rerun-if-changed=src/keymap_common.rs");
+    println!("cargo:rerun-if-changed=src/keymap_text.rs");
+    println!("cargo:rerun-if-changed=src/wasd.rs");
+    println!("cargo:rerun-if-env-changed=PADTARUST_KEYMAP");
+
+    let file = std::env::var("PADTARUST_KEYMAP").unwrap_or(DEFAULT_KEYMAP_FILE.into());
+    println!("cargo:rerun-if-changed={}", file);
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let text = std::fs::read_to_string(manifest_dir.join(&file))
+        .unwrap_or_else(|e| panic!("cannot read keymap file {}: {}", file, e));
+
+    // errors become a compile_error! in the included file, which cargo reports like any other
+    let generated = match keymap_text::parse(&text) {
+        Ok(keymap) => keymap_expression(&keymap),
+        Err(error) => format!("compile_error!({:?})", error_message(&file, &text, &error)),
+    };
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("default_keymap.rs"), generated).unwrap();
+}
@@ -0,0 +1,392 @@
+use padtarust::color::Hsv;
+use padtarust::config_protocol::{command, status, LiveState, PROTOCOL_VERSION, REPORT_LEN};
+use padtarust::joystick::{CalibrationPhase, ResponseCurve, StickResponse, CURVE_POINTS};
+use padtarust::keymap_common::{
+    DecodeError, KeyboardAction, Keymap, Mapping, INPUT_COUNT, LAYER_COUNT,
+};
+use padtarust::wasd::{RunMode, Sectors, WasdConfig, WasdRun};
+use std::fmt;
+
+/// Carries one request to the pad and brings back its response.
+pub trait Transport {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN], Error>;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Transport(String),
+    Timeout,
+    /// The response was for a different command than the one sent
+    UnexpectedResponse { sent: u8, received: u8 },
+    Status { command: u8, status: u8 },
+    BadMapping(DecodeError),
+    /// The firmware speaks another version of the configuration protocol
+    Incompatible { protocol: u8 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no padtarust keypad found"),
+            Error::Transport(e) => write!(f, "{}", e),
+            Error::Timeout => write!(f, "the keypad did not answer"),
+            Error::UnexpectedResponse { sent, received } => write!(
+                f,
+                "sent command {:#04x} but got a response to {:#04x}",
+                sent, received
+            ),
+            Error::Status { command, status } => {
+                let reason = match *status {
+                    status::UNKNOWN_COMMAND => "unknown command",
+                    status::INVALID_ARGUMENT => "invalid argument",
+                    status::STORAGE_ERROR => "storage error",
+                    _ => "unknown error",
+                };
+                write!(f, "command {:#04x} failed: {}", command, reason)
+            }
+            Error::BadMapping(e) => write!(f, "the keypad sent a setting we can't read: {:?}", e),
+            Error::Incompatible { protocol } => write!(
+                f,
+                "the keypad speaks protocol version {}, this tool speaks {}",
+                protocol, PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Info {
+    pub protocol_version: u8,
+    pub keymap_version: u8,
+    pub layers: u8,
+    pub inputs: u8,
+    pub firmware_version: String,
+}
+
+#[derive(Copy, Clone)]
+pub struct Calibration {
+    pub x_center: u16,
+    pub y_center: u16,
+    pub rotation: u16,
+    pub x_min: u16,
+    pub x_max: u16,
+    pub y_min: u16,
+    pub y_max: u16,
+}
+
+fn u16_at(response: &[u8; REPORT_LEN], index: usize) -> u16 {
+    u16::from_le_bytes([response[index], response[index + 1]])
+}
+
+/// The configuration protocol, see padtarust::config_protocol, over any transport.
+pub struct Device<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Device<T> {
+    /// Wraps `transport`, checking that the firmware on the other end speaks our protocol.
+    pub fn new(transport: T) -> Result<Device<T>, Error> {
+        let mut device = Device { transport };
+        let info = device.info()?;
+        if info.protocol_version != PROTOCOL_VERSION {
+            return Err(Error::Incompatible {
+                protocol: info.protocol_version,
+            });
+        }
+        Ok(device)
+    }
+
+    fn request(&mut self, command: u8, args: &[u8]) -> Result<[u8; REPORT_LEN], Error> {
+        let mut request = [0u8; REPORT_LEN];
+        request[0] = command;
+        request[1..1 + args.len()].copy_from_slice(args);
+        let response = self.transport.exchange(&request)?;
+        if response[0] != command {
+            return Err(Error::UnexpectedResponse {
+                sent: command,
+                received: response[0],
+            });
+        }
+        match response[1] {
+            status::OK => Ok(response),
+            status => Err(Error::Status { command, status }),
+        }
+    }
+
+    pub fn info(&mut self) -> Result<Info, Error> {
+        let response = self.request(command::GET_INFO, &[])?;
+        let version = &response[6..];
+        let len = version.iter().position(|b| *b == 0).unwrap_or(version.len());
+        Ok(Info {
+            protocol_version: response[2],
+            keymap_version: response[3],
+            layers: response[4],
+            inputs: response[5],
+            firmware_version: String::from_utf8_lossy(&version[..len]).into_owned(),
+        })
+    }
+
+    pub fn mapping(&mut self, layer: usize, input: usize) -> Result<Mapping, Error> {
+        let response = self.request(command::GET_MAPPING, &[layer as u8, input as u8])?;
+        Mapping::from_raw(response[2], response[3], u16_at(&response, 4)).map_err(Error::BadMapping)
+    }
+
+    pub fn set_mapping(&mut self, layer: usize, input: usize, mapping: &Mapping) -> Result<(), Error> {
+        let (action, button, consumer_button) = mapping.to_raw();
+        let [consumer_lo, consumer_hi] = consumer_button.to_le_bytes();
+        self.request(
+            command::SET_MAPPING,
+            &[layer as u8, input as u8, action, button, consumer_lo, consumer_hi],
+        )?;
+        Ok(())
+    }
+
+    pub fn calibration(&mut self) -> Result<Calibration, Error> {
+        let response = self.request(command::GET_CALIBRATION, &[])?;
+        Ok(Calibration {
+            x_center: u16_at(&response, 2),
+            y_center: u16_at(&response, 4),
+            rotation: u16_at(&response, 6),
+            x_min: u16_at(&response, 8),
+            x_max: u16_at(&response, 10),
+            y_min: u16_at(&response, 12),
+            y_max: u16_at(&response, 14),
+        })
+    }
+
+    pub fn set_calibration(&mut self, calibration: &Calibration) -> Result<(), Error> {
+        let mut args = [0u8; 14];
+        let values = [
+            calibration.x_center,
+            calibration.y_center,
+            calibration.rotation,
+            calibration.x_min,
+            calibration.x_max,
+            calibration.y_min,
+            calibration.y_max,
+        ];
+        for (chunk, value) in args.chunks_mut(2).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        self.request(command::SET_CALIBRATION, &args)?;
+        Ok(())
+    }
+
+    /// Moves the pad's own calibration routine along by `step`, see CALIBRATE, and returns the
+    /// phase it is in afterwards.
+    pub fn calibrate(&mut self, step: u8) -> Result<CalibrationPhase, Error> {
+        let response = self.request(command::CALIBRATE, &[step])?;
+        Ok(match response[2] {
+            1 => CalibrationPhase::Centering,
+            2 => CalibrationPhase::Sweeping,
+            _ => CalibrationPhase::Idle,
+        })
+    }
+
+    pub fn response(&mut self) -> Result<StickResponse, Error> {
+        let response = self.request(command::GET_RESPONSE, &[])?;
+        let mut points = [0; CURVE_POINTS];
+        for (i, point) in points.iter_mut().enumerate() {
+            *point = u16_at(&response, 13 + i * 2);
+        }
+        let kind = response[10];
+        let curve = ResponseCurve::from_raw(kind, u16_at(&response, 11), points)
+            .ok_or(Error::BadMapping(DecodeError::UnknownCurve(kind)))?;
+        Ok(StickResponse {
+            axial_deadzone: u16_at(&response, 2),
+            radial_deadzone: u16_at(&response, 4),
+            outer_deadzone: u16_at(&response, 6),
+            anti_deadzone: u16_at(&response, 8),
+            curve,
+        })
+    }
+
+    pub fn set_response(&mut self, stick: &StickResponse) -> Result<(), Error> {
+        let (kind, expo, points) = stick.curve.to_raw();
+        let mut args = vec![];
+        for value in [
+            stick.axial_deadzone,
+            stick.radial_deadzone,
+            stick.outer_deadzone,
+            stick.anti_deadzone,
+        ] {
+            args.extend_from_slice(&value.to_le_bytes());
+        }
+        args.push(kind);
+        args.extend_from_slice(&expo.to_le_bytes());
+        for point in points {
+            args.extend_from_slice(&point.to_le_bytes());
+        }
+        self.request(command::SET_RESPONSE, &args)?;
+        Ok(())
+    }
+
+    pub fn wasd(&mut self) -> Result<WasdConfig, Error> {
+        let response = self.request(command::GET_WASD, &[])?;
+        let sectors = Sectors::from_count(response[2])
+            .ok_or(Error::BadMapping(DecodeError::UnknownSectors(response[2])))?;
+        Ok(WasdConfig {
+            sectors,
+            engage: u16_at(&response, 3),
+            release: u16_at(&response, 5),
+            diagonal_overlap: u16_at(&response, 7),
+            hysteresis: u16_at(&response, 9),
+            pulse_period_ms: u16_at(&response, 11),
+            min_pulse_ms: u16_at(&response, 13),
+        })
+    }
+
+    pub fn set_wasd(&mut self, wasd: &WasdConfig) -> Result<(), Error> {
+        let mut args = vec![wasd.sectors.count()];
+        for value in [
+            wasd.engage,
+            wasd.release,
+            wasd.diagonal_overlap,
+            wasd.hysteresis,
+            wasd.pulse_period_ms,
+            wasd.min_pulse_ms,
+        ] {
+            args.extend_from_slice(&value.to_le_bytes());
+        }
+        self.request(command::SET_WASD, &args)?;
+        Ok(())
+    }
+
+    /// Run threshold and mode of `layer`, and its run mapping for each direction.
+    pub fn wasd_run(&mut self, layer: usize) -> Result<(WasdRun, [Mapping; 4]), Error> {
+        let response = self.request(command::GET_WASD_RUN, &[layer as u8])?;
+        let mode = RunMode::from_raw(response[4])
+            .ok_or(Error::BadMapping(DecodeError::UnknownRunMode(response[4])))?;
+        let mut mappings = [Mapping::from_action(KeyboardAction::None); 4];
+        for (i, mapping) in mappings.iter_mut().enumerate() {
+            let at = 5 + i * 4;
+            *mapping = Mapping::from_raw(response[at], response[at + 1], u16_at(&response, at + 2))
+                .map_err(Error::BadMapping)?;
+        }
+        let run = WasdRun {
+            threshold: u16_at(&response, 2),
+            mode,
+        };
+        Ok((run, mappings))
+    }
+
+    pub fn set_wasd_run(
+        &mut self,
+        layer: usize,
+        run: &WasdRun,
+        mappings: &[Mapping; 4],
+    ) -> Result<(), Error> {
+        let mut args = vec![layer as u8];
+        args.extend_from_slice(&run.threshold.to_le_bytes());
+        args.push(run.mode.to_raw());
+        for mapping in mappings {
+            let (action, button, consumer_button) = mapping.to_raw();
+            args.extend_from_slice(&[action, button]);
+            args.extend_from_slice(&consumer_button.to_le_bytes());
+        }
+        self.request(command::SET_WASD_RUN, &args)?;
+        Ok(())
+    }
+
+    pub fn layer_color(&mut self, layer: usize) -> Result<Hsv, Error> {
+        let response = self.request(command::GET_LAYER_COLOR, &[layer as u8])?;
+        Ok(Hsv::new(response[2], response[3], response[4]))
+    }
+
+    pub fn set_layer_color(&mut self, layer: usize, color: Hsv) -> Result<(), Error> {
+        self.request(
+            command::SET_LAYER_COLOR,
+            &[layer as u8, color.h, color.s, color.v],
+        )?;
+        Ok(())
+    }
+
+    pub fn state(&mut self) -> Result<LiveState, Error> {
+        let response = self.request(command::GET_STATE, &[])?;
+        Ok(LiveState {
+            layer: response[2],
+            wasd_mode: response[3] != 0,
+            pressed: u32::from_le_bytes([response[4], response[5], response[6], response[7]]),
+            joy_x: u16_at(&response, 8),
+            joy_y: u16_at(&response, 10),
+        })
+    }
+
+    /// Log text from position `from` on, with the position it really starts at; see GET_LOG.
+    pub fn log(&mut self, from: u32) -> Result<(u32, Vec<u8>), Error> {
+        let response = self.request(command::GET_LOG, &from.to_le_bytes())?;
+        let start = u32::from_le_bytes([response[2], response[3], response[4], response[5]]);
+        let len = (response[6] as usize).min(REPORT_LEN - 7);
+        Ok((start, response[7..7 + len].to_vec()))
+    }
+
+    pub fn save(&mut self) -> Result<(), Error> {
+        self.request(command::SAVE, &[])?;
+        Ok(())
+    }
+
+    pub fn reload_defaults(&mut self) -> Result<(), Error> {
+        self.request(command::RELOAD_DEFAULTS, &[])?;
+        Ok(())
+    }
+
+    /// Reads the whole live keymap, one request per value.
+    pub fn read_keymap(&mut self) -> Result<Keymap, Error> {
+        let mut keymap = Keymap::default();
+        for input in 0..INPUT_COUNT {
+            for layer in 0..LAYER_COUNT {
+                let mapping = self.mapping(layer, input)?;
+                keymap.input_mappings_mut(input).unwrap()[layer] = mapping;
+            }
+        }
+        let calibration = self.calibration()?;
+        keymap.joy_x_center = calibration.x_center;
+        keymap.joy_y_center = calibration.y_center;
+        keymap.joy_x_y_rotation = calibration.rotation;
+        keymap.joy_x_min = calibration.x_min;
+        keymap.joy_x_max = calibration.x_max;
+        keymap.joy_y_min = calibration.y_min;
+        keymap.joy_y_max = calibration.y_max;
+        keymap.joy_response = self.response()?;
+        keymap.wasd = self.wasd()?;
+        for layer in 0..LAYER_COUNT {
+            let (run, mappings) = self.wasd_run(layer)?;
+            keymap.wasd_run[layer] = run;
+            for (direction, mapping) in keymap.wasd_run_mappings.iter_mut().zip(mappings) {
+                direction[layer] = mapping;
+            }
+            keymap.layer_colors[layer] = self.layer_color(layer)?;
+        }
+        Ok(keymap)
+    }
+
+    /// Replaces the whole live keymap. Nothing reaches flash until `save`.
+    pub fn write_keymap(&mut self, keymap: &Keymap) -> Result<(), Error> {
+        for input in 0..INPUT_COUNT {
+            for (layer, mapping) in keymap.input_mappings(input).unwrap().iter().enumerate() {
+                self.set_mapping(layer, input, mapping)?;
+            }
+        }
+        self.set_calibration(&Calibration {
+            x_center: keymap.joy_x_center,
+            y_center: keymap.joy_y_center,
+            rotation: keymap.joy_x_y_rotation,
+            x_min: keymap.joy_x_min,
+            x_max: keymap.joy_x_max,
+            y_min: keymap.joy_y_min,
+            y_max: keymap.joy_y_max,
+        })?;
+        self.set_response(&keymap.joy_response)?;
+        self.set_wasd(&keymap.wasd)?;
+        for (layer, run) in keymap.wasd_run.iter().enumerate() {
+            let mappings = keymap.wasd_run_mappings.map(|direction| direction[layer]);
+            self.set_wasd_run(layer, run, &mappings)?;
+        }
+        for (layer, color) in keymap.layer_colors.iter().enumerate() {
+            self.set_layer_color(layer, *color)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::device::{Error, Transport};
+use hidapi::{HidApi, HidDevice};
+use padtarust::config_protocol::{REPORT_LEN, USAGE, USAGE_PAGE, USB_PID, USB_VID};
+
+const TIMEOUT_MS: i32 = 1000;
+
+/// The keypad's raw HID interface.
+pub struct HidTransport {
+    device: HidDevice,
+}
+
+impl HidTransport {
+    /// Opens the first attached keypad.
+    pub fn open() -> Result<HidTransport, Error> {
+        let api = HidApi::new().map_err(|e| Error::Transport(e.to_string()))?;
+        let info = api
+            .device_list()
+            .find(|d| {
+                d.vendor_id() == USB_VID
+                    && d.product_id() == USB_PID
+                    && d.usage_page() == USAGE_PAGE
+                    && d.usage() == USAGE
+            })
+            .ok_or(Error::NotFound)?;
+        let device = info
+            .open_device(&api)
+            .map_err(|e| Error::Transport(e.to_string()))?;
+        Ok(HidTransport { device })
+    }
+}
+
+impl Transport for HidTransport {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN], Error> {
+        // the interface has no report IDs, which hidapi wants spelled out as a leading zero
+        let mut report = [0u8; REPORT_LEN + 1];
+        report[1..].copy_from_slice(request);
+        self.device
+            .write(&report)
+            .map_err(|e| Error::Transport(e.to_string()))?;
+        let mut response = [0u8; REPORT_LEN];
+        let len = self
+            .device
+            .read_timeout(&mut response, TIMEOUT_MS)
+            .map_err(|e| Error::Transport(e.to_string()))?;
+        if len == 0 {
+            return Err(Error::Timeout);
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,531 @@
+mod device;
+mod hid;
+mod simulator;
+
+use device::{Device, Error, Transport};
+use padtarust::config_protocol::calibrate_step;
+use padtarust::joystick::CalibrationPhase;
+use padtarust::keymap_common::{InputName, INPUT_COUNT, LAYER_COUNT};
+use padtarust::keymap_text::{self, DisplayCurve, DisplayMapping, DisplayRunMode};
+use padtarust::wasd::Sectors;
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: padtarust [--simulate] <command>
+
+commands:
+    info                     firmware and protocol versions
+    dump [FILE]              write the live keymap as text, to stdout without FILE
+    load FILE                replace the live keymap with a text keymap
+    set LAYER INPUT MAPPING  change one mapping, e.g. `set 1 key4 KC_VOLU`
+    calibrate [SAMPLES]      measure the joystick's resting centre; leave it alone meanwhile
+    calibrate sweep          measure the centre, then the range while you move the joystick
+                             all the way round; saved when done
+    calibrate NAME=VALUE...  set x_center, y_center, x_min, x_max, y_min, y_max or rotation
+    response                 show how the joystick's report is shaped
+    response NAME=VALUE...   set axial_deadzone, radial_deadzone, outer_deadzone or
+                             anti_deadzone (thousandths of full deflection), or curve: linear,
+                             exponential(EXPO) or custom(P0,...,P8)
+    wasd                     show how the joystick picks directions in WASD mode
+    wasd NAME=VALUE...       set sectors (4 or 8), engage or release (thousandths of full
+                             deflection), diagonal_overlap or hysteresis (degrees),
+                             pulse_period or min_pulse (milliseconds, a period of 0 doesn't
+                             pulse)
+    wasd run LAYER           show what a layer sends once the stick is tilted past its run
+                             threshold
+    wasd run LAYER NAME=VALUE...
+                             set threshold (thousandths, 0 never runs), mode (add or replace)
+                             or the up, left, down and right run mappings
+    save                     write the live keymap to flash
+    reset                    go back to the built-in keymap
+    monitor                  show held inputs and the joystick until interrupted
+    log [--follow]           print the firmware log, then keep printing with --follow
+
+Changes take effect at once but are lost on unplugging unless saved.
+INPUT is key0 to key20, joystick, scroll, up, left, down, right, or its number.
+--simulate talks to an in-process keypad instead of real hardware.";
+
+const DEFAULT_CALIBRATION_SAMPLES: u32 = 50;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+enum CliError {
+    Usage(String),
+    Device(Error),
+    Other(String),
+}
+
+impl From<Error> for CliError {
+    fn from(e: Error) -> CliError {
+        CliError::Device(e)
+    }
+}
+
+fn usage(message: &str) -> CliError {
+    CliError::Usage(message.into())
+}
+
+fn parse_input(name: &str) -> Result<usize, CliError> {
+    InputName::parse(name).ok_or_else(|| usage(&format!("unknown input `{}`", name)))
+}
+
+fn parse_layer(text: &str) -> Result<usize, CliError> {
+    let layer: usize = parse_number(text, "layer")?;
+    if layer >= LAYER_COUNT {
+        return Err(usage(&format!("layers go from 0 to {}", LAYER_COUNT - 1)));
+    }
+    Ok(layer)
+}
+
+fn parse_number<N: std::str::FromStr>(text: &str, what: &str) -> Result<N, CliError> {
+    text.parse()
+        .map_err(|_| usage(&format!("`{}` is not a valid {}", text, what)))
+}
+
+fn run<T: Transport>(device: &mut Device<T>, args: &[String]) -> Result<(), CliError> {
+    let Some(command) = args.first() else {
+        return Err(usage("no command given"));
+    };
+    let args = &args[1..];
+    match (command.as_str(), args) {
+        ("info", []) => {
+            let info = device.info()?;
+            println!("firmware version  {}", info.firmware_version);
+            println!("protocol version  {}", info.protocol_version);
+            println!("keymap version    {}", info.keymap_version);
+            println!("layers            {}", info.layers);
+            println!("inputs            {}", info.inputs);
+        }
+        ("dump", [] | [_]) => {
+            let keymap = device.read_keymap()?;
+            let mut text = String::new();
+            keymap_text::print(&keymap, &mut text).unwrap();
+            match args.first() {
+                Some(file) => std::fs::write(file, text)
+                    .map_err(|e| CliError::Other(format!("cannot write {}: {}", file, e)))?,
+                None => print!("{}", text),
+            }
+        }
+        ("load", [file]) => {
+            let text = std::fs::read_to_string(file)
+                .map_err(|e| CliError::Other(format!("cannot read {}: {}", file, e)))?;
+            let keymap = keymap_text::parse(&text)
+                .map_err(|e| CliError::Other(format!("{}:{}", file, e)))?;
+            device.write_keymap(&keymap)?;
+            println!("loaded {}, run `save` to keep it", file);
+        }
+        ("set", [layer, input, mapping @ ..]) if !mapping.is_empty() => {
+            let layer = parse_layer(layer)?;
+            let input = parse_input(input)?;
+            let mapping = keymap_text::parse_mapping(&mapping.join(" "))
+                .map_err(|e| usage(&format!("bad mapping: {}", e.kind)))?;
+            device.set_mapping(layer, input, &mapping)?;
+            println!(
+                "layer {} {} = {}",
+                layer,
+                InputName(input),
+                DisplayMapping(&mapping)
+            );
+        }
+        ("calibrate", assignments) if assignments.iter().any(|a| a.contains('=')) => {
+            let mut calibration = device.calibration()?;
+            for assignment in assignments {
+                let Some((name, value)) = assignment.split_once('=') else {
+                    return Err(usage(&format!("expected NAME=VALUE, got `{}`", assignment)));
+                };
+                let value = parse_number(value, name)?;
+                match name {
+                    "x_center" => calibration.x_center = value,
+                    "y_center" => calibration.y_center = value,
+                    "x_min" => calibration.x_min = value,
+                    "x_max" => calibration.x_max = value,
+                    "y_min" => calibration.y_min = value,
+                    "y_max" => calibration.y_max = value,
+                    "rotation" => calibration.rotation = value,
+                    _ => return Err(usage(&format!("unknown calibration value `{}`", name))),
+                }
+            }
+            device.set_calibration(&calibration)?;
+        }
+        ("calibrate", [sweep]) if sweep == "sweep" => {
+            let mut last = device.calibrate(calibrate_step::START)?;
+            println!("leave the joystick alone");
+            while last != CalibrationPhase::Idle {
+                std::thread::sleep(POLL_INTERVAL);
+                let phase = device.calibrate(calibrate_step::QUERY)?;
+                if phase == CalibrationPhase::Sweeping && last != phase {
+                    println!("now move it all the way round, slowly, until this finishes");
+                }
+                last = phase;
+            }
+            let c = device.calibration()?;
+            println!(
+                "x {}..{}..{}, y {}..{}..{}, saved",
+                c.x_min, c.x_center, c.x_max, c.y_min, c.y_center, c.y_max
+            );
+        }
+        ("calibrate", [] | [_]) => {
+            let samples = match args.first() {
+                Some(samples) => parse_number(samples, "sample count")?,
+                None => DEFAULT_CALIBRATION_SAMPLES,
+            };
+            if samples == 0 {
+                return Err(usage("need at least one sample"));
+            }
+            let (mut x, mut y) = (0u32, 0u32);
+            for _ in 0..samples {
+                let state = device.state()?;
+                x += state.joy_x as u32;
+                y += state.joy_y as u32;
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            let mut calibration = device.calibration()?;
+            calibration.x_center = (x / samples) as u16;
+            calibration.y_center = (y / samples) as u16;
+            device.set_calibration(&calibration)?;
+            println!(
+                "joystick centre is now {}, {}; run `save` to keep it",
+                calibration.x_center, calibration.y_center
+            );
+        }
+        ("response", []) => {
+            let stick = device.response()?;
+            println!("axial_deadzone   {}", stick.axial_deadzone);
+            println!("radial_deadzone  {}", stick.radial_deadzone);
+            println!("outer_deadzone   {}", stick.outer_deadzone);
+            println!("anti_deadzone    {}", stick.anti_deadzone);
+            println!("curve            {}", DisplayCurve(&stick.curve));
+        }
+        ("response", assignments) => {
+            let mut stick = device.response()?;
+            for assignment in assignments {
+                let Some((name, value)) = assignment.split_once('=') else {
+                    return Err(usage(&format!("expected NAME=VALUE, got `{}`", assignment)));
+                };
+                match name {
+                    "axial_deadzone" => stick.axial_deadzone = parse_number(value, name)?,
+                    "radial_deadzone" => stick.radial_deadzone = parse_number(value, name)?,
+                    "outer_deadzone" => stick.outer_deadzone = parse_number(value, name)?,
+                    "anti_deadzone" => stick.anti_deadzone = parse_number(value, name)?,
+                    "curve" => {
+                        stick.curve = keymap_text::parse_curve(value)
+                            .map_err(|e| usage(&format!("bad curve: {}", e.kind)))?
+                    }
+                    _ => return Err(usage(&format!("unknown response value `{}`", name))),
+                }
+            }
+            if !stick.is_valid() {
+                return Err(usage("sizes and curve points go up to 1000"));
+            }
+            device.set_response(&stick)?;
+        }
+        ("wasd", [run, layer]) if run == "run" => {
+            let (run, mappings) = device.wasd_run(parse_layer(layer)?)?;
+            println!("threshold  {}", run.threshold);
+            println!("mode       {}", DisplayRunMode(run.mode));
+            for (direction, mapping) in mappings.iter().enumerate() {
+                let name = InputName(23 + direction).to_string();
+                println!("{:10} {}", name, DisplayMapping(mapping));
+            }
+        }
+        ("wasd", [run, layer, assignments @ ..]) if run == "run" => {
+            let layer = parse_layer(layer)?;
+            let (mut run, mut mappings) = device.wasd_run(layer)?;
+            for assignment in assignments {
+                let Some((name, value)) = assignment.split_once('=') else {
+                    return Err(usage(&format!("expected NAME=VALUE, got `{}`", assignment)));
+                };
+                match (name, InputName::parse(name)) {
+                    ("threshold", _) => run.threshold = parse_number(value, name)?,
+                    ("mode", _) => {
+                        run.mode = keymap_text::parse_run_mode(value)
+                            .map_err(|e| usage(&format!("bad mode: {}", e.kind)))?
+                    }
+                    (_, Some(input @ 23..=26)) => {
+                        mappings[input - 23] = keymap_text::parse_mapping(value)
+                            .map_err(|e| usage(&format!("bad mapping: {}", e.kind)))?
+                    }
+                    _ => return Err(usage(&format!("unknown run value `{}`", name))),
+                }
+            }
+            if !run.is_valid() {
+                return Err(usage("threshold goes up to 1000"));
+            }
+            device.set_wasd_run(layer, &run, &mappings)?;
+        }
+        ("wasd", []) => {
+            let wasd = device.wasd()?;
+            println!("sectors          {}", wasd.sectors.count());
+            println!("engage           {}", wasd.engage);
+            println!("release          {}", wasd.release);
+            println!("diagonal_overlap {}", wasd.diagonal_overlap);
+            println!("hysteresis       {}", wasd.hysteresis);
+            println!("pulse_period     {}", wasd.pulse_period_ms);
+            println!("min_pulse        {}", wasd.min_pulse_ms);
+        }
+        ("wasd", assignments) => {
+            let mut wasd = device.wasd()?;
+            for assignment in assignments {
+                let Some((name, value)) = assignment.split_once('=') else {
+                    return Err(usage(&format!("expected NAME=VALUE, got `{}`", assignment)));
+                };
+                match name {
+                    "sectors" => {
+                        wasd.sectors = Sectors::from_count(parse_number(value, name)?)
+                            .ok_or_else(|| usage("sectors is 4 or 8"))?
+                    }
+                    "engage" => wasd.engage = parse_number(value, name)?,
+                    "release" => wasd.release = parse_number(value, name)?,
+                    "diagonal_overlap" => wasd.diagonal_overlap = parse_number(value, name)?,
+                    "hysteresis" => wasd.hysteresis = parse_number(value, name)?,
+                    "pulse_period" => wasd.pulse_period_ms = parse_number(value, name)?,
+                    "min_pulse" => wasd.min_pulse_ms = parse_number(value, name)?,
+                    _ => return Err(usage(&format!("unknown wasd value `{}`", name))),
+                }
+            }
+            if !wasd.is_valid() {
+                return Err(usage(
+                    "engage goes up to 1000 and release up to engage, diagonal_overlap up to 45 \
+                     and hysteresis below 90 degrees",
+                ));
+            }
+            device.set_wasd(&wasd)?;
+        }
+        ("save", []) => device.save()?,
+        ("reset", []) => {
+            device.reload_defaults()?;
+            println!("back to the built-in keymap, run `save` to keep it");
+        }
+        ("monitor", []) => {
+            let mut last = None;
+            loop {
+                let state = device.state()?;
+                if last != Some(state) {
+                    let held: Vec<String> = (0..INPUT_COUNT)
+                        .filter(|i| state.pressed & (1 << i) != 0)
+                        .map(|i| InputName(i).to_string())
+                        .collect();
+                    println!(
+                        "layer {}  wasd {:3}  x {:4}  y {:4}  {}",
+                        state.layer,
+                        if state.wasd_mode { "on" } else { "off" },
+                        state.joy_x,
+                        state.joy_y,
+                        held.join(" ")
+                    );
+                    last = Some(state);
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        ("log", [] | [_]) => {
+            let follow = match args.first().map(String::as_str) {
+                None => false,
+                Some("-f" | "--follow") => true,
+                Some(flag) => return Err(usage(&format!("unknown flag `{}`", flag))),
+            };
+            let mut position = 0;
+            let mut stdout = std::io::stdout();
+            loop {
+                let (start, text) = device.log(position)?;
+                if start != position && position != 0 {
+                    println!("[{} bytes of log lost]", start.wrapping_sub(position));
+                }
+                position = start.wrapping_add(text.len() as u32);
+                stdout
+                    .write_all(&text)
+                    .and_then(|()| stdout.flush())
+                    .map_err(|e| CliError::Other(e.to_string()))?;
+                if text.is_empty() {
+                    if !follow {
+                        break;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+        _ => return Err(usage(&format!("bad arguments to `{}`", command))),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let simulate = args.first().map(String::as_str) == Some("--simulate");
+    if simulate {
+        args.remove(0);
+    }
+    let result = if simulate {
+        Device::new(simulator::SimulatedDevice::new())
+            .map_err(CliError::from)
+            .and_then(|mut device| run(&mut device, &args))
+    } else {
+        hid::HidTransport::open()
+            .and_then(Device::new)
+            .map_err(CliError::from)
+            .and_then(|mut device| run(&mut device, &args))
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            ExitCode::FAILURE
+        }
+        Err(CliError::Device(e)) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+        Err(CliError::Other(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use padtarust::config_protocol::{status, PROTOCOL_VERSION, REPORT_LEN};
+    use padtarust::joystick::ResponseCurve;
+    use padtarust::keymap_common::{Consumer, Keyboard, KeyboardAction, Keymap};
+    use padtarust::wasd::RunMode;
+    use simulator::SimulatedDevice;
+
+    fn simulated() -> Device<SimulatedDevice> {
+        Device::new(SimulatedDevice::new()).unwrap()
+    }
+
+    fn run_line<T: Transport>(device: &mut Device<T>, line: &str) -> Result<(), CliError> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        run(device, &args)
+    }
+
+    fn encoded(keymap: &Keymap) -> Vec<u8> {
+        let mut buf = vec![0; Keymap::ENCODED_LEN];
+        keymap.encode(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn set_changes_one_mapping() {
+        let mut device = simulated();
+        assert!(run_line(&mut device, "set 1 key4 KC_VOLU").is_ok());
+        let mapping = device.mapping(1, 4).unwrap();
+        assert!(mapping.consumer_button == Consumer::VolumeIncrement);
+
+        // a mapping may span several arguments
+        assert!(run_line(&mut device, "set 0 up Action::Layer1Momentary + W").is_ok());
+        let mapping = device.mapping(0, 23).unwrap();
+        assert!(mapping.action == KeyboardAction::Layer1Momentary);
+        assert!(mapping.button == Keyboard::W);
+    }
+
+    #[test]
+    fn dump_and_load_round_trip() {
+        let mut device = simulated();
+        for line in [
+            "set 2 key0 KC_MUTE",
+            "calibrate x_center=480 rotation=20",
+            "response curve=exponential(300) axial_deadzone=50",
+            "wasd sectors=4 engage=500 pulse_period=100",
+            "wasd run 1 threshold=800 mode=replace left=Q",
+        ] {
+            assert!(run_line(&mut device, line).is_ok(), "{}", line);
+        }
+        let edited = device.read_keymap().unwrap();
+
+        let file = std::env::temp_dir().join(format!("padtarust-cli-{}.txt", std::process::id()));
+        let file = file.to_str().unwrap();
+        assert!(run_line(&mut device, &format!("dump {}", file)).is_ok());
+        assert!(run_line(&mut device, "reset").is_ok());
+        assert_ne!(encoded(&device.read_keymap().unwrap()), encoded(&edited));
+        let loaded = run_line(&mut device, &format!("load {}", file));
+        std::fs::remove_file(file).unwrap();
+        assert!(loaded.is_ok());
+        assert_eq!(encoded(&device.read_keymap().unwrap()), encoded(&edited));
+    }
+
+    #[test]
+    fn settings_read_back() {
+        let mut device = simulated();
+        assert!(run_line(
+            &mut device,
+            "response curve=exponential(300) outer_deadzone=20"
+        )
+        .is_ok());
+        let stick = device.response().unwrap();
+        assert_eq!(stick.curve, ResponseCurve::Exponential(300));
+        assert_eq!(stick.outer_deadzone, 20);
+
+        assert!(run_line(&mut device, "wasd run 2 threshold=850 mode=replace up=R").is_ok());
+        let (run, mappings) = device.wasd_run(2).unwrap();
+        assert_eq!(run.threshold, 850);
+        assert_eq!(run.mode, RunMode::Replace);
+        assert!(mappings[0].button == Keyboard::R);
+    }
+
+    #[test]
+    fn bad_arguments_are_usage_errors() {
+        let mut device = simulated();
+        for line in [
+            "",
+            "frobnicate",
+            "info extra",
+            "set 9 key0 A",
+            "set 0 key99 A",
+            "set 0 key0 Bogus",
+            "set 0 key0",
+            "calibrate x_center=lots",
+            "calibrate x_size=3",
+            "calibrate 0",
+            "response axial_deadzone=2000",
+            "response curve=wobbly",
+            "wasd sectors=6",
+            "wasd release=900",
+            "wasd run 4",
+            "wasd run 0 sideways=W",
+            "log --bogus",
+        ] {
+            assert!(
+                matches!(run_line(&mut device, line), Err(CliError::Usage(_))),
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn pad_rejections_come_back_as_errors() {
+        let mut device = simulated();
+        let before = device.read_keymap().unwrap();
+        let result = run_line(&mut device, "calibrate x_min=900");
+        assert!(matches!(
+            result,
+            Err(CliError::Device(Error::Status {
+                status: status::INVALID_ARGUMENT,
+                ..
+            }))
+        ));
+        assert_eq!(encoded(&device.read_keymap().unwrap()), encoded(&before));
+    }
+
+    /// The simulator, but claiming another protocol version
+    struct OtherVersion(SimulatedDevice);
+
+    impl Transport for OtherVersion {
+        fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN], Error> {
+            let mut response = self.0.exchange(request)?;
+            response[2] = PROTOCOL_VERSION + 1;
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn other_protocol_versions_are_refused() {
+        let result = Device::new(OtherVersion(SimulatedDevice::new()));
+        assert!(
+            matches!(result, Err(Error::Incompatible { protocol }) if protocol == PROTOCOL_VERSION + 1)
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::device::{Error, Transport};
+use padtarust::config_protocol::{self, LiveState, REPORT_LEN};
+use padtarust::joystick::Calibrator;
+use padtarust::keymap_common::Keymap;
+use padtarust::log_ring::LogRing;
+use padtarust::storage::{KeymapStore, RamFlash};
+use padtarust::via::ViaState;
+use std::time::Instant;
+
+/// A keypad in-process: the firmware's own request handling over a keymap and flash in RAM.
+pub struct SimulatedDevice {
+    pub keymap: Keymap,
+    pub store: KeymapStore<RamFlash<4096, 2>>,
+    pub via: ViaState,
+    /// What GET_STATE reports
+    pub live: LiveState,
+    pub log: LogRing<4096>,
+    pub calibrator: Calibrator,
+    started: Instant,
+}
+
+impl SimulatedDevice {
+    /// A freshly flashed keypad, with nothing saved yet.
+    pub fn new() -> SimulatedDevice {
+        let mut store = KeymapStore::new(RamFlash::new());
+        let keymap = store.load().unwrap_or(Keymap::default());
+        let mut log = LogRing::new();
+        log.record(0, log::Level::Info, &format_args!("simulated keypad starting"));
+        SimulatedDevice {
+            keymap,
+            store,
+            via: ViaState::new(),
+            live: LiveState::default(),
+            log,
+            calibrator: Calibrator::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Transport for SimulatedDevice {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN], Error> {
+        // the joystick is scanned once per request, as far as calibration can tell
+        let now = self.started.elapsed().as_micros() as u64;
+        let current = (
+            self.keymap.joy_x_calibration(),
+            self.keymap.joy_y_calibration(),
+        );
+        if let Some((x, y)) = self
+            .calibrator
+            .sample(self.live.joy_x, self.live.joy_y, now, current)
+        {
+            self.keymap.set_joy_calibration(x, y);
+            let _ = self.store.save(&self.keymap);
+        }
+        Ok(config_protocol::handle(
+            request,
+            &mut self.keymap,
+            &mut self.store,
+            &mut self.via,
+            &self.live,
+            &mut self.calibrator,
+            &self.log,
+        ))
+    }
+}
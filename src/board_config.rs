@@ -0,0 +1,92 @@
+// Board configuration: everything that differs between PCB revisions lives here.
+use crate::idle::IdleConfig;
+use crate::led_map::{one_led, ColorOrder, LedMap};
+
+macro_rules! declare_led_pin {
+    ($pin:tt,1) => {
+        ::paste::paste! {
+            pub type LedPin = teensy4_pins::t41::[<P $pin>];
+            macro_rules! get_led_gpio_output {
+                ($gpio1:ident, $gpio2:ident, $gpio3:ident, $gpio4:ident, $pins:ident) => {
+                    $gpio1.output($pins.[<p $pin>])
+                }
+            }
+        }
+    };
+    ($pin:tt,2) => {
+        ::paste::paste! {
+            pub type LedPin = teensy4_pins::t41::[<P $pin>];
+            macro_rules! get_led_gpio_output {
+                ($gpio1:ident, $gpio2:ident, $gpio3:ident, $gpio4:ident, $pins:ident) => {
+                    $gpio2.output($pins.[<p $pin>])
+                }
+            }
+        }
+    };
+    ($pin:tt,3) => {
+        ::paste::paste! {
+            pub type LedPin = teensy4_pins::t41::[<P $pin>];
+            macro_rules! get_led_gpio_output {
+                ($gpio1:ident, $gpio2:ident, $gpio3:ident, $gpio4:ident, $pins:ident) => {
+                    $gpio3.output($pins.[<p $pin>])
+                }
+            }
+        }
+    };
+    ($pin:tt,4) => {
+        ::paste::paste! {
+            pub type LedPin = teensy4_pins::t41::[<P $pin>];
+            macro_rules! get_led_gpio_output {
+                ($gpio1:ident, $gpio2:ident, $gpio3:ident, $gpio4:ident, $pins:ident) => {
+                    $gpio4.output($pins.[<p $pin>])
+                }
+            }
+        }
+    };
+}
+
+// WS2812 data line: pin 41 on GPIO1
+declare_led_pin!(41, 1);
+
+pub const LED_COUNT: usize = 24;
+pub const LED_COLOR_ORDER: ColorOrder = ColorOrder::Grb;
+
+// one LED under each key, then the joystick and the wheel; LED 23 is not attached to an input
+pub const LED_MAP: LedMap = LedMap {
+    keys: [
+        one_led(0),
+        one_led(1),
+        one_led(2),
+        one_led(3),
+        one_led(4),
+        one_led(5),
+        one_led(6),
+        one_led(7),
+        one_led(8),
+        one_led(9),
+        one_led(10),
+        one_led(11),
+        one_led(12),
+        one_led(13),
+        one_led(14),
+        one_led(15),
+        one_led(16),
+        one_led(17),
+        one_led(18),
+        one_led(19),
+        one_led(20),
+    ],
+    joystick: one_led(21),
+    wheel: one_led(22),
+};
+
+const _: () = assert!(LED_MAP.fits(LED_COUNT), "LED_MAP refers to an LED past LED_COUNT");
+
+// dim the LEDs after a minute without input and turn them off after ten
+pub const IDLE_CONFIG: IdleConfig = IdleConfig {
+    dim_after_us: 60_000_000,
+    off_after_us: 600_000_000,
+    dim_brightness: 64,
+    dim_scan_interval_us: 20_000,
+    off_scan_interval_us: 50_000,
+};
@@ -0,0 +1,52 @@
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use teensy4_bsp::board;
+use teensy4_bsp::hal::gpt::{ClockSource, Gpt1, Mode};
+
+// Monotonic time since boot from GPT1 counting microseconds. The counter is 32 bits and wraps
+// every 71 minutes; each read carries the wraps into the upper half, so something must read the
+// clock at least that often (the main loop does).
+const GPT_DIVIDER: u32 = board::PERCLK_FREQUENCY / 1_000_000;
+
+struct Clock {
+    gpt: Gpt1,
+    last: u32,
+    wraps: u32,
+}
+
+static CLOCK: Mutex<RefCell<Option<Clock>>> = Mutex::new(RefCell::new(None));
+
+pub fn init(mut gpt: Gpt1) {
+    gpt.disable();
+    gpt.set_clock_source(ClockSource::PeripheralClock);
+    gpt.set_divider(GPT_DIVIDER);
+    gpt.set_mode(Mode::FreeRunning);
+    gpt.enable();
+    interrupt::free(|cs| {
+        CLOCK.borrow(cs).replace(Some(Clock {
+            gpt,
+            last: 0,
+            wraps: 0,
+        }))
+    });
+}
+
+/// Microseconds since `init`, 0 before it or when called from a panic inside this function.
+pub fn micros() -> u64 {
+    interrupt::free(|cs| {
+        let Ok(mut clock) = CLOCK.borrow(cs).try_borrow_mut() else {
+            return 0;
+        };
+        match clock.as_mut() {
+            Some(clock) => {
+                let count = clock.gpt.count();
+                if count < clock.last {
+                    clock.wraps += 1;
+                }
+                clock.last = count;
+                (clock.wraps as u64) << 32 | count as u64
+            }
+            None => 0,
+        }
+    })
+}
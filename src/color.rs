@@ -0,0 +1,226 @@
+// Integer-only colour math for the LED subsystem. Hue is a full circle in 0-255 so that
+// hue arithmetic wraps naturally in a u8.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Hsv {
+    pub h: u8,
+    pub s: u8,
+    pub v: u8,
+}
+
+/// Scales `value` by `scale / 256`, with 255 treated as "no scaling".
+pub const fn scale8(value: u8, scale: u8) -> u8 {
+    ((value as u16 * (scale as u16 + 1)) >> 8) as u8
+}
+
+/// Linear interpolation from `a` (`t == 0`) to `b` (`t == 255`).
+pub const fn lerp8(a: u8, b: u8, t: u8) -> u8 {
+    if b >= a {
+        a + scale8(b - a, t)
+    } else {
+        a - scale8(a - b, t)
+    }
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    pub const fn to_array(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub const fn from_array(color: [u8; 3]) -> Rgb {
+        Rgb::new(color[0], color[1], color[2])
+    }
+
+    pub const fn scale(self, brightness: u8) -> Rgb {
+        Rgb::new(
+            scale8(self.r, brightness),
+            scale8(self.g, brightness),
+            scale8(self.b, brightness),
+        )
+    }
+
+    /// Mixes `amount / 255` of `other` into this colour.
+    pub const fn blend(self, other: Rgb, amount: u8) -> Rgb {
+        Rgb::new(
+            lerp8(self.r, other.r, amount),
+            lerp8(self.g, other.g, amount),
+            lerp8(self.b, other.b, amount),
+        )
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = (max - min) as i32;
+        if max == 0 || delta == 0 {
+            return Hsv::new(0, 0, max);
+        }
+        let s = (255 * delta / max as i32) as u8;
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        let h = if max == self.r {
+            43 * (g - b) / delta
+        } else if max == self.g {
+            85 + 43 * (b - r) / delta
+        } else {
+            171 + 43 * (r - g) / delta
+        };
+        Hsv::new(h.rem_euclid(256) as u8, s, max)
+    }
+}
+
+impl Hsv {
+    pub const fn new(h: u8, s: u8, v: u8) -> Hsv {
+        Hsv { h, s, v }
+    }
+
+    pub const fn to_rgb(self) -> Rgb {
+        if self.s == 0 {
+            return Rgb::new(self.v, self.v, self.v);
+        }
+        // six regions of 43 hue steps each, remainder scaled back up to 0-255
+        let region = self.h / 43;
+        let remainder = ((self.h - region * 43) as u16 * 6) as u8;
+        let v = self.v;
+        let p = scale8(v, 255 - self.s);
+        let q = scale8(v, 255 - scale8(self.s, remainder));
+        let t = scale8(v, 255 - scale8(self.s, 255 - remainder));
+        match region {
+            0 => Rgb::new(v, t, p),
+            1 => Rgb::new(q, v, p),
+            2 => Rgb::new(p, v, t),
+            3 => Rgb::new(p, q, v),
+            4 => Rgb::new(t, p, v),
+            _ => Rgb::new(v, p, q),
+        }
+    }
+
+    pub const fn rotate_hue(self, amount: i16) -> Hsv {
+        Hsv::new(
+            (self.h as i16 + amount).rem_euclid(256) as u8,
+            self.s,
+            self.v,
+        )
+    }
+
+    pub const fn scale(self, brightness: u8) -> Hsv {
+        Hsv::new(self.h, self.s, scale8(self.v, brightness))
+    }
+
+    /// Interpolates between two colours, taking the shorter way around the hue circle.
+    pub const fn lerp(self, other: Hsv, t: u8) -> Hsv {
+        let mut delta = other.h as i16 - self.h as i16;
+        if delta > 128 {
+            delta -= 256;
+        } else if delta < -128 {
+            delta += 256;
+        }
+        let h = (self.h as i16 + (delta * t as i16) / 255).rem_euclid(256) as u8;
+        Hsv::new(h, lerp8(self.s, other.s, t), lerp8(self.v, other.v, t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale8_bounds() {
+        assert_eq!(scale8(0, 255), 0);
+        assert_eq!(scale8(255, 0), 0);
+        assert_eq!(scale8(255, 255), 255);
+        assert_eq!(scale8(200, 255), 200);
+        assert_eq!(scale8(255, 128), 128);
+    }
+
+    #[test]
+    fn lerp8_ends() {
+        assert_eq!(lerp8(10, 200, 0), 10);
+        assert_eq!(lerp8(10, 200, 255), 200);
+        assert_eq!(lerp8(200, 10, 255), 10);
+        assert_eq!(lerp8(0, 255, 128), 128);
+        assert_eq!(lerp8(255, 0, 128), 127);
+    }
+
+    #[test]
+    fn blend_ends() {
+        let black = Rgb::new(0, 0, 0);
+        let white = Rgb::new(255, 255, 255);
+        assert_eq!(black.blend(white, 0), black);
+        assert_eq!(black.blend(white, 255), white);
+        assert_eq!(white.blend(black, 255), black);
+        let mixed = Rgb::new(255, 0, 100).blend(Rgb::new(0, 255, 100), 128);
+        assert_eq!(mixed, Rgb::new(127, 128, 100));
+    }
+
+    #[test]
+    fn to_rgb_unsaturated_is_grey() {
+        assert_eq!(Hsv::new(123, 0, 77).to_rgb(), Rgb::new(77, 77, 77));
+        assert_eq!(Hsv::new(0, 255, 0).to_rgb(), Rgb::new(0, 0, 0));
+    }
+
+    #[test]
+    fn to_rgb_sector_edges() {
+        // each region starts on one primary or secondary and climbs towards the next
+        assert_eq!(Hsv::new(0, 255, 255).to_rgb(), Rgb::new(255, 0, 0));
+        assert_eq!(Hsv::new(42, 255, 255).to_rgb(), Rgb::new(255, 252, 0));
+        assert_eq!(Hsv::new(43, 255, 255).to_rgb(), Rgb::new(255, 255, 0));
+        assert_eq!(Hsv::new(86, 255, 255).to_rgb(), Rgb::new(0, 255, 0));
+        assert_eq!(Hsv::new(129, 255, 255).to_rgb(), Rgb::new(0, 255, 255));
+        assert_eq!(Hsv::new(172, 255, 255).to_rgb(), Rgb::new(0, 0, 255));
+        assert_eq!(Hsv::new(215, 255, 255).to_rgb(), Rgb::new(255, 0, 255));
+        // the last region is two steps longer and comes back round to red
+        assert_eq!(Hsv::new(255, 255, 255).to_rgb(), Rgb::new(255, 0, 15));
+    }
+
+    #[test]
+    fn to_hsv_primaries() {
+        assert_eq!(Rgb::new(255, 0, 0).to_hsv(), Hsv::new(0, 255, 255));
+        assert_eq!(Rgb::new(0, 255, 0).to_hsv(), Hsv::new(85, 255, 255));
+        assert_eq!(Rgb::new(0, 0, 255).to_hsv(), Hsv::new(171, 255, 255));
+        // red is the largest with blue above green, so the hue wraps below 0
+        assert_eq!(Rgb::new(255, 0, 255).to_hsv(), Hsv::new(213, 255, 255));
+        assert_eq!(Rgb::new(0, 0, 0).to_hsv(), Hsv::new(0, 0, 0));
+        assert_eq!(Rgb::new(90, 90, 90).to_hsv(), Hsv::new(0, 0, 90));
+    }
+
+    #[test]
+    fn hsv_round_trip_is_close() {
+        // 43 hue steps per region, so a round trip can land a few steps off
+        for h in 0..=255 {
+            let rgb = Hsv::new(h, 255, 255).to_rgb();
+            let back = rgb.to_hsv().to_rgb();
+            for (a, b) in rgb.to_array().iter().zip(back.to_array().iter()) {
+                assert!(a.abs_diff(*b) <= 9, "hue {} became {:?}", h, back);
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_hue_wraps() {
+        assert_eq!(Hsv::new(250, 1, 2).rotate_hue(10), Hsv::new(4, 1, 2));
+        assert_eq!(Hsv::new(5, 1, 2).rotate_hue(-10), Hsv::new(251, 1, 2));
+        assert_eq!(Hsv::new(77, 1, 2).rotate_hue(256), Hsv::new(77, 1, 2));
+        assert_eq!(Hsv::new(0, 1, 2).rotate_hue(-256), Hsv::new(0, 1, 2));
+    }
+
+    #[test]
+    fn hsv_lerp_takes_the_short_way() {
+        let a = Hsv::new(250, 0, 0);
+        let b = Hsv::new(10, 255, 255);
+        assert_eq!(a.lerp(b, 0), a);
+        assert_eq!(a.lerp(b, 255), b);
+        assert_eq!(a.lerp(b, 128).h, 2);
+        assert_eq!(b.lerp(a, 128).h, 2);
+    }
+}
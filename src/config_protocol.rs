@@ -0,0 +1,439 @@
+use crate::color::Hsv;
+use crate::joystick::{
+    AxisCalibration, CalibrationPhase, Calibrator, ResponseCurve, StickResponse, CURVE_POINTS,
+};
+use crate::keymap_common::{
+    KeyboardAction, Keymap, Mapping, INPUT_COUNT, KEYMAP_VERSION, LAYER_COUNT,
+};
+use crate::log_ring::LogRead;
+use crate::storage::{Flash, KeymapStore};
+use crate::via::{self, ViaState};
+use crate::wasd::{RunMode, Sectors, WasdConfig, WasdRun};
+
+// Command protocol spoken over the raw HID interface. The interface is shared with VIA, so
+// native commands start at 0x80 where VIA has none; everything else is handed to `via::handle`.
+// Every OUT report is one request and is answered by exactly one IN report:
+//
+//   request:  [command, arguments...]
+//   response: [command, status, data...]
+//
+// Reports are always REPORT_LEN bytes, unused bytes are zero. Multi-byte values are little endian.
+// Inputs are numbered as described at keymap_common::INPUT_COUNT.
+pub const REPORT_LEN: usize = 32;
+pub const USB_VID: u16 = 0x1209;
+pub const USB_PID: u16 = 0x0001;
+/// The raw HID interface is found by its usage page and usage, the ones VIA looks for
+pub const USAGE_PAGE: u16 = 0xFF60;
+pub const USAGE: u16 = 0x61;
+pub const PROTOCOL_VERSION: u8 = 9;
+
+pub mod command {
+    /// -> protocol version, keymap version, layer count, input count, firmware version (ASCII)
+    pub const GET_INFO: u8 = 0x80;
+    /// layer, input -> action, button, consumer_button (u16)
+    pub const GET_MAPPING: u8 = 0x81;
+    /// layer, input, action, button, consumer_button (u16) ->
+    pub const SET_MAPPING: u8 = 0x82;
+    /// -> x center, y center, rotation, x min, x max, y min, y max (all u16)
+    pub const GET_CALIBRATION: u8 = 0x83;
+    /// x center, y center, rotation, x min, x max, y min, y max (all u16) ->
+    /// Each axis needs min < center < max
+    pub const SET_CALIBRATION: u8 = 0x84;
+    /// Writes the live keymap to flash
+    pub const SAVE: u8 = 0x85;
+    /// Replaces the live keymap with `Keymap::default()`, flash is untouched until SAVE
+    pub const RELOAD_DEFAULTS: u8 = 0x86;
+    /// layer -> hue, saturation, value
+    pub const GET_LAYER_COLOR: u8 = 0x87;
+    /// layer, hue, saturation, value ->
+    pub const SET_LAYER_COLOR: u8 = 0x88;
+    /// -> current layer, WASD mode, held inputs (u32, bit n is input n), raw joystick x, y (u16)
+    pub const GET_STATE: u8 = 0x89;
+    /// log position (u32) -> position of the first byte returned (u32), byte count, log text.
+    /// The first position can be later than the one asked for if that text was overwritten; ask
+    /// again from first position + byte count to continue. Position 0 reads the oldest text held.
+    pub const GET_LOG: u8 = 0x8A;
+    /// step -> calibration phase (0 idle, 1 centring, 2 sweeping). Step 0 starts joystick
+    /// calibration, 1 finishes the sweep, anything else only asks. The result is applied to the
+    /// live keymap and saved when the sweep ends; read it back with GET_CALIBRATION.
+    pub const CALIBRATE: u8 = 0x8B;
+    /// -> axial, radial, outer and anti deadzones (u16, thousandths), curve kind, expo (u16),
+    /// curve points (9 u16); see joystick::ResponseCurve::to_raw
+    pub const GET_RESPONSE: u8 = 0x8C;
+    /// axial, radial, outer and anti deadzones (u16, thousandths), curve kind, expo (u16),
+    /// curve points (9 u16) ->
+    pub const SET_RESPONSE: u8 = 0x8D;
+    /// -> sectors (4 or 8), engage, release (u16, thousandths), diagonal overlap, hysteresis
+    /// (u16, degrees), pulse period, min pulse (u16, milliseconds); see wasd::WasdConfig
+    pub const GET_WASD: u8 = 0x8E;
+    /// sectors (4 or 8), engage, release (u16, thousandths), diagonal overlap, hysteresis
+    /// (u16, degrees), pulse period, min pulse (u16, milliseconds) ->
+    pub const SET_WASD: u8 = 0x8F;
+    /// layer -> run threshold (u16, thousandths), run mode (0 add, 1 replace), then the up, left,
+    /// down and right run mappings as action, button, consumer_button (u16)
+    pub const GET_WASD_RUN: u8 = 0x90;
+    /// layer, run threshold (u16, thousandths), run mode, then the up, left, down and right run
+    /// mappings as action, button, consumer_button (u16) ->
+    pub const SET_WASD_RUN: u8 = 0x91;
+}
+
+pub mod calibrate_step {
+    pub const START: u8 = 0;
+    pub const FINISH: u8 = 1;
+    pub const QUERY: u8 = 2;
+}
+
+pub mod status {
+    pub const OK: u8 = 0x00;
+    pub const UNKNOWN_COMMAND: u8 = 0x01;
+    pub const INVALID_ARGUMENT: u8 = 0x02;
+    pub const STORAGE_ERROR: u8 = 0x03;
+}
+
+/// What the pad is doing right now, as last seen by the scan loop. Answers GET_STATE.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct LiveState {
+    pub layer: u8,
+    pub wasd_mode: bool,
+    /// Bit n is set while input n is held
+    pub pressed: u32,
+    pub joy_x: u16,
+    pub joy_y: u16,
+}
+
+struct Response {
+    report: [u8; REPORT_LEN],
+    len: usize,
+}
+
+impl Response {
+    fn new(command: u8) -> Response {
+        let mut report = [0u8; REPORT_LEN];
+        report[0] = command;
+        Response { report, len: 2 }
+    }
+    fn status(mut self, status: u8) -> [u8; REPORT_LEN] {
+        self.report[1] = status;
+        self.report
+    }
+    fn u8(&mut self, value: u8) {
+        self.report[self.len] = value;
+        self.len += 1;
+    }
+    fn u16(&mut self, value: u16) {
+        self.report[self.len..self.len + 2].copy_from_slice(&value.to_le_bytes());
+        self.len += 2;
+    }
+    fn u32(&mut self, value: u32) {
+        self.report[self.len..self.len + 4].copy_from_slice(&value.to_le_bytes());
+        self.len += 4;
+    }
+    fn bytes(&mut self, value: &[u8]) {
+        let len = value.len().min(REPORT_LEN - self.len);
+        self.report[self.len..self.len + len].copy_from_slice(&value[..len]);
+        self.len += len;
+    }
+}
+
+fn arg_u16(request: &[u8; REPORT_LEN], index: usize) -> u16 {
+    u16::from_le_bytes([request[index], request[index + 1]])
+}
+
+/// Answers a single request, applying any changes directly to the live keymap.
+pub fn handle<F: Flash, L: LogRead>(
+    request: &[u8; REPORT_LEN],
+    keymap: &mut Keymap,
+    store: &mut KeymapStore<F>,
+    via: &mut ViaState,
+    live: &LiveState,
+    calibrator: &mut Calibrator,
+    log: &L,
+) -> [u8; REPORT_LEN] {
+    let mut response = Response::new(request[0]);
+    match request[0] {
+        command::GET_INFO => {
+            response.u8(PROTOCOL_VERSION);
+            response.u8(KEYMAP_VERSION);
+            response.u8(LAYER_COUNT as u8);
+            response.u8(INPUT_COUNT as u8);
+            response.bytes(env!("CARGO_PKG_VERSION").as_bytes());
+            response.status(status::OK)
+        }
+        command::GET_MAPPING => {
+            let (layer, input) = (request[1] as usize, request[2] as usize);
+            match keymap.input_mappings(input) {
+                Some(mappings) if layer < LAYER_COUNT => {
+                    let (action, button, consumer_button) = mappings[layer].to_raw();
+                    response.u8(action);
+                    response.u8(button);
+                    response.u16(consumer_button);
+                    response.status(status::OK)
+                }
+                _ => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        command::SET_MAPPING => {
+            let (layer, input) = (request[1] as usize, request[2] as usize);
+            let mapping = Mapping::from_raw(request[3], request[4], arg_u16(request, 5));
+            match (keymap.input_mappings_mut(input), mapping) {
+                (Some(mappings), Ok(mapping)) if layer < LAYER_COUNT => {
+                    mappings[layer] = mapping;
+                    response.status(status::OK)
+                }
+                _ => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        command::GET_CALIBRATION => {
+            response.u16(keymap.joy_x_center);
+            response.u16(keymap.joy_y_center);
+            response.u16(keymap.joy_x_y_rotation);
+            response.u16(keymap.joy_x_min);
+            response.u16(keymap.joy_x_max);
+            response.u16(keymap.joy_y_min);
+            response.u16(keymap.joy_y_max);
+            response.status(status::OK)
+        }
+        command::SET_CALIBRATION => {
+            let x = AxisCalibration {
+                min: arg_u16(request, 7),
+                center: arg_u16(request, 1),
+                max: arg_u16(request, 9),
+            };
+            let y = AxisCalibration {
+                min: arg_u16(request, 11),
+                center: arg_u16(request, 3),
+                max: arg_u16(request, 13),
+            };
+            if x.is_valid() && y.is_valid() {
+                keymap.set_joy_calibration(x, y);
+                keymap.joy_x_y_rotation = arg_u16(request, 5);
+                response.status(status::OK)
+            } else {
+                response.status(status::INVALID_ARGUMENT)
+            }
+        }
+        command::SAVE => match store.save(keymap) {
+            Ok(()) => response.status(status::OK),
+            Err(e) => {
+                log::error!("failed to save keymap: {:?}", e);
+                response.status(status::STORAGE_ERROR)
+            }
+        },
+        command::RELOAD_DEFAULTS => {
+            *keymap = Keymap::default();
+            response.status(status::OK)
+        }
+        command::GET_LAYER_COLOR => match keymap.layer_colors.get(request[1] as usize) {
+            Some(color) => {
+                response.u8(color.h);
+                response.u8(color.s);
+                response.u8(color.v);
+                response.status(status::OK)
+            }
+            None => response.status(status::INVALID_ARGUMENT),
+        },
+        command::SET_LAYER_COLOR => match keymap.layer_colors.get_mut(request[1] as usize) {
+            Some(color) => {
+                *color = Hsv::new(request[2], request[3], request[4]);
+                response.status(status::OK)
+            }
+            None => response.status(status::INVALID_ARGUMENT),
+        },
+        command::GET_STATE => {
+            response.u8(live.layer);
+            response.u8(live.wasd_mode as u8);
+            response.u32(live.pressed);
+            response.u16(live.joy_x);
+            response.u16(live.joy_y);
+            response.status(status::OK)
+        }
+        command::GET_LOG => {
+            let from = u32::from_le_bytes([request[1], request[2], request[3], request[4]]);
+            let mut text = [0u8; REPORT_LEN - 7];
+            let (start, len) = log.read(from, &mut text);
+            response.u32(start);
+            response.u8(len as u8);
+            response.bytes(&text[..len]);
+            response.status(status::OK)
+        }
+        command::CALIBRATE => {
+            match request[1] {
+                calibrate_step::START => calibrator.start(),
+                calibrate_step::FINISH => calibrator.finish(),
+                _ => {}
+            }
+            response.u8(match calibrator.phase() {
+                CalibrationPhase::Idle => 0,
+                CalibrationPhase::Centering => 1,
+                CalibrationPhase::Sweeping => 2,
+            });
+            response.status(status::OK)
+        }
+        command::GET_RESPONSE => {
+            let stick = &keymap.joy_response;
+            response.u16(stick.axial_deadzone);
+            response.u16(stick.radial_deadzone);
+            response.u16(stick.outer_deadzone);
+            response.u16(stick.anti_deadzone);
+            let (kind, expo, points) = stick.curve.to_raw();
+            response.u8(kind);
+            response.u16(expo);
+            for point in points {
+                response.u16(point);
+            }
+            response.status(status::OK)
+        }
+        command::SET_RESPONSE => {
+            let mut points = [0; CURVE_POINTS];
+            for (i, point) in points.iter_mut().enumerate() {
+                *point = arg_u16(request, 12 + i * 2);
+            }
+            let curve = ResponseCurve::from_raw(request[9], arg_u16(request, 10), points);
+            let stick = curve.map(|curve| StickResponse {
+                axial_deadzone: arg_u16(request, 1),
+                radial_deadzone: arg_u16(request, 3),
+                outer_deadzone: arg_u16(request, 5),
+                anti_deadzone: arg_u16(request, 7),
+                curve,
+            });
+            match stick {
+                Some(stick) if stick.is_valid() => {
+                    keymap.joy_response = stick;
+                    response.status(status::OK)
+                }
+                _ => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        command::GET_WASD => {
+            let wasd = &keymap.wasd;
+            response.u8(wasd.sectors.count());
+            response.u16(wasd.engage);
+            response.u16(wasd.release);
+            response.u16(wasd.diagonal_overlap);
+            response.u16(wasd.hysteresis);
+            response.u16(wasd.pulse_period_ms);
+            response.u16(wasd.min_pulse_ms);
+            response.status(status::OK)
+        }
+        command::SET_WASD => {
+            let wasd = Sectors::from_count(request[1]).map(|sectors| WasdConfig {
+                sectors,
+                engage: arg_u16(request, 2),
+                release: arg_u16(request, 4),
+                diagonal_overlap: arg_u16(request, 6),
+                hysteresis: arg_u16(request, 8),
+                pulse_period_ms: arg_u16(request, 10),
+                min_pulse_ms: arg_u16(request, 12),
+            });
+            match wasd {
+                Some(wasd) if wasd.is_valid() => {
+                    keymap.wasd = wasd;
+                    response.status(status::OK)
+                }
+                _ => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        command::GET_WASD_RUN => {
+            let layer = request[1] as usize;
+            match keymap.wasd_run.get(layer) {
+                Some(run) => {
+                    response.u16(run.threshold);
+                    response.u8(run.mode.to_raw());
+                    for direction in keymap.wasd_run_mappings.iter() {
+                        let (action, button, consumer_button) = direction[layer].to_raw();
+                        response.u8(action);
+                        response.u8(button);
+                        response.u16(consumer_button);
+                    }
+                    response.status(status::OK)
+                }
+                None => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        command::SET_WASD_RUN => {
+            let layer = request[1] as usize;
+            let run = RunMode::from_raw(request[4]).map(|mode| WasdRun {
+                threshold: arg_u16(request, 2),
+                mode,
+            });
+            let mut mappings = [Mapping::from_action(KeyboardAction::None); 4];
+            let mut valid = layer < LAYER_COUNT;
+            for (i, mapping) in mappings.iter_mut().enumerate() {
+                let at = 5 + i * 4;
+                match Mapping::from_raw(request[at], request[at + 1], arg_u16(request, at + 2)) {
+                    Ok(decoded) => *mapping = decoded,
+                    Err(_) => valid = false,
+                }
+            }
+            match run {
+                Some(run) if valid && run.is_valid() => {
+                    keymap.wasd_run[layer] = run;
+                    for (direction, mapping) in keymap.wasd_run_mappings.iter_mut().zip(mappings) {
+                        direction[layer] = mapping;
+                    }
+                    response.status(status::OK)
+                }
+                _ => response.status(status::INVALID_ARGUMENT),
+            }
+        }
+        unknown if (0x80..=0xFD).contains(&unknown) => response.status(status::UNKNOWN_COMMAND),
+        _ => via::handle(request, keymap, via),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_ring::LogRing;
+    use crate::storage::RamFlash;
+
+    fn set_calibration(keymap: &mut Keymap, values: [u16; 7]) -> u8 {
+        let mut request = [0u8; REPORT_LEN];
+        request[0] = command::SET_CALIBRATION;
+        for (i, value) in values.iter().enumerate() {
+            request[1 + i * 2..3 + i * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        let mut store = KeymapStore::new(RamFlash::<4096, 2>::new());
+        let response = handle(
+            &request,
+            keymap,
+            &mut store,
+            &mut ViaState::new(),
+            &LiveState::default(),
+            &mut Calibrator::new(),
+            &LogRing::<64>::new(),
+        );
+        response[1]
+    }
+
+    #[test]
+    fn set_calibration_checks_each_axis() {
+        let mut keymap = Keymap::default();
+        let values = [500, 520, 15, 10, 1000, 20, 990];
+        assert_eq!(set_calibration(&mut keymap, values), status::OK);
+        assert_eq!(
+            keymap.joy_y_calibration(),
+            AxisCalibration {
+                min: 20,
+                center: 520,
+                max: 990
+            }
+        );
+        assert_eq!(keymap.joy_x_y_rotation, 15);
+
+        let accepted = keymap.joy_x_calibration();
+        for values in [
+            [500, 520, 0, 1000, 10, 20, 990],  // x inverted
+            [500, 520, 0, 10, 1000, 520, 990], // y min at centre
+            [500, 520, 0, 10, 1000, 20, 520],  // y max at centre
+            [0, 520, 0, 0, 1000, 20, 990],     // x all at rest
+        ] {
+            assert_eq!(
+                set_calibration(&mut keymap, values),
+                status::INVALID_ARGUMENT
+            );
+            assert_eq!(keymap.joy_x_calibration(), accepted);
+            assert_eq!(keymap.joy_x_y_rotation, 15);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::keymap_common::crc32;
+use core::fmt::{self, Write};
+use core::ptr;
+
+// What the panic handler leaves behind for the next boot. The record lives in RAM that the startup
+// code doesn't clear, so it survives a reset though not a power cycle; the magic number and CRC
+// tell a real record from whatever the RAM held at power-up.
+pub const CRASH_TEXT_LEN: usize = 1024;
+const MAGIC: u32 = 0x4352_5348; // "CRSH"
+
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    crc: u32,
+    len: u32,
+    text: [u8; CRASH_TEXT_LEN],
+}
+
+impl CrashRecord {
+    pub const fn new() -> CrashRecord {
+        CrashRecord {
+            magic: 0,
+            crc: 0,
+            len: 0,
+            text: [0; CRASH_TEXT_LEN],
+        }
+    }
+
+    /// Copies out the record at `record`, which may hold anything the RAM did, and leaves an empty
+    /// record there. Nothing but the magic number is read unless it matches.
+    ///
+    /// # Safety
+    ///
+    /// `record` must be valid for reads and writes and not referenced anywhere else.
+    pub unsafe fn recover(record: *mut CrashRecord) -> CrashRecord {
+        // volatile, as the contents came from before the reset rather than from this program
+        let found = if ptr::addr_of!((*record).magic).read_volatile() == MAGIC {
+            record.read_volatile()
+        } else {
+            CrashRecord::new()
+        };
+        record.write(CrashRecord::new());
+        found
+    }
+
+    /// Makes the text written so far the record.
+    pub fn seal(&mut self) {
+        self.crc = crc32(&self.text[..self.len as usize]);
+        self.magic = MAGIC;
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.len as usize <= CRASH_TEXT_LEN
+            && self.crc == crc32(&self.text[..self.len as usize])
+    }
+
+    /// The text of a valid record. The record is invalidated, so each crash is reported once.
+    pub fn take(&mut self) -> Option<&str> {
+        if !self.is_valid() {
+            return None;
+        }
+        self.magic = 0;
+        let text = &self.text[..self.len as usize];
+        match core::str::from_utf8(text) {
+            Ok(text) => Some(text),
+            // cut short in the middle of a character
+            Err(e) => core::str::from_utf8(&text[..e.valid_up_to()]).ok(),
+        }
+    }
+}
+
+impl Default for CrashRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appends to the record; whatever doesn't fit is dropped.
+impl Write for CrashRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.len as usize;
+        let len = s.len().min(CRASH_TEXT_LEN - start);
+        self.text[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len as u32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(text: &str) -> CrashRecord {
+        let mut record = CrashRecord::new();
+        record.write_str(text).unwrap();
+        record.seal();
+        record
+    }
+
+    #[test]
+    fn only_sealed_records_are_valid() {
+        let mut record = CrashRecord::new();
+        assert!(!record.is_valid());
+        record.write_str("panicked").unwrap();
+        assert!(!record.is_valid());
+        record.seal();
+        assert!(record.is_valid());
+
+        // a flipped bit or an impossible length
+        let mut damaged = sealed("panicked");
+        damaged.text[0] ^= 1;
+        assert!(!damaged.is_valid());
+        let mut damaged = sealed("panicked");
+        damaged.len = CRASH_TEXT_LEN as u32 + 1;
+        assert!(!damaged.is_valid());
+    }
+
+    #[test]
+    fn take_reports_once() {
+        let mut record = sealed("panicked at src/main.rs");
+        assert_eq!(record.take(), Some("panicked at src/main.rs"));
+        assert_eq!(record.take(), None);
+        assert_eq!(CrashRecord::new().take(), None);
+    }
+
+    #[test]
+    fn overlong_text_is_cut() {
+        let mut record = CrashRecord::new();
+        for _ in 0..CRASH_TEXT_LEN / 4 - 1 {
+            record.write_str("abcd").unwrap();
+        }
+        // the last character doesn't fit whole
+        record.write_str("abc\u{e9}").unwrap();
+        record.seal();
+        let text = record.take().unwrap();
+        assert_eq!(text.len(), CRASH_TEXT_LEN - 1);
+        assert!(text.ends_with("dabc"));
+    }
+
+    #[test]
+    fn recover_takes_the_record_and_leaves_an_empty_one() {
+        let mut slot = sealed("panicked");
+        let mut found = unsafe { CrashRecord::recover(&mut slot) };
+        assert_eq!(found.take(), Some("panicked"));
+        assert!(!slot.is_valid());
+        let mut again = unsafe { CrashRecord::recover(&mut slot) };
+        assert_eq!(again.take(), None);
+
+        // whatever the RAM held at power-up
+        let mut slot = CrashRecord::new();
+        slot.magic = 0x1234_5678;
+        slot.len = u32::MAX;
+        assert!(!unsafe { CrashRecord::recover(&mut slot) }.is_valid());
+        assert_eq!(slot.len, 0);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::color::Hsv;
+use crate::joystick::{ResponseCurve, StickResponse};
+use crate::keymap_common::{Consumer, Keyboard, KeyboardAction, Keymap, Mapping};
+use crate::wasd::{RunMode, Sectors, WasdConfig, WasdRun};
+
+// Written by build.rs from keymap.txt
+const DEFAULT_KEYMAP: Keymap = include!(concat!(env!("OUT_DIR"), "/default_keymap.rs"));
+
+impl Keymap {
+    /// The layout described by keymap.txt
+    pub const fn default() -> Keymap {
+        DEFAULT_KEYMAP
+    }
+}
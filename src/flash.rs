@@ -0,0 +1,146 @@
+use crate::storage::Flash;
+
+// The keymap ring sits below the region Teensyduino uses for EEPROM emulation (0x7C0000 onwards on
+// the Teensy 4.1), well past anything the firmware image will grow into.
+const REGION_OFFSET: u32 = 0x0078_0000;
+const REGION_SECTORS: usize = 8;
+const SECTOR_SIZE: usize = 4096;
+const PAGE_SIZE: usize = 256;
+const FLEXSPI_AHB_BASE: u32 = 0x6000_0000;
+const FLEXSPI_INSTANCE: u32 = 0;
+
+// The boot ROM's FlexSPI NOR driver, see the i.MX RT1060 reference manual, chapter 9.13
+#[repr(C)]
+struct FlexSpiNorDriver {
+    version: u32,
+    init: unsafe extern "C" fn(u32, *mut FlexSpiNorConfig) -> i32,
+    program: unsafe extern "C" fn(u32, *mut FlexSpiNorConfig, u32, *const u32) -> i32,
+    erase_all: unsafe extern "C" fn(u32, *mut FlexSpiNorConfig) -> i32,
+    erase: unsafe extern "C" fn(u32, *mut FlexSpiNorConfig, u32, u32) -> i32,
+    read: unsafe extern "C" fn(u32, *mut FlexSpiNorConfig, *mut u32, u32, u32) -> i32,
+    clear_cache: unsafe extern "C" fn(u32),
+}
+
+#[repr(C)]
+struct BootloaderApi {
+    version: u32,
+    copyright: *const u8,
+    run_bootloader: unsafe extern "C" fn(*mut u32),
+    reserved: *const u32,
+    flexspi_nor_driver: *const FlexSpiNorDriver,
+}
+
+const BOOTLOADER_API_POINTER: *const *const BootloaderApi = 0x0020_001C as *const _;
+
+/// The 512 byte FlexSPI NOR configuration block, copied from the start of flash where the boot
+/// ROM found it.
+#[repr(C, align(4))]
+struct FlexSpiNorConfig([u32; 128]);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TeensyFlashError {
+    OutOfBounds,
+    /// Status code returned by the ROM driver
+    Rom(i32),
+}
+
+pub struct TeensyFlash {
+    config: FlexSpiNorConfig,
+}
+
+impl TeensyFlash {
+    pub fn new() -> TeensyFlash {
+        let mut config = FlexSpiNorConfig([0; 128]);
+        let fcb = FLEXSPI_AHB_BASE as *const u32;
+        for (i, word) in config.0.iter_mut().enumerate() {
+            *word = unsafe { core::ptr::read_volatile(fcb.add(i)) };
+        }
+        TeensyFlash { config }
+    }
+
+    fn driver() -> &'static FlexSpiNorDriver {
+        unsafe { &*(**BOOTLOADER_API_POINTER).flexspi_nor_driver }
+    }
+
+    fn address(sector: usize, offset: usize, len: usize) -> Result<u32, TeensyFlashError> {
+        if sector >= REGION_SECTORS || offset + len > SECTOR_SIZE {
+            return Err(TeensyFlashError::OutOfBounds);
+        }
+        Ok(REGION_OFFSET + (sector * SECTOR_SIZE + offset) as u32)
+    }
+
+    /// Runs a ROM operation with interrupts off, since nothing may fetch from flash meanwhile,
+    /// then drops any stale copies of the region from the FlexSPI and data caches.
+    fn rom_operation(
+        &mut self,
+        op: impl FnOnce(&FlexSpiNorDriver, *mut FlexSpiNorConfig) -> i32,
+    ) -> Result<(), TeensyFlashError> {
+        let driver = TeensyFlash::driver();
+        let config = &mut self.config as *mut FlexSpiNorConfig;
+        let status = cortex_m::interrupt::free(|_| {
+            let status = op(driver, config);
+            unsafe { (driver.clear_cache)(FLEXSPI_INSTANCE) };
+            status
+        });
+        let mut peripherals = unsafe { cortex_m::Peripherals::steal() };
+        unsafe {
+            peripherals.SCB.invalidate_dcache_by_address(
+                (FLEXSPI_AHB_BASE + REGION_OFFSET) as usize,
+                REGION_SECTORS * SECTOR_SIZE,
+            )
+        };
+        match status {
+            0 => Ok(()),
+            e => Err(TeensyFlashError::Rom(e)),
+        }
+    }
+}
+
+impl Flash for TeensyFlash {
+    type Error = TeensyFlashError;
+    const SECTOR_SIZE: usize = SECTOR_SIZE;
+
+    fn sector_count(&self) -> usize {
+        REGION_SECTORS
+    }
+
+    fn read(&mut self, sector: usize, offset: usize, buf: &mut [u8]) -> Result<(), TeensyFlashError> {
+        let address = TeensyFlash::address(sector, offset, buf.len())?;
+        let mapped = (FLEXSPI_AHB_BASE + address) as *const u8;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile(mapped.add(i)) };
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), TeensyFlashError> {
+        let address = TeensyFlash::address(sector, 0, SECTOR_SIZE)?;
+        self.rom_operation(|driver, config| unsafe {
+            (driver.erase)(FLEXSPI_INSTANCE, config, address, SECTOR_SIZE as u32)
+        })
+    }
+
+    fn program(&mut self, sector: usize, offset: usize, data: &[u8]) -> Result<(), TeensyFlashError> {
+        TeensyFlash::address(sector, offset, data.len())?;
+        // the ROM programs whole pages; bytes outside `data` are left erased (0xFF)
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let page_offset = offset % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(data.len());
+            let mut page = [0xFFFF_FFFFu32; PAGE_SIZE / 4];
+            for (i, byte) in data[..len].iter().enumerate() {
+                let index = page_offset + i;
+                let shift = (index % 4) * 8;
+                page[index / 4] &= !(0xFF << shift) | ((*byte as u32) << shift);
+            }
+            let address = TeensyFlash::address(sector, offset - page_offset, PAGE_SIZE)?;
+            self.rom_operation(|driver, config| unsafe {
+                (driver.program)(FLEXSPI_INSTANCE, config, address, page.as_ptr())
+            })?;
+            offset += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+}
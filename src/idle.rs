@@ -0,0 +1,191 @@
+use crate::config_protocol::LiveState;
+
+// Power saving when nobody is using the pad: after a while without input the LEDs dim and the
+// inputs are scanned less often, later the LEDs go out. A suspended host puts the pad straight to
+// sleep. Any input brings back full brightness and scan rate.
+/// Raw joystick movement, on either axis, that counts as input
+pub const JOYSTICK_ACTIVITY: u16 = 24;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct IdleConfig {
+    pub dim_after_us: u64,
+    pub off_after_us: u64,
+    /// LED brightness while dimmed, 255 being full
+    pub dim_brightness: u8,
+    pub dim_scan_interval_us: u64,
+    pub off_scan_interval_us: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PowerLevel {
+    Active,
+    Dimmed,
+    Off,
+}
+
+pub struct IdleTracker {
+    config: IdleConfig,
+    last_input_us: u64,
+    /// Where the joystick was at the last input
+    joystick: (u16, u16),
+    level: PowerLevel,
+}
+
+impl IdleTracker {
+    pub fn new(config: IdleConfig, now_us: u64) -> IdleTracker {
+        IdleTracker {
+            config,
+            last_input_us: now_us,
+            joystick: (0, 0),
+            level: PowerLevel::Active,
+        }
+    }
+
+    /// Looks at the latest scan for input; `wheel_moved` covers the scroll wheel, which
+    /// LiveState doesn't. Returns the new level if it changed.
+    pub fn update(
+        &mut self,
+        live: &LiveState,
+        wheel_moved: bool,
+        suspended: bool,
+        now_us: u64,
+    ) -> Option<PowerLevel> {
+        let joystick_moved = live.joy_x.abs_diff(self.joystick.0) >= JOYSTICK_ACTIVITY
+            || live.joy_y.abs_diff(self.joystick.1) >= JOYSTICK_ACTIVITY;
+        if live.pressed != 0 || wheel_moved || joystick_moved {
+            self.last_input_us = now_us;
+            self.joystick = (live.joy_x, live.joy_y);
+        }
+        let idle_for = now_us.wrapping_sub(self.last_input_us);
+        let level = if suspended || idle_for >= self.config.off_after_us {
+            PowerLevel::Off
+        } else if idle_for >= self.config.dim_after_us {
+            PowerLevel::Dimmed
+        } else {
+            PowerLevel::Active
+        };
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some(level)
+    }
+
+    pub fn led_brightness(&self) -> u8 {
+        match self.level {
+            PowerLevel::Active => 255,
+            PowerLevel::Dimmed => self.config.dim_brightness,
+            PowerLevel::Off => 0,
+        }
+    }
+
+    /// Least time between scans, 0 for every pass of the main loop
+    pub fn scan_interval_us(&self) -> u64 {
+        match self.level {
+            PowerLevel::Active => 0,
+            PowerLevel::Dimmed => self.config.dim_scan_interval_us,
+            PowerLevel::Off => self.config.off_scan_interval_us,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: IdleConfig = IdleConfig {
+        dim_after_us: 1_000,
+        off_after_us: 5_000,
+        dim_brightness: 64,
+        dim_scan_interval_us: 20,
+        off_scan_interval_us: 50,
+    };
+
+    fn still() -> LiveState {
+        LiveState::default()
+    }
+
+    fn pressed() -> LiveState {
+        LiveState {
+            pressed: 1 << 4,
+            ..LiveState::default()
+        }
+    }
+
+    #[test]
+    fn dims_then_turns_off() {
+        let mut idle = IdleTracker::new(CONFIG, 0);
+        assert_eq!(idle.update(&still(), false, false, 999), None);
+        assert_eq!((idle.led_brightness(), idle.scan_interval_us()), (255, 0));
+        assert_eq!(
+            idle.update(&still(), false, false, 1_000),
+            Some(PowerLevel::Dimmed)
+        );
+        assert_eq!((idle.led_brightness(), idle.scan_interval_us()), (64, 20));
+        assert_eq!(idle.update(&still(), false, false, 4_999), None);
+        assert_eq!(
+            idle.update(&still(), false, false, 5_000),
+            Some(PowerLevel::Off)
+        );
+        assert_eq!((idle.led_brightness(), idle.scan_interval_us()), (0, 50));
+    }
+
+    #[test]
+    fn input_wakes() {
+        let mut idle = IdleTracker::new(CONFIG, 0);
+        idle.update(&still(), false, false, 10_000);
+        assert_eq!(
+            idle.update(&pressed(), false, false, 10_001),
+            Some(PowerLevel::Active)
+        );
+        assert_eq!(idle.scan_interval_us(), 0);
+        // idle time counts from the last input
+        assert_eq!(idle.update(&still(), false, false, 11_000), None);
+        assert_eq!(
+            idle.update(&still(), false, false, 11_001),
+            Some(PowerLevel::Dimmed)
+        );
+        assert_eq!(
+            idle.update(&still(), true, false, 11_002),
+            Some(PowerLevel::Active)
+        );
+    }
+
+    #[test]
+    fn joystick_wakes_past_the_noise() {
+        let mut idle = IdleTracker::new(CONFIG, 0);
+        idle.update(&still(), false, false, 2_000);
+        let nudged = LiveState {
+            joy_x: JOYSTICK_ACTIVITY - 1,
+            ..LiveState::default()
+        };
+        assert_eq!(idle.update(&nudged, false, false, 2_001), None);
+        let moved = LiveState {
+            joy_y: JOYSTICK_ACTIVITY,
+            ..LiveState::default()
+        };
+        assert_eq!(
+            idle.update(&moved, false, false, 2_002),
+            Some(PowerLevel::Active)
+        );
+        // measured from where it moved to
+        assert_eq!(idle.update(&moved, false, false, 2_003), None);
+    }
+
+    #[test]
+    fn suspend_turns_off_until_resumed() {
+        let mut idle = IdleTracker::new(CONFIG, 0);
+        assert_eq!(
+            idle.update(&still(), false, true, 10),
+            Some(PowerLevel::Off)
+        );
+        assert_eq!(idle.led_brightness(), 0);
+        // input alone doesn't wake a suspended pad's LEDs, the host has to resume first
+        assert_eq!(idle.update(&pressed(), false, true, 20), None);
+        assert_eq!(
+            idle.update(&still(), false, false, 30),
+            Some(PowerLevel::Active)
+        );
+        assert_eq!(idle.scan_interval_us(), 0);
+    }
+}
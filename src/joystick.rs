@@ -0,0 +1,888 @@
+// Joystick signal handling that doesn't need hardware. Raw ADC readings are 10 bits, but no stick
+// reaches both ends of that range, and few rest in its middle; each axis is calibrated with the
+// reading at its minimum, at rest and at its maximum, and stretched so that those end up at 0,
+// AXIS_CENTER and AXIS_MAX, the range the joystick report advertises.
+//
+// Between the raw readings and the report the stick goes through a pipeline, all in fixed point
+// with FULL_SCALE standing for full deflection either way:
+//
+//   centre     calibrate each axis into -FULL_SCALE..=FULL_SCALE, see AxisCalibration
+//   rotate     turn the stick to sit square in the case, see rotate
+//   deadzones  axial, then radial, then outer, see StickResponse
+//   curve      reshape the deflection, see ResponseCurve
+//   anti       start the output past the game's own deadzone
+//
+// The curve is applied before the anti-deadzone so that it can't pull small deflections back
+// under it.
+pub const AXIS_MAX: u16 = 0x3FF;
+pub const AXIS_CENTER: u16 = 0x200;
+/// Highest raw reading
+pub const RAW_MAX: u16 = 0x3FF;
+/// Full deflection inside the pipeline
+pub const FULL_SCALE: i32 = 4096;
+/// Response settings are in thousandths of full deflection
+pub const PERMILLE: u16 = 1000;
+/// Points on a custom response curve, evenly spaced from rest to full deflection
+pub const CURVE_POINTS: usize = 9;
+
+/// Rest samples averaged for the centre
+pub const CENTER_SAMPLES: u16 = 32;
+/// How long the sweep runs unless finished early
+pub const SWEEP_US: u64 = 10_000_000;
+/// An axis swept over less than this, either side of centre, keeps its old limit
+pub const MIN_SWEEP: u16 = 64;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AxisCalibration {
+    pub min: u16,
+    pub center: u16,
+    pub max: u16,
+}
+
+impl AxisCalibration {
+    /// Whether the centre sits strictly between the ends, so the axis deflects both ways
+    pub fn is_valid(&self) -> bool {
+        self.min < self.center && self.center < self.max
+    }
+
+    /// Maps a raw reading onto -FULL_SCALE..=FULL_SCALE, 0 being the centre.
+    pub fn deflection(&self, raw: u16) -> i32 {
+        let (min, center, max) = (self.min as i32, self.center as i32, self.max as i32);
+        let raw = raw as i32;
+        if raw < center {
+            if center <= min {
+                0
+            } else {
+                -(center - raw.max(min)) * FULL_SCALE / (center - min)
+            }
+        } else if max <= center {
+            0
+        } else {
+            (raw.min(max) - center) * FULL_SCALE / (max - center)
+        }
+    }
+}
+
+/// A stick position in pipeline units, with the axes as the report has them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StickVector {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl StickVector {
+    pub const ZERO: StickVector = StickVector { x: 0, y: 0 };
+
+    pub fn magnitude(&self) -> i32 {
+        let squared = (self.x as i64 * self.x as i64 + self.y as i64 * self.y as i64) as u64;
+        isqrt(squared) as i32
+    }
+
+    /// Direction in whole degrees, 0 along +x and 90 along +y. The centre reads 0.
+    pub fn angle(&self) -> u16 {
+        let (ax, ay) = (self.x.unsigned_abs() as i64, self.y.unsigned_abs() as i64);
+        // within the first octant: the smallest whole angle whose tangent reaches low / high
+        let (low, high) = if ay <= ax { (ay, ax) } else { (ax, ay) };
+        let mut octant = 0;
+        while octant < 45 && low * SIN_TABLE[90 - octant] as i64 > high * SIN_TABLE[octant] as i64 {
+            octant += 1;
+        }
+        let quadrant = if ay <= ax { octant } else { 90 - octant } as u16;
+        match (self.x >= 0, self.y >= 0) {
+            (true, true) => quadrant,
+            (false, true) => 180 - quadrant,
+            (false, false) => 180 + quadrant,
+            (true, false) => (360 - quadrant) % 360,
+        }
+    }
+
+    /// Report value for each axis, saturating anything past full deflection.
+    pub fn to_report(self) -> (u16, u16) {
+        (axis_to_report(self.x), axis_to_report(self.y))
+    }
+}
+
+fn axis_to_report(value: i32) -> u16 {
+    let value = value.clamp(-FULL_SCALE, FULL_SCALE);
+    let half = if value < 0 {
+        AXIS_CENTER as i32
+    } else {
+        (AXIS_MAX - AXIS_CENTER) as i32
+    };
+    // round to nearest, away from the centre at .5
+    let offset = (value * half + value.signum() * FULL_SCALE / 2) / FULL_SCALE;
+    (AXIS_CENTER as i32 + offset) as u16
+}
+
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method from above, converging on the floor of the root
+    let mut x = value;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+/// sin(d) for d in 0..=90 degrees, scaled by 1 << SIN_SHIFT
+const SIN_TABLE: [i32; 91] = [
+    0, 286, 572, 857, 1143, 1428, 1713, 1997, 2280, 2563, 2845, 3126, 3406, 3686, 3964, 4240, 4516,
+    4790, 5063, 5334, 5604, 5872, 6138, 6402, 6664, 6924, 7182, 7438, 7692, 7943, 8192, 8438, 8682,
+    8923, 9162, 9397, 9630, 9860, 10087, 10311, 10531, 10749, 10963, 11174, 11381, 11585, 11786,
+    11982, 12176, 12365, 12551, 12733, 12911, 13085, 13255, 13421, 13583, 13741, 13894, 14044,
+    14189, 14330, 14466, 14598, 14726, 14849, 14968, 15082, 15191, 15296, 15396, 15491, 15582,
+    15668, 15749, 15826, 15897, 15964, 16026, 16083, 16135, 16182, 16225, 16262, 16294, 16322,
+    16344, 16362, 16374, 16382, 16384,
+];
+const SIN_SHIFT: u32 = 14;
+
+fn sin(degrees: u16) -> i32 {
+    let degrees = (degrees % 360) as usize;
+    match degrees {
+        0..=90 => SIN_TABLE[degrees],
+        91..=180 => SIN_TABLE[180 - degrees],
+        181..=270 => -SIN_TABLE[degrees - 180],
+        _ => -SIN_TABLE[360 - degrees],
+    }
+}
+
+fn cos(degrees: u16) -> i32 {
+    sin((degrees % 360) + 90)
+}
+
+/// Turns the stick about its centre by whole `degrees`, any number of them, +x towards -y
+/// (against StickVector::angle). The result can reach past full deflection on an axis when a
+/// corner is turned onto it; StickResponse::apply and StickVector::to_report saturate it.
+pub fn rotate(vector: StickVector, degrees: u16) -> StickVector {
+    let (sin, cos) = (sin(degrees) as i64, cos(degrees) as i64);
+    let (x, y) = (vector.x as i64, vector.y as i64);
+    let round = 1 << (SIN_SHIFT - 1);
+    StickVector {
+        x: ((x * cos + y * sin + round) >> SIN_SHIFT) as i32,
+        y: ((y * cos - x * sin + round) >> SIN_SHIFT) as i32,
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ResponseCurve {
+    Linear,
+    /// Blends the deflection with its cube, the "expo" of RC transmitters: 0 is linear, PERMILLE
+    /// is fully cubic and gentlest near the centre.
+    Exponential(u16),
+    /// Output at each of CURVE_POINTS evenly spaced deflections, in thousandths, joined by
+    /// straight lines
+    Custom([u16; CURVE_POINTS]),
+}
+
+impl ResponseCurve {
+    /// Custom points that make a straight line
+    pub const LINEAR_POINTS: [u16; CURVE_POINTS] = [0, 125, 250, 375, 500, 625, 750, 875, 1000];
+
+    /// Kind (0 linear, 1 exponential, 2 custom), expo and points, as stored and sent; fields the
+    /// kind doesn't use are 0 and LINEAR_POINTS.
+    pub fn to_raw(self) -> (u8, u16, [u16; CURVE_POINTS]) {
+        match self {
+            ResponseCurve::Linear => (0, 0, ResponseCurve::LINEAR_POINTS),
+            ResponseCurve::Exponential(expo) => (1, expo, ResponseCurve::LINEAR_POINTS),
+            ResponseCurve::Custom(points) => (2, 0, points),
+        }
+    }
+
+    pub fn from_raw(kind: u8, expo: u16, points: [u16; CURVE_POINTS]) -> Option<ResponseCurve> {
+        match kind {
+            0 => Some(ResponseCurve::Linear),
+            1 => Some(ResponseCurve::Exponential(expo)),
+            2 => Some(ResponseCurve::Custom(points)),
+            _ => None,
+        }
+    }
+
+    /// Reshapes a deflection in 0..=FULL_SCALE.
+    pub fn apply(&self, deflection: i32) -> i32 {
+        let t = deflection.clamp(0, FULL_SCALE) as i64;
+        let full = FULL_SCALE as i64;
+        match *self {
+            ResponseCurve::Linear => t as i32,
+            ResponseCurve::Exponential(expo) => {
+                let expo = expo.min(PERMILLE) as i64;
+                let cubed = t * t / full * t / full;
+                ((t * (PERMILLE as i64 - expo) + cubed * expo) / PERMILLE as i64) as i32
+            }
+            ResponseCurve::Custom(points) => {
+                let segments = (CURVE_POINTS - 1) as i64;
+                let segment = (t * segments / full).min(segments - 1) as usize;
+                let from = from_permille(points[segment]) as i64;
+                let to = from_permille(points[segment + 1]) as i64;
+                let into = t * segments - segment as i64 * full;
+                (from + (to - from) * into / full) as i32
+            }
+        }
+    }
+}
+
+fn from_permille(value: u16) -> i32 {
+    value.min(PERMILLE) as i32 * FULL_SCALE / PERMILLE as i32
+}
+
+/// How the stick feels past calibration and rotation. Every size is in thousandths of full
+/// deflection.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StickResponse {
+    /// Each axis reads 0 until it passes this, then rises from 0
+    pub axial_deadzone: u16,
+    /// The stick reads centred until it is this far out in any direction
+    pub radial_deadzone: u16,
+    /// Anything within this of the edge reads as full deflection
+    pub outer_deadzone: u16,
+    /// The smallest deflection sent once the stick is past the deadzones
+    pub anti_deadzone: u16,
+    pub curve: ResponseCurve,
+}
+
+impl StickResponse {
+    /// No deadzones, linear: the stick as calibrated
+    pub const LINEAR: StickResponse = StickResponse {
+        axial_deadzone: 0,
+        radial_deadzone: 0,
+        outer_deadzone: 0,
+        anti_deadzone: 0,
+        curve: ResponseCurve::Linear,
+    };
+
+    /// Whether every size and curve point is within PERMILLE
+    pub fn is_valid(&self) -> bool {
+        let (_, expo, points) = self.curve.to_raw();
+        [
+            self.axial_deadzone,
+            self.radial_deadzone,
+            self.outer_deadzone,
+            self.anti_deadzone,
+            expo,
+        ]
+        .into_iter()
+        .chain(points)
+        .all(|value| value <= PERMILLE)
+    }
+
+    /// Runs the deadzone, curve and anti-deadzone stages. The result is at most FULL_SCALE long.
+    pub fn apply(&self, vector: StickVector) -> StickVector {
+        let axial = from_permille(self.axial_deadzone);
+        let axis = |value: i32| {
+            // past full deflection only after rotation; clamping also keeps a full axial
+            // deadzone from dividing by zero, as nothing gets past it
+            let value = value.clamp(-FULL_SCALE, FULL_SCALE);
+            if value.abs() <= axial {
+                0
+            } else {
+                value.signum() * (value.abs() - axial) * FULL_SCALE / (FULL_SCALE - axial)
+            }
+        };
+        let vector = StickVector {
+            x: axis(vector.x),
+            y: axis(vector.y),
+        };
+
+        let inner = from_permille(self.radial_deadzone);
+        let edge = FULL_SCALE - from_permille(self.outer_deadzone);
+        let magnitude = vector.magnitude();
+        if magnitude <= inner {
+            return StickVector::ZERO;
+        }
+        let deflection = if edge <= inner {
+            FULL_SCALE
+        } else {
+            ((magnitude - inner) * FULL_SCALE / (edge - inner)).min(FULL_SCALE)
+        };
+        let deflection = self.curve.apply(deflection);
+        let anti = from_permille(self.anti_deadzone);
+        let deflection = anti + deflection * (FULL_SCALE - anti) / FULL_SCALE;
+
+        // keep the direction, swap in the new length
+        let scale = |value: i32| (value as i64 * deflection as i64 / magnitude as i64) as i32;
+        StickVector {
+            x: scale(vector.x),
+            y: scale(vector.y),
+        }
+    }
+}
+
+/// The whole pipeline from raw readings to stick position, as a keymap configures it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StickPipeline {
+    pub x: AxisCalibration,
+    pub y: AxisCalibration,
+    /// In degrees, see rotate
+    pub rotation: u16,
+    pub response: StickResponse,
+}
+
+impl StickPipeline {
+    /// The centre and rotate stages: where the stick points, before any deadzone.
+    pub fn aligned(&self, raw_x: u16, raw_y: u16) -> StickVector {
+        let centred = StickVector {
+            x: self.x.deflection(raw_x),
+            y: self.y.deflection(raw_y),
+        };
+        rotate(centred, self.rotation)
+    }
+
+    pub fn run(&self, raw_x: u16, raw_y: u16) -> StickVector {
+        self.response.apply(self.aligned(raw_x, raw_y))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CalibrationPhase {
+    Idle,
+    /// Averaging readings with the stick left alone
+    Centering,
+    /// Recording the extremes while the stick is moved all the way round
+    Sweeping,
+}
+
+/// Calibration routine: the centre from the stick at rest, then the limits from a sweep.
+pub struct Calibrator {
+    phase: CalibrationPhase,
+    samples: u16,
+    sum: (u32, u32),
+    center: (u16, u16),
+    min: (u16, u16),
+    max: (u16, u16),
+    sweep_started_us: u64,
+    finish_requested: bool,
+}
+
+impl Calibrator {
+    pub fn new() -> Calibrator {
+        Calibrator {
+            phase: CalibrationPhase::Idle,
+            samples: 0,
+            sum: (0, 0),
+            center: (0, 0),
+            min: (0, 0),
+            max: (0, 0),
+            sweep_started_us: 0,
+            finish_requested: false,
+        }
+    }
+
+    pub fn phase(&self) -> CalibrationPhase {
+        self.phase
+    }
+
+    /// Starts over from centring, whatever the routine was doing.
+    pub fn start(&mut self) {
+        self.phase = CalibrationPhase::Centering;
+        self.samples = 0;
+        self.sum = (0, 0);
+        self.finish_requested = false;
+    }
+
+    /// Ends the sweep at the next sample rather than after SWEEP_US. Ignored while centring.
+    pub fn finish(&mut self) {
+        if self.phase == CalibrationPhase::Sweeping {
+            self.finish_requested = true;
+        }
+    }
+
+    /// Starts the routine if it isn't running, otherwise moves it along: the single button
+    /// mapped to Action::CalibrateJoystick does both.
+    pub fn advance(&mut self) {
+        match self.phase {
+            CalibrationPhase::Idle => self.start(),
+            _ => self.finish(),
+        }
+    }
+
+    /// Takes one pair of raw readings. Once the sweep ends, returns the new x and y calibration,
+    /// starting from `current` for any limit that wasn't swept far enough to trust.
+    pub fn sample(
+        &mut self,
+        x: u16,
+        y: u16,
+        now_us: u64,
+        current: (AxisCalibration, AxisCalibration),
+    ) -> Option<(AxisCalibration, AxisCalibration)> {
+        match self.phase {
+            CalibrationPhase::Idle => None,
+            CalibrationPhase::Centering => {
+                self.sum.0 += x as u32;
+                self.sum.1 += y as u32;
+                self.samples += 1;
+                if self.samples == CENTER_SAMPLES {
+                    let samples = CENTER_SAMPLES as u32;
+                    self.center = ((self.sum.0 / samples) as u16, (self.sum.1 / samples) as u16);
+                    self.min = self.center;
+                    self.max = self.center;
+                    self.sweep_started_us = now_us;
+                    self.phase = CalibrationPhase::Sweeping;
+                }
+                None
+            }
+            CalibrationPhase::Sweeping => {
+                self.min = (self.min.0.min(x), self.min.1.min(y));
+                self.max = (self.max.0.max(x), self.max.1.max(y));
+                let timed_out = now_us.wrapping_sub(self.sweep_started_us) >= SWEEP_US;
+                if !(timed_out || self.finish_requested) {
+                    return None;
+                }
+                self.phase = CalibrationPhase::Idle;
+                let axis = |center: u16, min: u16, max: u16, current: AxisCalibration| {
+                    // an old limit on the wrong side of the new centre is no use at all
+                    let min = match (center - min >= MIN_SWEEP, current.min < center) {
+                        (true, _) => min,
+                        (false, true) => current.min,
+                        (false, false) => 0,
+                    };
+                    let max = match (max - center >= MIN_SWEEP, current.max > center) {
+                        (true, _) => max,
+                        (false, true) => current.max,
+                        (false, false) => RAW_MAX,
+                    };
+                    AxisCalibration { min, center, max }
+                };
+                Some((
+                    axis(self.center.0, self.min.0, self.max.0, current.0),
+                    axis(self.center.1, self.min.1, self.max.1, current.1),
+                ))
+            }
+        }
+    }
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick(x: i32, y: i32) -> StickVector {
+        StickVector { x, y }
+    }
+
+    #[test]
+    fn deflection_stretches_each_side() {
+        let axis = AxisCalibration {
+            min: 100,
+            center: 500,
+            max: 900,
+        };
+        assert_eq!(axis.deflection(500), 0);
+        assert_eq!(axis.deflection(900), FULL_SCALE);
+        assert_eq!(axis.deflection(1023), FULL_SCALE);
+        assert_eq!(axis.deflection(100), -FULL_SCALE);
+        assert_eq!(axis.deflection(0), -FULL_SCALE);
+        assert_eq!(axis.deflection(300), -FULL_SCALE / 2);
+        assert_eq!(axis.deflection(700), FULL_SCALE / 2);
+    }
+
+    #[test]
+    fn degenerate_calibration_reads_centred() {
+        for (min, center, max) in [(500, 500, 500), (600, 500, 400), (0, 0, 0)] {
+            let axis = AxisCalibration { min, center, max };
+            assert!(!axis.is_valid());
+            for raw in [0, 400, 500, 600, RAW_MAX] {
+                assert_eq!(axis.deflection(raw), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn report_saturates() {
+        assert_eq!(StickVector::ZERO.to_report(), (AXIS_CENTER, AXIS_CENTER));
+        assert_eq!(stick(FULL_SCALE, -FULL_SCALE).to_report(), (AXIS_MAX, 0));
+        assert_eq!(
+            stick(2 * FULL_SCALE, -2 * FULL_SCALE).to_report(),
+            (AXIS_MAX, 0)
+        );
+    }
+
+    #[test]
+    fn angle_in_each_quadrant() {
+        assert_eq!(StickVector::ZERO.angle(), 0);
+        // along the axes and the diagonals
+        for (x, y, angle) in [
+            (1000, 0, 0),
+            (1000, 1000, 45),
+            (0, 1000, 90),
+            (-1000, 1000, 135),
+            (-1000, 0, 180),
+            (-1000, -1000, 225),
+            (0, -1000, 270),
+            (1000, -1000, 315),
+        ] {
+            assert_eq!(stick(x, y).angle(), angle, "{x}, {y}");
+        }
+        // atan(1/3) is 18.4, read as 19 and mirrored into each quadrant
+        for (x, y, angle) in [
+            (3000, 1000, 19),
+            (1000, 3000, 71),
+            (-1000, 3000, 109),
+            (-3000, 1000, 161),
+            (-3000, -1000, 199),
+            (-1000, -3000, 251),
+            (1000, -3000, 289),
+            (3000, -1000, 341),
+        ] {
+            assert_eq!(stick(x, y).angle(), angle, "{x}, {y}");
+        }
+        // just below +x
+        assert_eq!(stick(FULL_SCALE, -1).angle(), 359);
+    }
+
+    #[test]
+    fn rotate_by_right_angles() {
+        let v = stick(3000, -1000);
+        assert_eq!(rotate(v, 0), v);
+        assert_eq!(rotate(v, 90), stick(-1000, -3000));
+        assert_eq!(rotate(v, 180), stick(-3000, 1000));
+        assert_eq!(rotate(v, 270), stick(1000, 3000));
+        // whole turns are nothing
+        assert_eq!(rotate(v, 360), v);
+        assert_eq!(rotate(v, 450), rotate(v, 90));
+        // +x turns towards -y, against the angle
+        assert_eq!(rotate(stick(FULL_SCALE, 0), 90), stick(0, -FULL_SCALE));
+        assert_eq!(stick(FULL_SCALE, 0).angle(), 0);
+        assert_eq!(stick(0, -FULL_SCALE).angle(), 270);
+    }
+
+    #[test]
+    fn rotate_by_odd_angles() {
+        // cos 15 = 15826 / 16384, sin 15 = 4240 / 16384
+        assert_eq!(rotate(stick(FULL_SCALE, 0), 15), stick(3957, -1060));
+        assert_eq!(rotate(stick(0, FULL_SCALE), 15), stick(1060, 3957));
+        for degrees in [1, 15, 37, 122, 359] {
+            let turned = rotate(stick(2000, 1500), degrees);
+            assert!((turned.magnitude() - 2500).abs() <= 1);
+            let angle = (stick(2000, 1500).angle() + 360 - degrees) % 360;
+            let off = (turned.angle() + 360 - angle) % 360;
+            assert!(off <= 1 || off == 359);
+        }
+        assert_eq!(rotate(StickVector::ZERO, 15), StickVector::ZERO);
+    }
+
+    #[test]
+    fn rotation_turns_about_the_calibrated_centre() {
+        let pipeline = StickPipeline {
+            x: AxisCalibration {
+                min: 100,
+                center: 600,
+                max: 1000,
+            },
+            y: AxisCalibration {
+                min: 20,
+                center: 450,
+                max: 900,
+            },
+            rotation: 90,
+            response: StickResponse::LINEAR,
+        };
+        // at rest the stick stays centred however far the centre is from the raw middle
+        assert_eq!(pipeline.aligned(600, 450), StickVector::ZERO);
+        assert_eq!(pipeline.aligned(1000, 450), stick(0, -FULL_SCALE));
+        assert_eq!(pipeline.aligned(600, 20), stick(-FULL_SCALE, 0));
+        assert_eq!(pipeline.aligned(350, 450), stick(0, FULL_SCALE / 2));
+
+        let pipeline = StickPipeline {
+            rotation: 15,
+            ..pipeline
+        };
+        assert_eq!(pipeline.aligned(600, 450), StickVector::ZERO);
+        assert_eq!(pipeline.aligned(1000, 450), stick(3957, -1060));
+    }
+
+    #[test]
+    fn rotated_corners_saturate_in_the_report() {
+        let corner = stick(FULL_SCALE, FULL_SCALE);
+        let turned = rotate(corner, 45);
+        assert!(turned.x > FULL_SCALE && turned.y.abs() <= 1);
+        assert_eq!(turned.to_report(), (AXIS_MAX, AXIS_CENTER));
+        let turned = rotate(corner, 15);
+        assert_eq!(turned.to_report().0, AXIS_MAX);
+        let turned = rotate(stick(-FULL_SCALE, -FULL_SCALE), 45);
+        assert_eq!(turned.to_report(), (0, AXIS_CENTER));
+    }
+
+    #[test]
+    fn axial_deadzone_zeroes_each_axis() {
+        let response = StickResponse {
+            axial_deadzone: 250,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(stick(1000, -1000)), StickVector::ZERO);
+        // the x axis rises from 0 past the deadzone while y stays inside it
+        assert_eq!(response.apply(stick(2560, 500)), stick(2048, 0));
+        assert_eq!(response.apply(stick(-FULL_SCALE, 0)), stick(-FULL_SCALE, 0));
+    }
+
+    #[test]
+    fn radial_deadzone_zeroes_the_centre() {
+        let response = StickResponse {
+            radial_deadzone: 250,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(stick(700, 700)), StickVector::ZERO);
+        assert_eq!(response.apply(stick(0, -2560)), stick(0, -2048));
+        assert_eq!(response.apply(stick(0, FULL_SCALE)), stick(0, FULL_SCALE));
+    }
+
+    #[test]
+    fn outer_deadzone_reaches_full_deflection_early() {
+        let response = StickResponse {
+            outer_deadzone: 250,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(stick(1536, 0)), stick(2048, 0));
+        assert_eq!(response.apply(stick(3072, 0)), stick(FULL_SCALE, 0));
+        assert_eq!(response.apply(stick(FULL_SCALE, 0)), stick(FULL_SCALE, 0));
+    }
+
+    #[test]
+    fn anti_deadzone_starts_past_the_game_deadzone() {
+        let response = StickResponse {
+            anti_deadzone: 250,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(StickVector::ZERO), StickVector::ZERO);
+        assert_eq!(response.apply(stick(1, 0)), stick(1024, 0));
+        assert_eq!(response.apply(stick(0, -2048)), stick(0, -2560));
+        assert_eq!(response.apply(stick(FULL_SCALE, 0)), stick(FULL_SCALE, 0));
+    }
+
+    #[test]
+    fn overlapping_deadzones_give_full_deflection() {
+        let response = StickResponse {
+            radial_deadzone: 600,
+            outer_deadzone: 600,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(stick(2000, 0)), StickVector::ZERO);
+        assert_eq!(response.apply(stick(0, 2500)), stick(0, FULL_SCALE));
+    }
+
+    #[test]
+    fn full_axial_deadzone_after_rotation() {
+        // turning a corner onto an axis reaches past full deflection
+        let turned = rotate(stick(FULL_SCALE, FULL_SCALE), 15);
+        assert!(turned.x > FULL_SCALE);
+        let response = StickResponse {
+            axial_deadzone: PERMILLE,
+            ..StickResponse::LINEAR
+        };
+        assert_eq!(response.apply(turned), StickVector::ZERO);
+        assert_eq!(response.apply(stick(-2 * FULL_SCALE, 0)), StickVector::ZERO);
+
+        let response = StickResponse {
+            axial_deadzone: 900,
+            ..StickResponse::LINEAR
+        };
+        let applied = response.apply(turned);
+        assert!(applied.magnitude() <= FULL_SCALE);
+        assert_eq!(applied.x, FULL_SCALE);
+    }
+
+    #[test]
+    fn linear_curve_is_the_identity() {
+        for t in (0..=FULL_SCALE).step_by(7) {
+            assert_eq!(ResponseCurve::Linear.apply(t), t);
+            let custom = ResponseCurve::Custom(ResponseCurve::LINEAR_POINTS);
+            assert_eq!(custom.apply(t), t);
+        }
+        assert_eq!(ResponseCurve::Linear.apply(-1), 0);
+        assert_eq!(ResponseCurve::Linear.apply(FULL_SCALE + 1), FULL_SCALE);
+    }
+
+    #[test]
+    fn exponential_curve_blends_in_the_cube() {
+        let half = FULL_SCALE / 2;
+        assert_eq!(ResponseCurve::Exponential(0).apply(half), half);
+        assert_eq!(ResponseCurve::Exponential(PERMILLE).apply(half), half / 4);
+        assert_eq!(
+            ResponseCurve::Exponential(500).apply(half),
+            (half + half / 4) / 2
+        );
+        for expo in [0, 500, PERMILLE, u16::MAX] {
+            let curve = ResponseCurve::Exponential(expo);
+            assert_eq!(curve.apply(0), 0);
+            assert_eq!(curve.apply(FULL_SCALE), FULL_SCALE);
+        }
+    }
+
+    #[test]
+    fn custom_curve_joins_its_points() {
+        let curve = ResponseCurve::Custom([0, 0, 0, 0, 500, 1000, 1000, 1000, 1000]);
+        assert_eq!(curve.apply(1024), 0);
+        assert_eq!(curve.apply(2048), 2048);
+        assert_eq!(curve.apply(2304), 3072);
+        assert_eq!(curve.apply(2560), FULL_SCALE);
+        assert_eq!(curve.apply(FULL_SCALE), FULL_SCALE);
+    }
+
+    #[test]
+    fn curve_keeps_the_direction() {
+        let response = StickResponse {
+            curve: ResponseCurve::Exponential(PERMILLE),
+            ..StickResponse::LINEAR
+        };
+        // 2048 long at 3-4-5, so a quarter of that after the cube
+        let applied = response.apply(stick(-1229, 1638));
+        assert!((applied.x + 307).abs() <= 1 && (applied.y - 409).abs() <= 1);
+    }
+
+    #[test]
+    fn response_checks_its_sizes() {
+        assert!(StickResponse::LINEAR.is_valid());
+        let too_big = StickResponse {
+            outer_deadzone: PERMILLE + 1,
+            ..StickResponse::LINEAR
+        };
+        assert!(!too_big.is_valid());
+        let bad_point = StickResponse {
+            curve: ResponseCurve::Custom([0, 0, 0, 0, 0, 0, 0, 0, PERMILLE + 1]),
+            ..StickResponse::LINEAR
+        };
+        assert!(!bad_point.is_valid());
+    }
+
+    const CURRENT: (AxisCalibration, AxisCalibration) = (
+        AxisCalibration {
+            min: 50,
+            center: 510,
+            max: 980,
+        },
+        AxisCalibration {
+            min: 40,
+            center: 505,
+            max: 990,
+        },
+    );
+
+    /// Runs the centring with the stick resting alternately either side of (x, y).
+    fn centred(x: u16, y: u16) -> Calibrator {
+        let mut calibrator = Calibrator::new();
+        calibrator.start();
+        for i in 0..CENTER_SAMPLES {
+            let wobble = if i % 2 == 0 { 0 } else { 2 };
+            assert_eq!(
+                calibrator.sample(x - 1 + wobble, y + 1 - wobble, 0, CURRENT),
+                None
+            );
+        }
+        calibrator
+    }
+
+    #[test]
+    fn centring_averages_the_rest_readings() {
+        let mut calibrator = Calibrator::new();
+        assert_eq!(calibrator.phase(), CalibrationPhase::Idle);
+        assert_eq!(calibrator.sample(0, 0, 0, CURRENT), None);
+
+        calibrator.start();
+        assert_eq!(calibrator.phase(), CalibrationPhase::Centering);
+        // finishing early only applies to the sweep
+        calibrator.finish();
+        for _ in 0..CENTER_SAMPLES - 1 {
+            assert_eq!(calibrator.sample(500, 520, 0, CURRENT), None);
+        }
+        assert_eq!(calibrator.phase(), CalibrationPhase::Centering);
+
+        let mut calibrator = centred(500, 520);
+        assert_eq!(calibrator.phase(), CalibrationPhase::Sweeping);
+        calibrator.finish();
+        let (x, y) = calibrator.sample(500, 520, 1000, CURRENT).unwrap();
+        assert_eq!((x.center, y.center), (500, 520));
+    }
+
+    #[test]
+    fn sweep_records_the_extremes() {
+        let mut calibrator = centred(500, 520);
+        for (x, y) in [(30, 520), (500, 1000), (1010, 480), (500, 15)] {
+            assert_eq!(calibrator.sample(x, y, 1000, CURRENT), None);
+        }
+        calibrator.finish();
+        let (x, y) = calibrator.sample(500, 520, 2000, CURRENT).unwrap();
+        assert_eq!(
+            x,
+            AxisCalibration {
+                min: 30,
+                center: 500,
+                max: 1010
+            }
+        );
+        assert_eq!(
+            y,
+            AxisCalibration {
+                min: 15,
+                center: 520,
+                max: 1000
+            }
+        );
+        assert!(x.is_valid() && y.is_valid());
+        assert_eq!(calibrator.phase(), CalibrationPhase::Idle);
+    }
+
+    #[test]
+    fn sweep_ends_by_itself() {
+        let mut calibrator = centred(500, 520);
+        assert_eq!(calibrator.sample(100, 900, SWEEP_US - 1, CURRENT), None);
+        let (x, y) = calibrator.sample(900, 100, SWEEP_US, CURRENT).unwrap();
+        assert_eq!((x.min, x.max, y.min, y.max), (100, 900, 100, 900));
+    }
+
+    #[test]
+    fn short_sweeps_keep_the_old_limits() {
+        let mut calibrator = centred(500, 520);
+        // x only nudged right, y not at all
+        calibrator.sample(500 + MIN_SWEEP - 1, 520, 0, CURRENT);
+        calibrator.finish();
+        let (x, y) = calibrator.sample(500, 520, 0, CURRENT).unwrap();
+        assert_eq!((x.min, x.max), (CURRENT.0.min, CURRENT.0.max));
+        assert_eq!((y.min, y.max), (CURRENT.1.min, CURRENT.1.max));
+
+        // exactly MIN_SWEEP is enough
+        let mut calibrator = centred(500, 520);
+        calibrator.sample(500 - MIN_SWEEP, 520 + MIN_SWEEP, 0, CURRENT);
+        calibrator.finish();
+        let (x, y) = calibrator.sample(500, 520, 0, CURRENT).unwrap();
+        assert_eq!((x.min, y.max), (500 - MIN_SWEEP, 520 + MIN_SWEEP));
+    }
+
+    #[test]
+    fn old_limits_past_the_new_centre_are_dropped() {
+        // the centre has moved beyond both of the old x limits
+        let mut calibrator = centred(1000, 20);
+        calibrator.finish();
+        let (x, y) = calibrator.sample(1000, 20, 0, CURRENT).unwrap();
+        assert_eq!((x.min, x.max), (CURRENT.0.min, RAW_MAX));
+        assert_eq!((y.min, y.max), (0, CURRENT.1.max));
+        assert!(x.is_valid() && y.is_valid());
+    }
+
+    #[test]
+    fn advance_starts_then_finishes() {
+        let mut calibrator = Calibrator::new();
+        calibrator.advance();
+        assert_eq!(calibrator.phase(), CalibrationPhase::Centering);
+        for _ in 0..CENTER_SAMPLES {
+            calibrator.sample(500, 520, 0, CURRENT);
+        }
+        calibrator.advance();
+        assert!(calibrator.sample(500, 520, 0, CURRENT).is_some());
+        // starting again drops a pending finish
+        let mut calibrator = centred(500, 520);
+        calibrator.finish();
+        calibrator.start();
+        for _ in 0..CENTER_SAMPLES {
+            calibrator.sample(500, 520, 0, CURRENT);
+        }
+        assert_eq!(calibrator.sample(500, 520, 0, CURRENT), None);
+    }
+}
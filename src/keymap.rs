@@ -1,3 +1,8 @@
+use crate::board_config::{LedPin, LED_COLOR_ORDER, LED_COUNT, LED_MAP};
+use crate::color::{Hsv, Rgb};
+use crate::config_protocol::LiveState;
+use crate::led_map::Input;
+use crate::wasd::{DirectionResolver, Directions, RunMode};
 use crate::ws2812::WS2812;
 use crate::KeypadReport;
 use crate::keymap_common::{Consumer, Keyboard, KeyboardAction, Mapping};
@@ -6,6 +11,8 @@ use teensy4_bsp::hal;
 use teensy4_bsp::pins::t41::Pins;
 
 const DEFAULT_WASD_MODE: bool = false;
+/// Scans an LED test lasts, about three seconds
+const LED_TEST_FRAMES: u16 = 300;
 
 macro_rules! declare_gpio_pin {
     ($type:tt,$number:tt,$pin:tt,1) => {
@@ -119,7 +126,7 @@ pub struct KeymapIOPoints {
     rotary2: hal::gpio::Input<Rotary2Pin>,
     joyx: hal::adc::AnalogInput<JoyXPin, 1>,
     joyy: hal::adc::AnalogInput<JoyYPin, 1>,
-    leds: WS2812,
+    leds: WS2812<LedPin, LED_COUNT>,
 }
 
 impl KeymapIOPoints {
@@ -159,7 +166,18 @@ impl KeymapIOPoints {
             rotary2: get_Rotary2_gpio_input!(gpio1, gpio2, gpio3, gpio4, pins),
             joyx: get_JoyX_adc_input!(pins),
             joyy: get_JoyY_adc_input!(pins),
-            leds: WS2812::new(gpio1.output(pins.p41), pit1),
+            leds: WS2812::new(
+                get_led_gpio_output!(gpio1, gpio2, gpio3, gpio4, pins),
+                pit1,
+                LED_COLOR_ORDER,
+            ),
+        }
+    }
+
+    /// Sets every LED the board attaches to `input`.
+    pub fn set_input_color(&mut self, input: Input, color: [u8; 3]) {
+        for led in LED_MAP.leds_for(input) {
+            self.leds.set_color(led, color);
         }
     }
 }
@@ -167,6 +185,7 @@ impl KeymapIOPoints {
 pub struct Report {
     buttons: [Option<Keyboard>; 26],
     button_count: usize,
+    modifier: u8,
     mouse_buttons: [Option<bool>; 3],
     joystick_button: Option<bool>,
     consumer_code: Consumer,
@@ -177,13 +196,16 @@ impl Report {
         Report {
             buttons: [None; 26],
             button_count: 0,
+            modifier: 0,
             mouse_buttons: [None; 3],
             joystick_button: None,
             consumer_code: Consumer::Unassigned,
         }
     }
     fn add_mapping(&mut self, mapping: Mapping) {
-        if mapping.button > Keyboard::ErrorUndefined {
+        if let Some(bit) = mapping.button.modifier_bit() {
+            self.modifier |= bit;
+        } else if mapping.button > Keyboard::ErrorUndefined {
             if self.button_count < 26 {
                 self.buttons[self.button_count] = Some(mapping.button);
                 self.button_count += 1;
@@ -219,7 +241,7 @@ impl Report {
             joy_buttons: 0,
             x: 0,
             y: 0,
-            modifier: 0, // TODO: modifier support
+            modifier: self.modifier,
             keycodes: [0; 26],
             consumer_keycode: 0,
         };
@@ -267,6 +289,13 @@ pub struct KeymapState {
     stored_layer: u8,
     current_layer: u8,
     rotary_1_prev: bool,
+    live: LiveState,
+    led_test_frames: u16,
+    led_brightness: u8,
+    /// Whether a CalibrateJoystick mapping was held at the last scan
+    calibrate_held: bool,
+    calibration_requested: bool,
+    directions: DirectionResolver,
 }
 
 impl KeymapState {
@@ -276,8 +305,40 @@ impl KeymapState {
             stored_layer: 0,
             current_layer: 0,
             rotary_1_prev: false,
+            live: LiveState::default(),
+            led_test_frames: 0,
+            led_brightness: 255,
+            calibrate_held: false,
+            calibration_requested: false,
+            directions: DirectionResolver::new(),
         }
     }
+
+    /// Makes `layer` the stored layer, as its LayerNSet action would.
+    pub fn set_layer(&mut self, layer: u8) {
+        self.stored_layer = layer;
+        self.current_layer = layer;
+    }
+
+    /// Sweeps every LED through the colour wheel for the next few seconds.
+    pub fn start_led_test(&mut self) {
+        self.led_test_frames = LED_TEST_FRAMES;
+    }
+
+    /// Dims the LEDs from the next `update` on: 255 is full brightness, 0 is off.
+    pub fn set_led_brightness(&mut self, brightness: u8) {
+        self.led_brightness = brightness;
+    }
+
+    /// Whether a CalibrateJoystick mapping was pressed since the last call
+    pub fn take_calibration_request(&mut self) -> bool {
+        core::mem::replace(&mut self.calibration_requested, false)
+    }
+
+    /// Inputs and layer as of the last `update`
+    pub fn live(&self) -> &LiveState {
+        &self.live
+    }
 }
 
 macro_rules! collapse_mapping {
@@ -290,12 +351,27 @@ macro_rules! collapse_mapping {
     }};
 }
 
+/// What a held WASD `direction` sends on `layer`: its own mapping, its run mapping or both.
+fn direction_mappings(
+    keymap: &Keymap,
+    direction: usize,
+    running: bool,
+    layer: u8,
+) -> [Option<Mapping>; 2] {
+    let replaced = running && keymap.wasd_run[layer as usize].mode == RunMode::Replace;
+    let walk = (!replaced).then(|| collapse_mapping!(keymap.wasd_mappings[direction], layer));
+    let run = running.then(|| collapse_mapping!(keymap.wasd_run_mappings[direction], layer));
+    [walk, run]
+}
+
 impl KeymapState {
+    /// Scans the inputs at `now_us`, as clock::micros has it, and builds the report they ask for.
     pub fn update(
         &mut self,
         adc1: &mut hal::adc::Adc<1>,
         io: &mut KeymapIOPoints,
         keymap: &mut Keymap,
+        now_us: u64,
     ) -> KeypadReport {
         let mut report = Report::new();
 
@@ -334,24 +410,38 @@ impl KeymapState {
         let joy_x = adc1.read_blocking(&mut io.joyx);
         let joy_y = adc1.read_blocking(&mut io.joyy);
 
-        // generate joystick report
-        let mut joy_x_f = joy_x as f32 - keymap.joy_x_center as f32;
-        let mut joy_y_f = joy_y as f32 - keymap.joy_y_center as f32;
-        // perform rotation
-        if keymap.joy_x_y_rotation != 0 {
-            let rads = (keymap.joy_x_y_rotation as f32).to_radians();
-            let cosine = libm::cosf(rads);
-            let sine = libm::sinf(rads);
-            joy_x_f = (joy_x as f32) * cosine + (joy_y as f32) * sine;
-            joy_y_f = -(joy_x as f32) * sine + (joy_y as f32) * cosine;
-        }
-        joy_x_f += keymap.joy_x_center as f32;
-        joy_y_f += keymap.joy_y_center as f32;
+        // centre the stick and turn it square with the case
+        let pipeline = keymap.joystick_pipeline();
+        let stick = pipeline.aligned(joy_x, joy_y);
+
+        // WASD directions act like keys, so they are resolved before anything else
+        let wasd_mode = self.wasd_mode;
+        let directions = if wasd_mode {
+            let run = &keymap.wasd_run[self.current_layer as usize];
+            self.directions.update(&keymap.wasd, stick, now_us);
+            self.directions.directions(&keymap.wasd, run, now_us)
+        } else {
+            self.directions.reset();
+            Directions::NONE
+        };
 
         // generate keyboard report and set joystick button
         // determine current layer and wasd mode before doing anything else
-        let mut keyboard_operations = [KeyboardAction::None; 23];
+        let mut keyboard_operations = [KeyboardAction::None; 31];
         let mut keyboard_op_count: usize = 0;
+        // WASD directions first
+        for (direction, held) in directions.held.into_iter().enumerate() {
+            if held {
+                let mappings =
+                    direction_mappings(keymap, direction, directions.running, self.current_layer);
+                for mapping in mappings.into_iter().flatten() {
+                    if let Some(op) = mapping.affects_reports() {
+                        keyboard_operations[keyboard_op_count] = op;
+                        keyboard_op_count += 1;
+                    }
+                }
+            }
+        }
         // keys in order
         for (i, key) in keys.into_iter().enumerate() {
             if key {
@@ -378,6 +468,7 @@ impl KeymapState {
                 keyboard_op_count += 1;
             }
         }
+        let mut calibrate_pressed = false;
         if keyboard_op_count > 0 {
             let mut layer_change = false;
             let mut wasd_change = false;
@@ -464,27 +555,32 @@ impl KeymapState {
                         self.wasd_mode = !self.wasd_mode;
                         wasd_change = true;
                     }
+                    KeyboardAction::CalibrateJoystick => calibrate_pressed = true,
                     _ => {
                         unreachable!("Mapping::affects_reports does not function correctly")
                     }
                 }
             }
         }
+        if calibrate_pressed && !self.calibrate_held {
+            self.calibration_requested = true;
+        }
+        self.calibrate_held = calibrate_pressed;
+        // run against the threshold of the layer the report is built on
+        let directions = if wasd_mode {
+            let run = &keymap.wasd_run[self.current_layer as usize];
+            self.directions.directions(&keymap.wasd, run, now_us)
+        } else {
+            directions
+        };
         // add WASD keys first
-        if self.wasd_mode {
-            if joy_y_f > (keymap.joy_y_center as f32 + keymap.joy_y_deadzone as f32) {
-                let mapping = collapse_mapping!(keymap.wasd_mappings[0], self.current_layer);
-                report.add_mapping(mapping);
-            } else if joy_y_f < (keymap.joy_y_center as f32 + keymap.joy_y_deadzone as f32) {
-                let mapping = collapse_mapping!(keymap.wasd_mappings[1], self.current_layer);
-                report.add_mapping(mapping);
-            }
-            if joy_x_f < (keymap.joy_x_center as f32 - keymap.joy_x_deadzone as f32) {
-                let mapping = collapse_mapping!(keymap.wasd_mappings[2], self.current_layer);
-                report.add_mapping(mapping);
-            } else if joy_y_f > (keymap.joy_x_center as f32 + keymap.joy_x_deadzone as f32) {
-                let mapping = collapse_mapping!(keymap.wasd_mappings[3], self.current_layer);
-                report.add_mapping(mapping);
+        for (direction, held) in directions.held.into_iter().enumerate() {
+            if held {
+                let mappings =
+                    direction_mappings(keymap, direction, directions.running, self.current_layer);
+                for mapping in mappings.into_iter().flatten() {
+                    report.add_mapping(mapping);
+                }
             }
         }
         // then keys in order
@@ -505,12 +601,46 @@ impl KeymapState {
             report.add_mapping(mapping);
         }
 
+        // remember what was held, inputs numbered as for INPUT_COUNT
+        let held = keys
+            .into_iter()
+            .chain([joy_button, scroll_button])
+            .chain(directions.held);
+        self.live = LiveState {
+            layer: self.current_layer,
+            wasd_mode: self.wasd_mode,
+            pressed: held
+                .enumerate()
+                .fold(0, |pressed, (i, held)| pressed | ((held as u32) << i)),
+            joy_x,
+            joy_y,
+        };
+
+        // paint the current layer's colour, lightening whatever is held
+        io.leds.set_brightness(self.led_brightness);
+        let layer_color = keymap.layer_colors[self.current_layer as usize].to_rgb();
+        let held_color = layer_color.blend(Rgb::new(255, 255, 255), 128);
+        for (i, key) in keys.into_iter().enumerate() {
+            let color = if key { held_color } else { layer_color };
+            io.set_input_color(Input::Key(i), color.to_array());
+        }
+        let color = if joy_button { held_color } else { layer_color };
+        io.set_input_color(Input::Joystick, color.to_array());
+        let color = if scroll_button { held_color } else { layer_color };
+        io.set_input_color(Input::Wheel, color.to_array());
+        if self.led_test_frames > 0 {
+            self.led_test_frames -= 1;
+            let hue = (self.led_test_frames as u8).wrapping_mul(4);
+            for led in 0..LED_COUNT {
+                let color = Hsv::new(hue.wrapping_add(led as u8 * 8), 255, 255).to_rgb();
+                io.leds.set_color(led, color.to_array());
+            }
+        }
         io.leds.show();
         let mut usb_report = report.finalize();
         if !self.wasd_mode {
             // write joystick x and y to report
-            usb_report.x = unsafe { joy_x_f.to_int_unchecked::<u16>() };
-            usb_report.y = unsafe { joy_y_f.to_int_unchecked::<u16>() };
+            (usb_report.x, usb_report.y) = pipeline.response.apply(stick).to_report();
         }
         // generate mouse wheel report
         // TODO: allow the scroll wheel to do something else
@@ -1,596 +1,1123 @@
+use crate::color::Hsv;
+use crate::joystick::{
+    AxisCalibration, ResponseCurve, StickPipeline, StickResponse, AXIS_CENTER, CURVE_POINTS,
+    PERMILLE, RAW_MAX,
+};
+use crate::wasd::{RunMode, Sectors, WasdConfig, WasdRun};
+
+/// Number of mappable inputs: 21 keys, the joystick button, the scroll button and the four
+/// WASD directions, numbered in that order.
+pub const INPUT_COUNT: usize = 21 + 1 + 1 + 4;
+
+/// Displays an input numbered as described at INPUT_COUNT: key0 to key20, joystick, scroll, then
+/// up, left, down and right for the WASD directions.
+#[derive(Copy, Clone, PartialEq)]
+pub struct InputName(pub usize);
+
+impl InputName {
+    /// The input called `name`, which may also be just its number.
+    pub fn parse(name: &str) -> Option<usize> {
+        let input = match name {
+            "joystick" => 21,
+            "scroll" => 22,
+            "up" => 23,
+            "left" => 24,
+            "down" => 25,
+            "right" => 26,
+            _ => name.strip_prefix("key").unwrap_or(name).parse().ok()?,
+        };
+        if name.starts_with("key") && input > 20 {
+            return None;
+        }
+        (input < INPUT_COUNT).then_some(input)
+    }
+}
+
+impl core::fmt::Display for InputName {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            0..=20 => write!(f, "key{}", self.0),
+            21 => f.write_str("joystick"),
+            22 => f.write_str("scroll"),
+            23 => f.write_str("up"),
+            24 => f.write_str("left"),
+            25 => f.write_str("down"),
+            26 => f.write_str("right"),
+            other => write!(f, "input{}", other),
+        }
+    }
+}
+
+/// Current keymap layout version. Bump it whenever the encoded format changes and teach
+/// `Keymap::decode` to fill in whatever older versions lack.
+pub const KEYMAP_VERSION: u8 = 7;
+pub const LAYER_COUNT: usize = 4;
 
 const DEFAULT_JOY_X_CENTER: u16 = 500;
 const DEFAULT_JOY_Y_CENTER: u16 = 500;
+const DEFAULT_JOY_MIN: u16 = 0;
+const DEFAULT_JOY_MAX: u16 = RAW_MAX;
 const DEFAULT_JOY_X_Y_ROTATION: u16 = 15;
-const DEFAULT_JOY_X_DEAD_ZONE: u16 = 200;
-const DEFAULT_JOY_Y_DEAD_ZONE: u16 = 200;
+const DEFAULT_LAYER_COLORS: [Hsv; 4] = [
+    Hsv::new(0, 0, 255),
+    Hsv::new(0, 255, 255),
+    Hsv::new(85, 255, 255),
+    Hsv::new(171, 255, 255),
+];
+const BLANK_LAYERS: [Mapping; 4] = [
+    Mapping::from_action(KeyboardAction::None),
+    Mapping::from_action(KeyboardAction::Transparent),
+    Mapping::from_action(KeyboardAction::Transparent),
+    Mapping::from_action(KeyboardAction::Transparent),
+];
+
+/// Declares a fieldless enum along with the names of its variants, a checked conversion from its
+/// `repr` type and a lookup by name or alias. Each enum supplies its own `ALIASES` table.
+macro_rules! named_enum {
+    (
+        #[repr($repr:ident)]
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident $(= $value:expr)?,)*
+        }
+    ) => {
+        #[repr($repr)]
+        $(#[$meta])*
+        pub enum $name {
+            $($variant $(= $value)?,)*
+        }
+
+        impl $name {
+            /// Every variant, in declaration order
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $($name::$variant => stringify!($variant),)*
+                }
+            }
+
+            pub const fn from_name(name: &str) -> Option<$name> {
+                let mut i = 0;
+                while i < $name::ALL.len() {
+                    if str_eq($name::ALL[i].name(), name) {
+                        return Some($name::ALL[i]);
+                    }
+                    i += 1;
+                }
+                None
+            }
+
+            /// Looks `name` up as a variant name first, then in `ALIASES`.
+            pub const fn lookup(name: &str) -> Option<$name> {
+                if let Some(value) = $name::from_name(name) {
+                    return Some(value);
+                }
+                let mut i = 0;
+                while i < $name::ALIASES.len() {
+                    if str_eq($name::ALIASES[i].0, name) {
+                        return Some($name::ALIASES[i].1);
+                    }
+                    i += 1;
+                }
+                None
+            }
+
+            pub const fn from_repr(value: $repr) -> Option<$name> {
+                match value {
+                    $(v if v == $name::$variant as $repr => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl TryFrom<$repr> for $name {
+            type Error = UnknownValue<$repr>;
+
+            fn try_from(value: $repr) -> Result<$name, UnknownValue<$repr>> {
+                $name::from_repr(value).ok_or(UnknownValue(value))
+            }
+        }
+
+        impl core::fmt::Display for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+}
+
+/// A number that is not the discriminant of any variant
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct UnknownValue<T>(pub T);
+
+/// `==` for strings, usable in const contexts.
+pub const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
 
 // we have lots of RAM... why not...
-#[repr(u16)]
-#[derive(Copy, Clone, PartialEq, PartialOrd)]
-pub enum Consumer {
-    Unassigned = 0x00,
-    Plus10 = 0x20,
-    Plus100 = 0x21,
-    AmPm = 0x22,
-    Power = 0x30,
-    Reset = 0x31,
-    Sleep = 0x32,
-    SleepAfter = 0x33,
-    SleepMode = 0x34,
-    Illumination = 0x35,
-    Menu = 0x40,
-    MenuPick = 0x41,
-    MenuUp = 0x42,
-    MenuDown = 0x43,
-    MenuLeft = 0x44,
-    MenuRight = 0x45,
-    MenuEscape = 0x46,
-    MenuValueIncrease = 0x47,
-    MenuValueDecrease = 0x48,
-    DataOnScreen = 0x60,
-    ClosedCaption = 0x61,
-    ClosedCaptionSelect = 0x62,
-    VcrTv = 0x63,
-    BroadcastMode = 0x64,
-    Snapshot = 0x65,
-    Still = 0x66,
-    Selection = 0x80,
-    AssignSelection = 0x81,
-    ModeStep = 0x82,
-    RecallLast = 0x83,
-    EnterChannel = 0x84,
-    OrderMovie = 0x85,
-    Channel = 0x86,
-    MediaSelection = 0x87,
-    MediaSelectComputer = 0x88,
-    MediaSelectTV = 0x89,
-    MediaSelectWWW = 0x8A,
-    MediaSelectDVD = 0x8B,
-    MediaSelectTelephone = 0x8C,
-    MediaSelectProgramGuide = 0x8D,
-    MediaSelectVideoPhone = 0x8E,
-    MediaSelectGames = 0x8F,
-    MediaSelectMessages = 0x90,
-    MediaSelectCD = 0x91,
-    MediaSelectVCR = 0x92,
-    MediaSelectTuner = 0x93,
-    Quit = 0x94,
-    Help = 0x95,
-    MediaSelectTape = 0x96,
-    MediaSelectCable = 0x97,
-    MediaSelectSatellite = 0x98,
-    MediaSelectSecurity = 0x99,
-    MediaSelectHome = 0x9A,
-    MediaSelectCall = 0x9B,
-    ChannelIncrement = 0x9C,
-    ChannelDecrement = 0x9D,
-    MediaSelectSAP = 0x9E,
-    VCRPlus = 0xA0,
-    Once = 0xA1,
-    Daily = 0xA2,
-    Weekly = 0xA3,
-    Monthly = 0xA4,
-    Play = 0xB0,
-    Pause = 0xB1,
-    Record = 0xB2,
-    FastForward = 0xB3,
-    Rewind = 0xB4,
-    ScanNextTrack = 0xB5,
-    ScanPreviousTrack = 0xB6,
-    Stop = 0xB7,
-    Eject = 0xB8,
-    RandomPlay = 0xB9,
-    SelectDisc = 0xBA,
-    EnterDisc = 0xBB,
-    Repeat = 0xBC,
-    Tracking = 0xBD,
-    TrackNormal = 0xBE,
-    SlowTracking = 0xBF,
-    FrameForward = 0xC0,
-    FrameBack = 0xC1,
-    Mark = 0xC2,
-    ClearMark = 0xC3,
-    RepeatFromMark = 0xC4,
-    ReturnToMark = 0xC5,
-    SearchMarkForward = 0xC6,
-    SearchMarkBackwards = 0xC7,
-    CounterReset = 0xC8,
-    ShowCounter = 0xC9,
-    TrackingIncrement = 0xCA,
-    TrackingDecrement = 0xCB,
-    StopEject = 0xCC,
-    PlayPause = 0xCD,
-    PlaySkip = 0xCE,
-    Volume = 0xE0,
-    Balance = 0xE1,
-    Mute = 0xE2,
-    Bass = 0xE3,
-    Treble = 0xE4,
-    BassBoost = 0xE5,
-    SurroundMode = 0xE6,
-    Loudness = 0xE7,
-    MPX = 0xE8,
-    VolumeIncrement = 0xE9,
-    VolumeDecrement = 0xEA,
-    SpeedSelect = 0xF0,
-    PlaybackSpeed = 0xF1,
-    StandardPlay = 0xF2,
-    LongPlay = 0xF3,
-    ExtendedPlay = 0xF4,
-    Slow = 0xF5,
-    FanEnable = 0x100,
-    FanSpeed = 0x101,
-    LightEnable = 0x102,
-    LightIlluminationLevel = 0x103,
-    ClimateControlEnable = 0x104,
-    RoomTemperature = 0x105,
-    SecurityEnable = 0x106,
-    FireAlarm = 0x107,
-    PoliceAlarm = 0x108,
-    Proximity = 0x109,
-    Motion = 0x10A,
-    DuressAlarm = 0x10B,
-    HoldupAlarm = 0x10C,
-    MedicalAlarm = 0x10D,
-    BalanceRight = 0x150,
-    BalanceLeft = 0x151,
-    BassIncrement = 0x152,
-    BassDecrement = 0x153,
-    TrebleIncrement = 0x154,
-    TrebleDecrement = 0x155,
-    SubChannel = 0x170,
-    SubChannelIncrement = 0x171,
-    SubChannelDecrement = 0x172,
-    AlternateAudioIncrement = 0x173,
-    AlternateAudioDecrement = 0x174,
-    ALLaunchButtonConfigurationTool = 0x181,
-    ALProgrammableButtonConfiguration = 0x182,
-    ALConsumerControlConfiguration = 0x183,
-    ALWordProcessor = 0x184,
-    ALTextEditor = 0x185,
-    ALSpreadsheet = 0x186,
-    ALGraphicsEditor = 0x187,
-    ALPresentationApp = 0x188,
-    ALDatabaseApp = 0x189,
-    ALEmailReader = 0x18A,
-    ALNewsreader = 0x18B,
-    ALVoicemail = 0x18C,
-    ALContactsAddressBook = 0x18D,
-    ALCalendarSchedule = 0x18E,
-    ALTaskProjectManager = 0x18F,
-    ALLogJournalTimecard = 0x190,
-    ALCheckbookFinance = 0x191,
-    ALCalculator = 0x192,
-    ALAvCapturePlayback = 0x193,
-    ALLocalMachineBrowser = 0x194,
-    ALLanWanBrowser = 0x195,
-    ALInternetBrowser = 0x196,
-    ALRemoteNetworkingISPConnect = 0x197,
-    ALNetworkConference = 0x198,
-    ALNetworkChat = 0x199,
-    ALTelephonyDialer = 0x19A,
-    ALLogon = 0x19B,
-    ALLogoff = 0x19C,
-    ALLogonLogoff = 0x19D,
-    ALTerminalLockScreensaver = 0x19E,
-    ALControlPanel = 0x19F,
-    ALCommandLineProcessorRun = 0x1A0,
-    ALProcessTaskManager = 0x1A1,
-    ALSelectTaskApplication = 0x1A2,
-    ALNextTaskApplication = 0x1A3,
-    ALPreviousTaskApplication = 0x1A4,
-    ALPreemptiveHaltTaskApplication = 0x1A5,
-    ALIntegratedHelpCenter = 0x1A6,
-    ALDocuments = 0x1A7,
-    ALThesaurus = 0x1A8,
-    ALDictionary = 0x1A9,
-    ALDesktop = 0x1AA,
-    ALSpellCheck = 0x1AB,
-    ALGrammarCheck = 0x1AC,
-    ALWirelessStatus = 0x1AD,
-    ALKeyboardLayout = 0x1AE,
-    ALVirusProtection = 0x1AF,
-    ALEncryption = 0x1B0,
-    ALScreenSaver = 0x1B1,
-    ALAlarms = 0x1B2,
-    ALClock = 0x1B3,
-    ALFileBrowser = 0x1B4,
-    ALPowerStatus = 0x1B5,
-    ALImageBrowser = 0x1B6,
-    ALAudioBrowser = 0x1B7,
-    ALMovieBrowser = 0x1B8,
-    ALDigitalRightsManager = 0x1B9,
-    ALDigitalWallet = 0x1BA,
-    ALInstantMessaging = 0x1BC,
-    ALOemFeaturesTipsTutorialBrowser = 0x1BD,
-    ALOemHelp = 0x1BE,
-    ALOnlineCommunity = 0x1BF,
-    ALEntertainmentContentBrowser = 0x1C0,
-    ALOnlineShoppingBrowser = 0x1C1,
-    ALSmartCardInformationHelp = 0x1C2,
-    ALMarketMonitorFinanceBrowser = 0x1C3,
-    ALCustomizedCorporateNewsBrowser = 0x1C4,
-    ALOnlineActivityBrowser = 0x1C5,
-    ALResearchSearchBrowser = 0x1C6,
-    ALAudioPlayer = 0x1C7,
-    ACNew = 0x201,
-    ACOpen = 0x202,
-    ACClose = 0x203,
-    ACExit = 0x204,
-    ACMaximize = 0x205,
-    ACMinimize = 0x206,
-    ACSave = 0x207,
-    ACPrint = 0x208,
-    ACProperties = 0x209,
-    ACUndo = 0x21A,
-    ACCopy = 0x21B,
-    ACCut = 0x21C,
-    ACPaste = 0x21D,
-    ACSelectAll = 0x21E,
-    ACFind = 0x21F,
-    ACFindAndReplace = 0x220,
-    ACSearch = 0x221,
-    ACGoTo = 0x222,
-    ACHome = 0x223,
-    ACBack = 0x224,
-    ACForward = 0x225,
-    ACStop = 0x226,
-    ACRefresh = 0x227,
-    ACPreviousLink = 0x228,
-    ACNextLink = 0x229,
-    ACBookmarks = 0x22A,
-    ACHistory = 0x22B,
-    ACSubscriptions = 0x22C,
-    ACZoomIn = 0x22D,
-    ACZoomOut = 0x22E,
-    ACZoom = 0x22F,
-    ACFullScreenView = 0x230,
-    ACNormalView = 0x231,
-    ACViewToggle = 0x232,
-    ACScrollUp = 0x233,
-    ACScrollDown = 0x234,
-    ACScroll = 0x235,
-    ACPanLeft = 0x236,
-    ACPanRight = 0x237,
-    ACPan = 0x238,
-    ACNewWindow = 0x239,
-    ACTileHorizontally = 0x23A,
-    ACTileVertically = 0x23B,
-    ACFormat = 0x23C,
-    ACEdit = 0x23D,
-    ACBold = 0x23E,
-    ACItalics = 0x23F,
-    ACUnderline = 0x240,
-    ACStrikethrough = 0x241,
-    ACSubscript = 0x242,
-    ACSuperscript = 0x243,
-    ACAllCaps = 0x244,
-    ACRotate = 0x245,
-    ACResize = 0x246,
-    ACFlipHorizontal = 0x247,
-    ACFlipVertical = 0x248,
-    ACMirrorHorizontal = 0x249,
-    ACMirrorVertical = 0x24A,
-    ACFontSelect = 0x24B,
-    ACFontColor = 0x24C,
-    ACFontSize = 0x24D,
-    ACJustifyLeft = 0x24E,
-    ACJustifyCenterH = 0x24F,
-    ACJustifyRight = 0x250,
-    ACJustifyBlockH = 0x251,
-    ACJustifyTop = 0x252,
-    ACJustifyCenterV = 0x253,
-    ACJustifyBottom = 0x254,
-    ACJustifyBlockV = 0x255,
-    ACIndentDecrease = 0x256,
-    ACIndentIncrease = 0x257,
-    ACNumberedList = 0x258,
-    ACRestartNumbering = 0x259,
-    ACBulletedList = 0x25A,
-    ACPromote = 0x25B,
-    ACDemote = 0x25C,
-    ACYes = 0x25D,
-    ACNo = 0x25E,
-    ACCancel = 0x25F,
-    ACCatalog = 0x260,
-    ACBuyCheckout = 0x261,
-    ACAddToCart = 0x262,
-    ACExpand = 0x263,
-    ACExpandAll = 0x264,
-    ACCollapse = 0x265,
-    ACCollapseAll = 0x266,
-    ACPrintPreview = 0x267,
-    ACPasteSpecial = 0x268,
-    ACInsertMode = 0x269,
-    ACDelete = 0x26A,
-    ACLock = 0x26B,
-    ACUnlock = 0x26C,
-    ACProtect = 0x26D,
-    ACUnprotect = 0x26E,
-    ACAttachComment = 0x26F,
-    ACDeleteComment = 0x270,
-    ACViewComment = 0x271,
-    ACSelectWord = 0x272,
-    ACSelectSentence = 0x273,
-    ACSelectParagraph = 0x274,
-    ACSelectColumn = 0x275,
-    ACSelectRow = 0x276,
-    ACSelectTable = 0x277,
-    ACSelectObject = 0x278,
-    ACRedoRepeat = 0x279,
-    ACSort = 0x27A,
-    ACSortAscending = 0x27B,
-    ACSortDescending = 0x27C,
-    ACFilter = 0x27D,
-    ACSetClock = 0x27E,
-    ACViewClock = 0x27F,
-    ACSelectTimeZone = 0x280,
-    ACEditTimeZones = 0x281,
-    ACSetAlarm = 0x282,
-    ACClearAlarm = 0x283,
-    ACSnoozeAlarm = 0x284,
-    ACResetAlarm = 0x285,
-    ACSynchronize = 0x286,
-    ACSendReceive = 0x287,
-    ACSendTo = 0x288,
-    ACReply = 0x289,
-    ACReplyAll = 0x28A,
-    ACForwardMsg = 0x28B,
-    ACSend = 0x28C,
-    ACAttachFile = 0x28D,
-    ACUpload = 0x28E,
-    ACDownloadSaveTargetAs = 0x28F,
-    ACSetBorders = 0x290,
-    ACInsertRow = 0x291,
-    ACInsertColumn = 0x292,
-    ACInsertFile = 0x293,
-    ACInsertPicture = 0x294,
-    ACInsertObject = 0x295,
-    ACInsertSymbol = 0x296,
-    ACSaveAndClose = 0x297,
-    ACRename = 0x298,
-    ACMerge = 0x299,
-    ACSplit = 0x29A,
-    ACDistributeHorizontally = 0x29B,
-    ACDistributeVertically = 0x29C,
+named_enum! {
+    #[repr(u16)]
+    #[derive(Copy, Clone, PartialEq, PartialOrd)]
+    // named as in the HID usage tables, which the text format relies on
+    #[allow(clippy::upper_case_acronyms)]
+    pub enum Consumer {
+        Unassigned = 0x00,
+        Plus10 = 0x20,
+        Plus100 = 0x21,
+        AmPm = 0x22,
+        Power = 0x30,
+        Reset = 0x31,
+        Sleep = 0x32,
+        SleepAfter = 0x33,
+        SleepMode = 0x34,
+        Illumination = 0x35,
+        Menu = 0x40,
+        MenuPick = 0x41,
+        MenuUp = 0x42,
+        MenuDown = 0x43,
+        MenuLeft = 0x44,
+        MenuRight = 0x45,
+        MenuEscape = 0x46,
+        MenuValueIncrease = 0x47,
+        MenuValueDecrease = 0x48,
+        DataOnScreen = 0x60,
+        ClosedCaption = 0x61,
+        ClosedCaptionSelect = 0x62,
+        VcrTv = 0x63,
+        BroadcastMode = 0x64,
+        Snapshot = 0x65,
+        Still = 0x66,
+        Selection = 0x80,
+        AssignSelection = 0x81,
+        ModeStep = 0x82,
+        RecallLast = 0x83,
+        EnterChannel = 0x84,
+        OrderMovie = 0x85,
+        Channel = 0x86,
+        MediaSelection = 0x87,
+        MediaSelectComputer = 0x88,
+        MediaSelectTV = 0x89,
+        MediaSelectWWW = 0x8A,
+        MediaSelectDVD = 0x8B,
+        MediaSelectTelephone = 0x8C,
+        MediaSelectProgramGuide = 0x8D,
+        MediaSelectVideoPhone = 0x8E,
+        MediaSelectGames = 0x8F,
+        MediaSelectMessages = 0x90,
+        MediaSelectCD = 0x91,
+        MediaSelectVCR = 0x92,
+        MediaSelectTuner = 0x93,
+        Quit = 0x94,
+        Help = 0x95,
+        MediaSelectTape = 0x96,
+        MediaSelectCable = 0x97,
+        MediaSelectSatellite = 0x98,
+        MediaSelectSecurity = 0x99,
+        MediaSelectHome = 0x9A,
+        MediaSelectCall = 0x9B,
+        ChannelIncrement = 0x9C,
+        ChannelDecrement = 0x9D,
+        MediaSelectSAP = 0x9E,
+        VCRPlus = 0xA0,
+        Once = 0xA1,
+        Daily = 0xA2,
+        Weekly = 0xA3,
+        Monthly = 0xA4,
+        Play = 0xB0,
+        Pause = 0xB1,
+        Record = 0xB2,
+        FastForward = 0xB3,
+        Rewind = 0xB4,
+        ScanNextTrack = 0xB5,
+        ScanPreviousTrack = 0xB6,
+        Stop = 0xB7,
+        Eject = 0xB8,
+        RandomPlay = 0xB9,
+        SelectDisc = 0xBA,
+        EnterDisc = 0xBB,
+        Repeat = 0xBC,
+        Tracking = 0xBD,
+        TrackNormal = 0xBE,
+        SlowTracking = 0xBF,
+        FrameForward = 0xC0,
+        FrameBack = 0xC1,
+        Mark = 0xC2,
+        ClearMark = 0xC3,
+        RepeatFromMark = 0xC4,
+        ReturnToMark = 0xC5,
+        SearchMarkForward = 0xC6,
+        SearchMarkBackwards = 0xC7,
+        CounterReset = 0xC8,
+        ShowCounter = 0xC9,
+        TrackingIncrement = 0xCA,
+        TrackingDecrement = 0xCB,
+        StopEject = 0xCC,
+        PlayPause = 0xCD,
+        PlaySkip = 0xCE,
+        Volume = 0xE0,
+        Balance = 0xE1,
+        Mute = 0xE2,
+        Bass = 0xE3,
+        Treble = 0xE4,
+        BassBoost = 0xE5,
+        SurroundMode = 0xE6,
+        Loudness = 0xE7,
+        MPX = 0xE8,
+        VolumeIncrement = 0xE9,
+        VolumeDecrement = 0xEA,
+        SpeedSelect = 0xF0,
+        PlaybackSpeed = 0xF1,
+        StandardPlay = 0xF2,
+        LongPlay = 0xF3,
+        ExtendedPlay = 0xF4,
+        Slow = 0xF5,
+        FanEnable = 0x100,
+        FanSpeed = 0x101,
+        LightEnable = 0x102,
+        LightIlluminationLevel = 0x103,
+        ClimateControlEnable = 0x104,
+        RoomTemperature = 0x105,
+        SecurityEnable = 0x106,
+        FireAlarm = 0x107,
+        PoliceAlarm = 0x108,
+        Proximity = 0x109,
+        Motion = 0x10A,
+        DuressAlarm = 0x10B,
+        HoldupAlarm = 0x10C,
+        MedicalAlarm = 0x10D,
+        BalanceRight = 0x150,
+        BalanceLeft = 0x151,
+        BassIncrement = 0x152,
+        BassDecrement = 0x153,
+        TrebleIncrement = 0x154,
+        TrebleDecrement = 0x155,
+        SubChannel = 0x170,
+        SubChannelIncrement = 0x171,
+        SubChannelDecrement = 0x172,
+        AlternateAudioIncrement = 0x173,
+        AlternateAudioDecrement = 0x174,
+        ALLaunchButtonConfigurationTool = 0x181,
+        ALProgrammableButtonConfiguration = 0x182,
+        ALConsumerControlConfiguration = 0x183,
+        ALWordProcessor = 0x184,
+        ALTextEditor = 0x185,
+        ALSpreadsheet = 0x186,
+        ALGraphicsEditor = 0x187,
+        ALPresentationApp = 0x188,
+        ALDatabaseApp = 0x189,
+        ALEmailReader = 0x18A,
+        ALNewsreader = 0x18B,
+        ALVoicemail = 0x18C,
+        ALContactsAddressBook = 0x18D,
+        ALCalendarSchedule = 0x18E,
+        ALTaskProjectManager = 0x18F,
+        ALLogJournalTimecard = 0x190,
+        ALCheckbookFinance = 0x191,
+        ALCalculator = 0x192,
+        ALAvCapturePlayback = 0x193,
+        ALLocalMachineBrowser = 0x194,
+        ALLanWanBrowser = 0x195,
+        ALInternetBrowser = 0x196,
+        ALRemoteNetworkingISPConnect = 0x197,
+        ALNetworkConference = 0x198,
+        ALNetworkChat = 0x199,
+        ALTelephonyDialer = 0x19A,
+        ALLogon = 0x19B,
+        ALLogoff = 0x19C,
+        ALLogonLogoff = 0x19D,
+        ALTerminalLockScreensaver = 0x19E,
+        ALControlPanel = 0x19F,
+        ALCommandLineProcessorRun = 0x1A0,
+        ALProcessTaskManager = 0x1A1,
+        ALSelectTaskApplication = 0x1A2,
+        ALNextTaskApplication = 0x1A3,
+        ALPreviousTaskApplication = 0x1A4,
+        ALPreemptiveHaltTaskApplication = 0x1A5,
+        ALIntegratedHelpCenter = 0x1A6,
+        ALDocuments = 0x1A7,
+        ALThesaurus = 0x1A8,
+        ALDictionary = 0x1A9,
+        ALDesktop = 0x1AA,
+        ALSpellCheck = 0x1AB,
+        ALGrammarCheck = 0x1AC,
+        ALWirelessStatus = 0x1AD,
+        ALKeyboardLayout = 0x1AE,
+        ALVirusProtection = 0x1AF,
+        ALEncryption = 0x1B0,
+        ALScreenSaver = 0x1B1,
+        ALAlarms = 0x1B2,
+        ALClock = 0x1B3,
+        ALFileBrowser = 0x1B4,
+        ALPowerStatus = 0x1B5,
+        ALImageBrowser = 0x1B6,
+        ALAudioBrowser = 0x1B7,
+        ALMovieBrowser = 0x1B8,
+        ALDigitalRightsManager = 0x1B9,
+        ALDigitalWallet = 0x1BA,
+        ALInstantMessaging = 0x1BC,
+        ALOemFeaturesTipsTutorialBrowser = 0x1BD,
+        ALOemHelp = 0x1BE,
+        ALOnlineCommunity = 0x1BF,
+        ALEntertainmentContentBrowser = 0x1C0,
+        ALOnlineShoppingBrowser = 0x1C1,
+        ALSmartCardInformationHelp = 0x1C2,
+        ALMarketMonitorFinanceBrowser = 0x1C3,
+        ALCustomizedCorporateNewsBrowser = 0x1C4,
+        ALOnlineActivityBrowser = 0x1C5,
+        ALResearchSearchBrowser = 0x1C6,
+        ALAudioPlayer = 0x1C7,
+        ACNew = 0x201,
+        ACOpen = 0x202,
+        ACClose = 0x203,
+        ACExit = 0x204,
+        ACMaximize = 0x205,
+        ACMinimize = 0x206,
+        ACSave = 0x207,
+        ACPrint = 0x208,
+        ACProperties = 0x209,
+        ACUndo = 0x21A,
+        ACCopy = 0x21B,
+        ACCut = 0x21C,
+        ACPaste = 0x21D,
+        ACSelectAll = 0x21E,
+        ACFind = 0x21F,
+        ACFindAndReplace = 0x220,
+        ACSearch = 0x221,
+        ACGoTo = 0x222,
+        ACHome = 0x223,
+        ACBack = 0x224,
+        ACForward = 0x225,
+        ACStop = 0x226,
+        ACRefresh = 0x227,
+        ACPreviousLink = 0x228,
+        ACNextLink = 0x229,
+        ACBookmarks = 0x22A,
+        ACHistory = 0x22B,
+        ACSubscriptions = 0x22C,
+        ACZoomIn = 0x22D,
+        ACZoomOut = 0x22E,
+        ACZoom = 0x22F,
+        ACFullScreenView = 0x230,
+        ACNormalView = 0x231,
+        ACViewToggle = 0x232,
+        ACScrollUp = 0x233,
+        ACScrollDown = 0x234,
+        ACScroll = 0x235,
+        ACPanLeft = 0x236,
+        ACPanRight = 0x237,
+        ACPan = 0x238,
+        ACNewWindow = 0x239,
+        ACTileHorizontally = 0x23A,
+        ACTileVertically = 0x23B,
+        ACFormat = 0x23C,
+        ACEdit = 0x23D,
+        ACBold = 0x23E,
+        ACItalics = 0x23F,
+        ACUnderline = 0x240,
+        ACStrikethrough = 0x241,
+        ACSubscript = 0x242,
+        ACSuperscript = 0x243,
+        ACAllCaps = 0x244,
+        ACRotate = 0x245,
+        ACResize = 0x246,
+        ACFlipHorizontal = 0x247,
+        ACFlipVertical = 0x248,
+        ACMirrorHorizontal = 0x249,
+        ACMirrorVertical = 0x24A,
+        ACFontSelect = 0x24B,
+        ACFontColor = 0x24C,
+        ACFontSize = 0x24D,
+        ACJustifyLeft = 0x24E,
+        ACJustifyCenterH = 0x24F,
+        ACJustifyRight = 0x250,
+        ACJustifyBlockH = 0x251,
+        ACJustifyTop = 0x252,
+        ACJustifyCenterV = 0x253,
+        ACJustifyBottom = 0x254,
+        ACJustifyBlockV = 0x255,
+        ACIndentDecrease = 0x256,
+        ACIndentIncrease = 0x257,
+        ACNumberedList = 0x258,
+        ACRestartNumbering = 0x259,
+        ACBulletedList = 0x25A,
+        ACPromote = 0x25B,
+        ACDemote = 0x25C,
+        ACYes = 0x25D,
+        ACNo = 0x25E,
+        ACCancel = 0x25F,
+        ACCatalog = 0x260,
+        ACBuyCheckout = 0x261,
+        ACAddToCart = 0x262,
+        ACExpand = 0x263,
+        ACExpandAll = 0x264,
+        ACCollapse = 0x265,
+        ACCollapseAll = 0x266,
+        ACPrintPreview = 0x267,
+        ACPasteSpecial = 0x268,
+        ACInsertMode = 0x269,
+        ACDelete = 0x26A,
+        ACLock = 0x26B,
+        ACUnlock = 0x26C,
+        ACProtect = 0x26D,
+        ACUnprotect = 0x26E,
+        ACAttachComment = 0x26F,
+        ACDeleteComment = 0x270,
+        ACViewComment = 0x271,
+        ACSelectWord = 0x272,
+        ACSelectSentence = 0x273,
+        ACSelectParagraph = 0x274,
+        ACSelectColumn = 0x275,
+        ACSelectRow = 0x276,
+        ACSelectTable = 0x277,
+        ACSelectObject = 0x278,
+        ACRedoRepeat = 0x279,
+        ACSort = 0x27A,
+        ACSortAscending = 0x27B,
+        ACSortDescending = 0x27C,
+        ACFilter = 0x27D,
+        ACSetClock = 0x27E,
+        ACViewClock = 0x27F,
+        ACSelectTimeZone = 0x280,
+        ACEditTimeZones = 0x281,
+        ACSetAlarm = 0x282,
+        ACClearAlarm = 0x283,
+        ACSnoozeAlarm = 0x284,
+        ACResetAlarm = 0x285,
+        ACSynchronize = 0x286,
+        ACSendReceive = 0x287,
+        ACSendTo = 0x288,
+        ACReply = 0x289,
+        ACReplyAll = 0x28A,
+        ACForwardMsg = 0x28B,
+        ACSend = 0x28C,
+        ACAttachFile = 0x28D,
+        ACUpload = 0x28E,
+        ACDownloadSaveTargetAs = 0x28F,
+        ACSetBorders = 0x290,
+        ACInsertRow = 0x291,
+        ACInsertColumn = 0x292,
+        ACInsertFile = 0x293,
+        ACInsertPicture = 0x294,
+        ACInsertObject = 0x295,
+        ACInsertSymbol = 0x296,
+        ACSaveAndClose = 0x297,
+        ACRename = 0x298,
+        ACMerge = 0x299,
+        ACSplit = 0x29A,
+        ACDistributeHorizontally = 0x29B,
+        ACDistributeVertically = 0x29C,
+    }
 }
 
-#[derive(Copy, Clone, PartialEq)]
-pub enum KeyboardAction {
-    None,
-    Layer0Momentary,
-    Layer0Set,
-    Layer1Momentary,
-    Layer1Set,
-    Layer2Momentary,
-    Layer2Set,
-    Layer3Momentary,
-    Layer3Set,
-    WasdModeOn,
-    WasdModeOff,
-    WasdModeToggle,
-    Transparent,
-    MouseLeftButton,
-    MouseRightButton,
-    MouseScrollButton,
-    JoystickButton,
+named_enum! {
+    #[repr(u8)]
+    #[derive(Copy, Clone, PartialEq)]
+    pub enum KeyboardAction {
+        None,
+        Layer0Momentary,
+        Layer0Set,
+        Layer1Momentary,
+        Layer1Set,
+        Layer2Momentary,
+        Layer2Set,
+        Layer3Momentary,
+        Layer3Set,
+        WasdModeOn,
+        WasdModeOff,
+        WasdModeToggle,
+        Transparent,
+        MouseLeftButton,
+        MouseRightButton,
+        MouseScrollButton,
+        JoystickButton,
+        CalibrateJoystick,
+    }
 }
 
-#[repr(u8)]
-#[derive(Copy, Clone, PartialEq, PartialOrd)]
-pub enum Keyboard {
-    NoEventIndicated = 0x00,
-    RolloverError = 0x01,
-    POSTFail = 0x02,
-    ErrorUndefined = 0x03,
-    A = 0x04,
-    B = 0x05,
-    C = 0x06,
-    D = 0x07,
-    E = 0x08,
-    F = 0x09,
-    G = 0x0A,
-    H = 0x0B,
-    I = 0x0C,
-    J = 0x0D,
-    K = 0x0E,
-    L = 0x0F,
-    M = 0x10,
-    N = 0x11,
-    O = 0x12,
-    P = 0x13,
-    Q = 0x14,
-    R = 0x15,
-    S = 0x16,
-    T = 0x17,
-    U = 0x18,
-    V = 0x19,
-    W = 0x1A,
-    X = 0x1B,
-    Y = 0x1C,
-    Z = 0x1D,
-    Number1 = 0x1E,
-    Number2 = 0x1F,
-    Number3 = 0x20,
-    Number4 = 0x21,
-    Number5 = 0x22,
-    Number6 = 0x23,
-    Number7 = 0x24,
-    Number8 = 0x25,
-    Number9 = 0x26,
-    Number0 = 0x27,
-    Return = 0x28,
-    Escape = 0x29,
-    Backspace = 0x2A,
-    Tab = 0x2B,
-    Space = 0x2C,
-    Minus = 0x2D,
-    Plus = 0x2E,
-    LeftBracket = 0x2F,
-    RightBracket = 0x30,
-    Backslash = 0x31,
-    Intl = 0x32,
-    Semicolon = 0x33,
-    Quote = 0x34,
-    GraveTilde = 0x35,
-    Comma = 0x36,
-    Period = 0x37,
-    ForwardSlash = 0x38,
-    CapsLock = 0x39,
-    F1 = 0x3A,
-    F2 = 0x3B,
-    F3 = 0x3C,
-    F4 = 0x3D,
-    F5 = 0x3E,
-    F6 = 0x3F,
-    F7 = 0x40,
-    F8 = 0x41,
-    F9 = 0x42,
-    F10 = 0x43,
-    F11 = 0x44,
-    F12 = 0x45,
-    PrintScreen = 0x46,
-    ScrollLock = 0x47,
-    Pause = 0x48,
-    Insert = 0x49,
-    Home = 0x4A,
-    PageUp = 0x4B,
-    Delete = 0x4C,
-    End = 0x4D,
-    PageDown = 0x4E,
-    RightArrow = 0x4F,
-    LeftArrow = 0x50,
-    DownArrow = 0x51,
-    UpArrow = 0x52,
-    KeypadNumLockAndClear = 0x53,
-    KeypadDivide = 0x54,
-    KeypadMultiply = 0x55,
-    KeypadSubtract = 0x56,
-    KeypadAdd = 0x57,
-    KeypadEnter = 0x58,
-    Keypad1 = 0x59,
-    Keypad2 = 0x5A,
-    Keypad3 = 0x5B,
-    Keypad4 = 0x5C,
-    Keypad5 = 0x5D,
-    Keypad6 = 0x5E,
-    Keypad7 = 0x5F,
-    Keypad8 = 0x60,
-    Keypad9 = 0x61,
-    Keypad0 = 0x62,
-    KeypadDot = 0x63,
-    Intl2 = 0x64,
-    Application = 0x65,
-    Power = 0x66,
-    KeypadEqual = 0x67,
-    F13 = 0x68,
-    F14 = 0x69,
-    F15 = 0x6A,
-    F16 = 0x6B,
-    F17 = 0x6C,
-    F18 = 0x6D,
-    F19 = 0x6E,
-    F20 = 0x6F,
-    F21 = 0x70,
-    F22 = 0x71,
-    F23 = 0x72,
-    F24 = 0x73,
-    Execute = 0x74,
-    Help = 0x75,
-    Menu = 0x76,
-    Select = 0x77,
-    Stop = 0x78,
-    Again = 0x79,
-    Undo = 0x7A,
-    Cut = 0x7B,
-    Copy = 0x7C,
-    Paste = 0x7D,
-    Find = 0x7E,
-    Mute = 0x7F,
-    VolumeUp = 0x80,
-    VolumeDown = 0x81,
-    LockingCapsLock = 0x82,
-    LockingNumLock = 0x83,
-    LockingScrollLock = 0x84,
-    KeypadComma = 0x85,
-    KeypadEqualsSign = 0x86,
-    Ro = 0x87,
-    Kana = 0x88,
-    Yen = 0x89,
-    Henkan = 0x8A,
-    Muhenkan = 0x8B,
-    Int6 = 0x8C,
-    Int7 = 0x8D,
-    Int8 = 0x8E,
-    Int9 = 0x8F,
-    Lang1 = 0x90,
-    Lang2 = 0x91,
-    Lang3 = 0x92,
-    Lang4 = 0x93,
-    Lang5 = 0x94,
-    Lang6 = 0x95,
-    Lang7 = 0x96,
-    Lang8 = 0x97,
-    Lang9 = 0x98,
-    AltErase = 0x99,
-    SysReq = 0x9A,
-    Cancel = 0x9B,
-    Clear = 0x9C,
-    Prior = 0x9D,
-    Return2 = 0x9E,
-    Separator = 0x9F,
-    Out = 0xA0,
-    Oper = 0xA1,
-    ClearAgain = 0xA2,
-    ClSelProps = 0xA3,
-    ExSel = 0xA4,
-    Keypad00 = 0xB0,
-    Keypad000 = 0xB1,
-    ThousandsSep = 0xB2,
-    DecimalSep = 0xB3,
-    CurrencyUnit = 0xB4,
-    CurrencySubUnit = 0xB5,
-    KeypadLeftParen = 0xB6,
-    KeypadRightParen = 0xB7,
-    KeypadLeftBracket = 0xB8,
-    KeypadRightBracket = 0xB9,
-    KeypadTab = 0xBA,
-    KeypadBackspace = 0xBB,
-    KeypadA = 0xBC,
-    KeypadB = 0xBD,
-    KeypadC = 0xBE,
-    KeypadD = 0xBF,
-    KeypadE = 0xC0,
-    KeypadF = 0xC1,
-    KeypadXOR = 0xC2,
-    KeypadCaret = 0xC3,
-    KeypadPercent = 0xC4,
-    KeypadLeftAngleBracket = 0xC5,
-    KeypadRightAngleBracket = 0xC6,
-    KeypadBitwiseAnd = 0xC7,
-    KeypadAnd = 0xC8,
-    KeypadBitwiseOr = 0xC9,
-    KeypadOr = 0xCA,
-    KeypadColon = 0xCB,
-    KeypadPound = 0xCC,
-    KeypadSpace = 0xCD,
-    KeypadAt = 0xCE,
-    KeypadExclamation = 0xCF,
-    KeypadMemStore = 0xD0,
-    KeypadMemRecall = 0xD1,
-    KeypadMemClear = 0xD2,
-    KeypadMemAdd = 0xD3,
-    KeypadMemSubstract = 0xD4,
-    KeypadMemMultiply = 0xD5,
-    KeypadMemDivide = 0xD6,
-    KeypadSign = 0xD7,
-    KeypadClear = 0xD8,
-    KeypadClearEntry = 0xD9,
-    KeypadBinary = 0xDA,
-    KeypadOctal = 0xDB,
-    KeypadDecimal = 0xDC,
-    KeypadHexadecimal = 0xDD,
+named_enum! {
+    #[repr(u8)]
+    #[derive(Copy, Clone, PartialEq, PartialOrd)]
+    pub enum Keyboard {
+        NoEventIndicated = 0x00,
+        RolloverError = 0x01,
+        POSTFail = 0x02,
+        ErrorUndefined = 0x03,
+        A = 0x04,
+        B = 0x05,
+        C = 0x06,
+        D = 0x07,
+        E = 0x08,
+        F = 0x09,
+        G = 0x0A,
+        H = 0x0B,
+        I = 0x0C,
+        J = 0x0D,
+        K = 0x0E,
+        L = 0x0F,
+        M = 0x10,
+        N = 0x11,
+        O = 0x12,
+        P = 0x13,
+        Q = 0x14,
+        R = 0x15,
+        S = 0x16,
+        T = 0x17,
+        U = 0x18,
+        V = 0x19,
+        W = 0x1A,
+        X = 0x1B,
+        Y = 0x1C,
+        Z = 0x1D,
+        Number1 = 0x1E,
+        Number2 = 0x1F,
+        Number3 = 0x20,
+        Number4 = 0x21,
+        Number5 = 0x22,
+        Number6 = 0x23,
+        Number7 = 0x24,
+        Number8 = 0x25,
+        Number9 = 0x26,
+        Number0 = 0x27,
+        Return = 0x28,
+        Escape = 0x29,
+        Backspace = 0x2A,
+        Tab = 0x2B,
+        Space = 0x2C,
+        Minus = 0x2D,
+        Plus = 0x2E,
+        LeftBracket = 0x2F,
+        RightBracket = 0x30,
+        Backslash = 0x31,
+        Intl = 0x32,
+        Semicolon = 0x33,
+        Quote = 0x34,
+        GraveTilde = 0x35,
+        Comma = 0x36,
+        Period = 0x37,
+        ForwardSlash = 0x38,
+        CapsLock = 0x39,
+        F1 = 0x3A,
+        F2 = 0x3B,
+        F3 = 0x3C,
+        F4 = 0x3D,
+        F5 = 0x3E,
+        F6 = 0x3F,
+        F7 = 0x40,
+        F8 = 0x41,
+        F9 = 0x42,
+        F10 = 0x43,
+        F11 = 0x44,
+        F12 = 0x45,
+        PrintScreen = 0x46,
+        ScrollLock = 0x47,
+        Pause = 0x48,
+        Insert = 0x49,
+        Home = 0x4A,
+        PageUp = 0x4B,
+        Delete = 0x4C,
+        End = 0x4D,
+        PageDown = 0x4E,
+        RightArrow = 0x4F,
+        LeftArrow = 0x50,
+        DownArrow = 0x51,
+        UpArrow = 0x52,
+        KeypadNumLockAndClear = 0x53,
+        KeypadDivide = 0x54,
+        KeypadMultiply = 0x55,
+        KeypadSubtract = 0x56,
+        KeypadAdd = 0x57,
+        KeypadEnter = 0x58,
+        Keypad1 = 0x59,
+        Keypad2 = 0x5A,
+        Keypad3 = 0x5B,
+        Keypad4 = 0x5C,
+        Keypad5 = 0x5D,
+        Keypad6 = 0x5E,
+        Keypad7 = 0x5F,
+        Keypad8 = 0x60,
+        Keypad9 = 0x61,
+        Keypad0 = 0x62,
+        KeypadDot = 0x63,
+        Intl2 = 0x64,
+        Application = 0x65,
+        Power = 0x66,
+        KeypadEqual = 0x67,
+        F13 = 0x68,
+        F14 = 0x69,
+        F15 = 0x6A,
+        F16 = 0x6B,
+        F17 = 0x6C,
+        F18 = 0x6D,
+        F19 = 0x6E,
+        F20 = 0x6F,
+        F21 = 0x70,
+        F22 = 0x71,
+        F23 = 0x72,
+        F24 = 0x73,
+        Execute = 0x74,
+        Help = 0x75,
+        Menu = 0x76,
+        Select = 0x77,
+        Stop = 0x78,
+        Again = 0x79,
+        Undo = 0x7A,
+        Cut = 0x7B,
+        Copy = 0x7C,
+        Paste = 0x7D,
+        Find = 0x7E,
+        Mute = 0x7F,
+        VolumeUp = 0x80,
+        VolumeDown = 0x81,
+        LockingCapsLock = 0x82,
+        LockingNumLock = 0x83,
+        LockingScrollLock = 0x84,
+        KeypadComma = 0x85,
+        KeypadEqualsSign = 0x86,
+        Ro = 0x87,
+        Kana = 0x88,
+        Yen = 0x89,
+        Henkan = 0x8A,
+        Muhenkan = 0x8B,
+        Int6 = 0x8C,
+        Int7 = 0x8D,
+        Int8 = 0x8E,
+        Int9 = 0x8F,
+        Lang1 = 0x90,
+        Lang2 = 0x91,
+        Lang3 = 0x92,
+        Lang4 = 0x93,
+        Lang5 = 0x94,
+        Lang6 = 0x95,
+        Lang7 = 0x96,
+        Lang8 = 0x97,
+        Lang9 = 0x98,
+        AltErase = 0x99,
+        SysReq = 0x9A,
+        Cancel = 0x9B,
+        Clear = 0x9C,
+        Prior = 0x9D,
+        Return2 = 0x9E,
+        Separator = 0x9F,
+        Out = 0xA0,
+        Oper = 0xA1,
+        ClearAgain = 0xA2,
+        ClSelProps = 0xA3,
+        ExSel = 0xA4,
+        Keypad00 = 0xB0,
+        Keypad000 = 0xB1,
+        ThousandsSep = 0xB2,
+        DecimalSep = 0xB3,
+        CurrencyUnit = 0xB4,
+        CurrencySubUnit = 0xB5,
+        KeypadLeftParen = 0xB6,
+        KeypadRightParen = 0xB7,
+        KeypadLeftBracket = 0xB8,
+        KeypadRightBracket = 0xB9,
+        KeypadTab = 0xBA,
+        KeypadBackspace = 0xBB,
+        KeypadA = 0xBC,
+        KeypadB = 0xBD,
+        KeypadC = 0xBE,
+        KeypadD = 0xBF,
+        KeypadE = 0xC0,
+        KeypadF = 0xC1,
+        KeypadXOR = 0xC2,
+        KeypadCaret = 0xC3,
+        KeypadPercent = 0xC4,
+        KeypadLeftAngleBracket = 0xC5,
+        KeypadRightAngleBracket = 0xC6,
+        KeypadBitwiseAnd = 0xC7,
+        KeypadAnd = 0xC8,
+        KeypadBitwiseOr = 0xC9,
+        KeypadOr = 0xCA,
+        KeypadColon = 0xCB,
+        KeypadPound = 0xCC,
+        KeypadSpace = 0xCD,
+        KeypadAt = 0xCE,
+        KeypadExclamation = 0xCF,
+        KeypadMemStore = 0xD0,
+        KeypadMemRecall = 0xD1,
+        KeypadMemClear = 0xD2,
+        KeypadMemAdd = 0xD3,
+        KeypadMemSubstract = 0xD4,
+        KeypadMemMultiply = 0xD5,
+        KeypadMemDivide = 0xD6,
+        KeypadSign = 0xD7,
+        KeypadClear = 0xD8,
+        KeypadClearEntry = 0xD9,
+        KeypadBinary = 0xDA,
+        KeypadOctal = 0xDB,
+        KeypadDecimal = 0xDC,
+        KeypadHexadecimal = 0xDD,
+        LeftControl = 0xE0,
+        LeftShift = 0xE1,
+        LeftAlt = 0xE2,
+        LeftGUI = 0xE3,
+        RightControl = 0xE4,
+        RightShift = 0xE5,
+        RightAlt = 0xE6,
+        RightGUI = 0xE7,
+    }
+}
+
+impl Consumer {
+    /// QMK names for the media keys
+    pub const ALIASES: &'static [(&'static str, Consumer)] = &[
+        ("KC_AUDIO_MUTE", Consumer::Mute),
+        ("KC_MUTE", Consumer::Mute),
+        ("KC_AUDIO_VOL_UP", Consumer::VolumeIncrement),
+        ("KC_VOLU", Consumer::VolumeIncrement),
+        ("KC_AUDIO_VOL_DOWN", Consumer::VolumeDecrement),
+        ("KC_VOLD", Consumer::VolumeDecrement),
+        ("KC_MEDIA_NEXT_TRACK", Consumer::ScanNextTrack),
+        ("KC_MNXT", Consumer::ScanNextTrack),
+        ("KC_MEDIA_PREV_TRACK", Consumer::ScanPreviousTrack),
+        ("KC_MPRV", Consumer::ScanPreviousTrack),
+        ("KC_MEDIA_STOP", Consumer::Stop),
+        ("KC_MSTP", Consumer::Stop),
+        ("KC_MEDIA_PLAY_PAUSE", Consumer::PlayPause),
+        ("KC_MPLY", Consumer::PlayPause),
+        ("KC_MEDIA_SELECT", Consumer::ALConsumerControlConfiguration),
+        ("KC_MSEL", Consumer::ALConsumerControlConfiguration),
+        ("KC_MEDIA_EJECT", Consumer::Eject),
+        ("KC_EJCT", Consumer::Eject),
+        ("KC_MAIL", Consumer::ALEmailReader),
+        ("KC_CALCULATOR", Consumer::ALCalculator),
+        ("KC_CALC", Consumer::ALCalculator),
+        ("KC_MY_COMPUTER", Consumer::ALLocalMachineBrowser),
+        ("KC_MYCM", Consumer::ALLocalMachineBrowser),
+        ("KC_WWW_SEARCH", Consumer::ACSearch),
+        ("KC_WSCH", Consumer::ACSearch),
+        ("KC_WWW_HOME", Consumer::ACHome),
+        ("KC_WHOM", Consumer::ACHome),
+        ("KC_WWW_BACK", Consumer::ACBack),
+        ("KC_WBAK", Consumer::ACBack),
+        ("KC_WWW_FORWARD", Consumer::ACForward),
+        ("KC_WFWD", Consumer::ACForward),
+        ("KC_WWW_STOP", Consumer::ACStop),
+        ("KC_WSTP", Consumer::ACStop),
+        ("KC_WWW_REFRESH", Consumer::ACRefresh),
+        ("KC_WREF", Consumer::ACRefresh),
+        ("KC_WWW_FAVORITES", Consumer::ACBookmarks),
+        ("KC_WFAV", Consumer::ACBookmarks),
+        ("KC_MEDIA_FAST_FORWARD", Consumer::FastForward),
+        ("KC_MFFD", Consumer::FastForward),
+        ("KC_MEDIA_REWIND", Consumer::Rewind),
+        ("KC_MRWD", Consumer::Rewind),
+        ("KC_CONTROL_PANEL", Consumer::ALControlPanel),
+        ("KC_CPNL", Consumer::ALControlPanel),
+    ];
+}
+
+impl KeyboardAction {
+    /// QMK names for the actions QMK has an equivalent of
+    pub const ALIASES: &'static [(&'static str, KeyboardAction)] = &[
+        ("KC_TRANSPARENT", KeyboardAction::Transparent),
+        ("KC_TRNS", KeyboardAction::Transparent),
+        ("_______", KeyboardAction::Transparent),
+        ("MS_BTN1", KeyboardAction::MouseLeftButton),
+        ("KC_MS_BTN1", KeyboardAction::MouseLeftButton),
+        ("KC_BTN1", KeyboardAction::MouseLeftButton),
+        ("MS_BTN2", KeyboardAction::MouseRightButton),
+        ("KC_MS_BTN2", KeyboardAction::MouseRightButton),
+        ("KC_BTN2", KeyboardAction::MouseRightButton),
+        ("MS_BTN3", KeyboardAction::MouseScrollButton),
+        ("KC_MS_BTN3", KeyboardAction::MouseScrollButton),
+        ("KC_BTN3", KeyboardAction::MouseScrollButton),
+        ("JS_0", KeyboardAction::JoystickButton),
+    ];
+}
+
+impl Keyboard {
+    /// QMK names, both the full and the short forms
+    pub const ALIASES: &'static [(&'static str, Keyboard)] = &[
+        ("KC_NO", Keyboard::NoEventIndicated),
+        ("XXXXXXX", Keyboard::NoEventIndicated),
+        ("KC_A", Keyboard::A),
+        ("KC_B", Keyboard::B),
+        ("KC_C", Keyboard::C),
+        ("KC_D", Keyboard::D),
+        ("KC_E", Keyboard::E),
+        ("KC_F", Keyboard::F),
+        ("KC_G", Keyboard::G),
+        ("KC_H", Keyboard::H),
+        ("KC_I", Keyboard::I),
+        ("KC_J", Keyboard::J),
+        ("KC_K", Keyboard::K),
+        ("KC_L", Keyboard::L),
+        ("KC_M", Keyboard::M),
+        ("KC_N", Keyboard::N),
+        ("KC_O", Keyboard::O),
+        ("KC_P", Keyboard::P),
+        ("KC_Q", Keyboard::Q),
+        ("KC_R", Keyboard::R),
+        ("KC_S", Keyboard::S),
+        ("KC_T", Keyboard::T),
+        ("KC_U", Keyboard::U),
+        ("KC_V", Keyboard::V),
+        ("KC_W", Keyboard::W),
+        ("KC_X", Keyboard::X),
+        ("KC_Y", Keyboard::Y),
+        ("KC_Z", Keyboard::Z),
+        ("KC_1", Keyboard::Number1),
+        ("KC_2", Keyboard::Number2),
+        ("KC_3", Keyboard::Number3),
+        ("KC_4", Keyboard::Number4),
+        ("KC_5", Keyboard::Number5),
+        ("KC_6", Keyboard::Number6),
+        ("KC_7", Keyboard::Number7),
+        ("KC_8", Keyboard::Number8),
+        ("KC_9", Keyboard::Number9),
+        ("KC_0", Keyboard::Number0),
+        ("KC_ENTER", Keyboard::Return),
+        ("KC_ENT", Keyboard::Return),
+        ("KC_ESCAPE", Keyboard::Escape),
+        ("KC_ESC", Keyboard::Escape),
+        ("KC_BACKSPACE", Keyboard::Backspace),
+        ("KC_BSPC", Keyboard::Backspace),
+        ("KC_TAB", Keyboard::Tab),
+        ("KC_SPACE", Keyboard::Space),
+        ("KC_SPC", Keyboard::Space),
+        ("KC_MINUS", Keyboard::Minus),
+        ("KC_MINS", Keyboard::Minus),
+        ("KC_EQUAL", Keyboard::Plus),
+        ("KC_EQL", Keyboard::Plus),
+        ("KC_LEFT_BRACKET", Keyboard::LeftBracket),
+        ("KC_LBRC", Keyboard::LeftBracket),
+        ("KC_RIGHT_BRACKET", Keyboard::RightBracket),
+        ("KC_RBRC", Keyboard::RightBracket),
+        ("KC_BACKSLASH", Keyboard::Backslash),
+        ("KC_BSLS", Keyboard::Backslash),
+        ("KC_NONUS_HASH", Keyboard::Intl),
+        ("KC_NUHS", Keyboard::Intl),
+        ("KC_SEMICOLON", Keyboard::Semicolon),
+        ("KC_SCLN", Keyboard::Semicolon),
+        ("KC_QUOTE", Keyboard::Quote),
+        ("KC_QUOT", Keyboard::Quote),
+        ("KC_GRAVE", Keyboard::GraveTilde),
+        ("KC_GRV", Keyboard::GraveTilde),
+        ("KC_COMMA", Keyboard::Comma),
+        ("KC_COMM", Keyboard::Comma),
+        ("KC_DOT", Keyboard::Period),
+        ("KC_SLASH", Keyboard::ForwardSlash),
+        ("KC_SLSH", Keyboard::ForwardSlash),
+        ("KC_CAPS_LOCK", Keyboard::CapsLock),
+        ("KC_CAPS", Keyboard::CapsLock),
+        ("KC_F1", Keyboard::F1),
+        ("KC_F2", Keyboard::F2),
+        ("KC_F3", Keyboard::F3),
+        ("KC_F4", Keyboard::F4),
+        ("KC_F5", Keyboard::F5),
+        ("KC_F6", Keyboard::F6),
+        ("KC_F7", Keyboard::F7),
+        ("KC_F8", Keyboard::F8),
+        ("KC_F9", Keyboard::F9),
+        ("KC_F10", Keyboard::F10),
+        ("KC_F11", Keyboard::F11),
+        ("KC_F12", Keyboard::F12),
+        ("KC_F13", Keyboard::F13),
+        ("KC_F14", Keyboard::F14),
+        ("KC_F15", Keyboard::F15),
+        ("KC_F16", Keyboard::F16),
+        ("KC_F17", Keyboard::F17),
+        ("KC_F18", Keyboard::F18),
+        ("KC_F19", Keyboard::F19),
+        ("KC_F20", Keyboard::F20),
+        ("KC_F21", Keyboard::F21),
+        ("KC_F22", Keyboard::F22),
+        ("KC_F23", Keyboard::F23),
+        ("KC_F24", Keyboard::F24),
+        ("KC_PRINT_SCREEN", Keyboard::PrintScreen),
+        ("KC_PSCR", Keyboard::PrintScreen),
+        ("KC_SCROLL_LOCK", Keyboard::ScrollLock),
+        ("KC_SCRL", Keyboard::ScrollLock),
+        ("KC_PAUSE", Keyboard::Pause),
+        ("KC_PAUS", Keyboard::Pause),
+        ("KC_INSERT", Keyboard::Insert),
+        ("KC_INS", Keyboard::Insert),
+        ("KC_HOME", Keyboard::Home),
+        ("KC_PAGE_UP", Keyboard::PageUp),
+        ("KC_PGUP", Keyboard::PageUp),
+        ("KC_DELETE", Keyboard::Delete),
+        ("KC_DEL", Keyboard::Delete),
+        ("KC_END", Keyboard::End),
+        ("KC_PAGE_DOWN", Keyboard::PageDown),
+        ("KC_PGDN", Keyboard::PageDown),
+        ("KC_RIGHT", Keyboard::RightArrow),
+        ("KC_RGHT", Keyboard::RightArrow),
+        ("KC_LEFT", Keyboard::LeftArrow),
+        ("KC_DOWN", Keyboard::DownArrow),
+        ("KC_UP", Keyboard::UpArrow),
+        ("KC_NUM_LOCK", Keyboard::KeypadNumLockAndClear),
+        ("KC_NUM", Keyboard::KeypadNumLockAndClear),
+        ("KC_KP_SLASH", Keyboard::KeypadDivide),
+        ("KC_PSLS", Keyboard::KeypadDivide),
+        ("KC_KP_ASTERISK", Keyboard::KeypadMultiply),
+        ("KC_PAST", Keyboard::KeypadMultiply),
+        ("KC_KP_MINUS", Keyboard::KeypadSubtract),
+        ("KC_PMNS", Keyboard::KeypadSubtract),
+        ("KC_KP_PLUS", Keyboard::KeypadAdd),
+        ("KC_PPLS", Keyboard::KeypadAdd),
+        ("KC_KP_ENTER", Keyboard::KeypadEnter),
+        ("KC_PENT", Keyboard::KeypadEnter),
+        ("KC_KP_1", Keyboard::Keypad1),
+        ("KC_P1", Keyboard::Keypad1),
+        ("KC_KP_2", Keyboard::Keypad2),
+        ("KC_P2", Keyboard::Keypad2),
+        ("KC_KP_3", Keyboard::Keypad3),
+        ("KC_P3", Keyboard::Keypad3),
+        ("KC_KP_4", Keyboard::Keypad4),
+        ("KC_P4", Keyboard::Keypad4),
+        ("KC_KP_5", Keyboard::Keypad5),
+        ("KC_P5", Keyboard::Keypad5),
+        ("KC_KP_6", Keyboard::Keypad6),
+        ("KC_P6", Keyboard::Keypad6),
+        ("KC_KP_7", Keyboard::Keypad7),
+        ("KC_P7", Keyboard::Keypad7),
+        ("KC_KP_8", Keyboard::Keypad8),
+        ("KC_P8", Keyboard::Keypad8),
+        ("KC_KP_9", Keyboard::Keypad9),
+        ("KC_P9", Keyboard::Keypad9),
+        ("KC_KP_0", Keyboard::Keypad0),
+        ("KC_P0", Keyboard::Keypad0),
+        ("KC_KP_DOT", Keyboard::KeypadDot),
+        ("KC_PDOT", Keyboard::KeypadDot),
+        ("KC_NONUS_BACKSLASH", Keyboard::Intl2),
+        ("KC_NUBS", Keyboard::Intl2),
+        ("KC_APPLICATION", Keyboard::Application),
+        ("KC_APP", Keyboard::Application),
+        ("KC_KB_POWER", Keyboard::Power),
+        ("KC_KP_EQUAL", Keyboard::KeypadEqual),
+        ("KC_PEQL", Keyboard::KeypadEqual),
+        ("KC_EXECUTE", Keyboard::Execute),
+        ("KC_EXEC", Keyboard::Execute),
+        ("KC_HELP", Keyboard::Help),
+        ("KC_MENU", Keyboard::Menu),
+        ("KC_SELECT", Keyboard::Select),
+        ("KC_SLCT", Keyboard::Select),
+        ("KC_STOP", Keyboard::Stop),
+        ("KC_AGAIN", Keyboard::Again),
+        ("KC_AGIN", Keyboard::Again),
+        ("KC_UNDO", Keyboard::Undo),
+        ("KC_CUT", Keyboard::Cut),
+        ("KC_COPY", Keyboard::Copy),
+        ("KC_PASTE", Keyboard::Paste),
+        ("KC_PSTE", Keyboard::Paste),
+        ("KC_FIND", Keyboard::Find),
+        ("KC_KB_MUTE", Keyboard::Mute),
+        ("KC_KB_VOLUME_UP", Keyboard::VolumeUp),
+        ("KC_KB_VOLUME_DOWN", Keyboard::VolumeDown),
+        ("KC_LOCKING_CAPS_LOCK", Keyboard::LockingCapsLock),
+        ("KC_LCAP", Keyboard::LockingCapsLock),
+        ("KC_LOCKING_NUM_LOCK", Keyboard::LockingNumLock),
+        ("KC_LNUM", Keyboard::LockingNumLock),
+        ("KC_LOCKING_SCROLL_LOCK", Keyboard::LockingScrollLock),
+        ("KC_LSCR", Keyboard::LockingScrollLock),
+        ("KC_KP_COMMA", Keyboard::KeypadComma),
+        ("KC_PCMM", Keyboard::KeypadComma),
+        ("KC_KP_EQUAL_AS400", Keyboard::KeypadEqualsSign),
+        ("KC_INTERNATIONAL_1", Keyboard::Ro),
+        ("KC_INT1", Keyboard::Ro),
+        ("KC_INTERNATIONAL_2", Keyboard::Kana),
+        ("KC_INT2", Keyboard::Kana),
+        ("KC_INTERNATIONAL_3", Keyboard::Yen),
+        ("KC_INT3", Keyboard::Yen),
+        ("KC_INTERNATIONAL_4", Keyboard::Henkan),
+        ("KC_INT4", Keyboard::Henkan),
+        ("KC_INTERNATIONAL_5", Keyboard::Muhenkan),
+        ("KC_INT5", Keyboard::Muhenkan),
+        ("KC_INTERNATIONAL_6", Keyboard::Int6),
+        ("KC_INT6", Keyboard::Int6),
+        ("KC_INTERNATIONAL_7", Keyboard::Int7),
+        ("KC_INT7", Keyboard::Int7),
+        ("KC_INTERNATIONAL_8", Keyboard::Int8),
+        ("KC_INT8", Keyboard::Int8),
+        ("KC_INTERNATIONAL_9", Keyboard::Int9),
+        ("KC_INT9", Keyboard::Int9),
+        ("KC_LANGUAGE_1", Keyboard::Lang1),
+        ("KC_LNG1", Keyboard::Lang1),
+        ("KC_LANGUAGE_2", Keyboard::Lang2),
+        ("KC_LNG2", Keyboard::Lang2),
+        ("KC_LANGUAGE_3", Keyboard::Lang3),
+        ("KC_LNG3", Keyboard::Lang3),
+        ("KC_LANGUAGE_4", Keyboard::Lang4),
+        ("KC_LNG4", Keyboard::Lang4),
+        ("KC_LANGUAGE_5", Keyboard::Lang5),
+        ("KC_LNG5", Keyboard::Lang5),
+        ("KC_LANGUAGE_6", Keyboard::Lang6),
+        ("KC_LNG6", Keyboard::Lang6),
+        ("KC_LANGUAGE_7", Keyboard::Lang7),
+        ("KC_LNG7", Keyboard::Lang7),
+        ("KC_LANGUAGE_8", Keyboard::Lang8),
+        ("KC_LNG8", Keyboard::Lang8),
+        ("KC_LANGUAGE_9", Keyboard::Lang9),
+        ("KC_LNG9", Keyboard::Lang9),
+        ("KC_ALTERNATE_ERASE", Keyboard::AltErase),
+        ("KC_ERAS", Keyboard::AltErase),
+        ("KC_SYSTEM_REQUEST", Keyboard::SysReq),
+        ("KC_SYRQ", Keyboard::SysReq),
+        ("KC_CANCEL", Keyboard::Cancel),
+        ("KC_CNCL", Keyboard::Cancel),
+        ("KC_CLEAR", Keyboard::Clear),
+        ("KC_CLR", Keyboard::Clear),
+        ("KC_PRIOR", Keyboard::Prior),
+        ("KC_PRIR", Keyboard::Prior),
+        ("KC_RETURN", Keyboard::Return2),
+        ("KC_RETN", Keyboard::Return2),
+        ("KC_SEPARATOR", Keyboard::Separator),
+        ("KC_SEPR", Keyboard::Separator),
+        ("KC_OUT", Keyboard::Out),
+        ("KC_OPER", Keyboard::Oper),
+        ("KC_CLEAR_AGAIN", Keyboard::ClearAgain),
+        ("KC_CLAG", Keyboard::ClearAgain),
+        ("KC_CRSEL", Keyboard::ClSelProps),
+        ("KC_CRSL", Keyboard::ClSelProps),
+        ("KC_EXSEL", Keyboard::ExSel),
+        ("KC_EXSL", Keyboard::ExSel),
+        ("KC_LEFT_CTRL", Keyboard::LeftControl),
+        ("KC_LCTL", Keyboard::LeftControl),
+        ("KC_LEFT_SHIFT", Keyboard::LeftShift),
+        ("KC_LSFT", Keyboard::LeftShift),
+        ("KC_LEFT_ALT", Keyboard::LeftAlt),
+        ("KC_LALT", Keyboard::LeftAlt),
+        ("KC_LOPT", Keyboard::LeftAlt),
+        ("KC_LEFT_GUI", Keyboard::LeftGUI),
+        ("KC_LGUI", Keyboard::LeftGUI),
+        ("KC_LCMD", Keyboard::LeftGUI),
+        ("KC_LWIN", Keyboard::LeftGUI),
+        ("KC_RIGHT_CTRL", Keyboard::RightControl),
+        ("KC_RCTL", Keyboard::RightControl),
+        ("KC_RIGHT_SHIFT", Keyboard::RightShift),
+        ("KC_RSFT", Keyboard::RightShift),
+        ("KC_RIGHT_ALT", Keyboard::RightAlt),
+        ("KC_RALT", Keyboard::RightAlt),
+        ("KC_ROPT", Keyboard::RightAlt),
+        ("KC_ALGR", Keyboard::RightAlt),
+        ("KC_RIGHT_GUI", Keyboard::RightGUI),
+        ("KC_RGUI", Keyboard::RightGUI),
+        ("KC_RCMD", Keyboard::RightGUI),
+        ("KC_RWIN", Keyboard::RightGUI),
+    ];
+
+    /// The key's bit in the report's modifier byte, for LeftControl through RightGUI. Those are
+    /// sent there rather than among the keycodes.
+    pub fn modifier_bit(self) -> Option<u8> {
+        let code = self as u8;
+        (code >= Keyboard::LeftControl as u8).then(|| 1 << (code - Keyboard::LeftControl as u8))
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -601,20 +1128,40 @@ pub struct Mapping {
 }
 
 impl Mapping {
-    pub fn from_button(b: Keyboard) -> Mapping {
+    pub const fn from_button(b: Keyboard) -> Mapping {
         Mapping {
             action: KeyboardAction::None,
             button: b,
             consumer_button: Consumer::Unassigned,
         }
     }
-    pub fn from_action(a: KeyboardAction) -> Mapping {
+    pub const fn from_action(a: KeyboardAction) -> Mapping {
         Mapping {
             action: a,
             button: Keyboard::NoEventIndicated,
             consumer_button: Consumer::Unassigned,
         }
     }
+    pub const fn from_consumer(c: Consumer) -> Mapping {
+        Mapping {
+            action: KeyboardAction::None,
+            button: Keyboard::NoEventIndicated,
+            consumer_button: c,
+        }
+    }
+    /// Resolves a single keycode name. Keyboard names win over consumer and action names, so
+    /// those are only reachable here by names the keyboard table lacks, such as `KC_VOLU`.
+    pub const fn from_keycode(name: &str) -> Option<Mapping> {
+        if let Some(button) = Keyboard::lookup(name) {
+            Some(Mapping::from_button(button))
+        } else if let Some(consumer_button) = Consumer::lookup(name) {
+            Some(Mapping::from_consumer(consumer_button))
+        } else if let Some(action) = KeyboardAction::lookup(name) {
+            Some(Mapping::from_action(action))
+        } else {
+            None
+        }
+    }
     pub fn affects_reports(&self) -> Option<KeyboardAction> {
         match self.action {
             KeyboardAction::Layer0Momentary => Some(KeyboardAction::Layer0Momentary),
@@ -628,200 +1175,866 @@ impl Mapping {
             KeyboardAction::WasdModeOff => Some(KeyboardAction::WasdModeOff),
             KeyboardAction::WasdModeOn => Some(KeyboardAction::WasdModeOn),
             KeyboardAction::WasdModeToggle => Some(KeyboardAction::WasdModeToggle),
+            KeyboardAction::CalibrateJoystick => Some(KeyboardAction::CalibrateJoystick),
             _ => None,
         }
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct Keymap {
     pub key_mappings: [[Mapping; 4]; 21],
     pub joy_button_mappings: [Mapping; 4],
     pub scroll_button_mappings: [Mapping; 4],
-    /// KeyboardAction mappings are currently not supported in WASD mode to save processing power
+    /// Held while the stick points that way in WASD mode, just as a key would be; see wasd
     pub wasd_mappings: [[Mapping; 4]; 4],
+    /// Sent for each direction once the stick runs, see wasd_run
+    pub wasd_run_mappings: [[Mapping; 4]; 4],
+    /// Raw joystick readings at rest and at either end of each axis, see joystick::AxisCalibration
     pub joy_x_center: u16,
     pub joy_y_center: u16,
+    pub joy_x_min: u16,
+    pub joy_x_max: u16,
+    pub joy_y_min: u16,
+    pub joy_y_max: u16,
+    /// In degrees, see joystick::rotate
     pub joy_x_y_rotation: u16,
-    pub joy_x_deadzone: u16,
-    pub joy_y_deadzone: u16,
-    pub version: u8
+    /// Deadzones and curve for the joystick report
+    pub joy_response: StickResponse,
+    /// How the stick picks directions in WASD mode
+    pub wasd: WasdConfig,
+    /// Where each layer starts running in WASD mode, and what running sends
+    pub wasd_run: [WasdRun; LAYER_COUNT],
+    /// Colour of the key LEDs while each layer is active
+    pub layer_colors: [Hsv; 4],
+    /// Layout version, see KEYMAP_VERSION
+    pub version: u8,
+}
+
+impl Keymap {
+    /// Per-layer mappings of an input numbered as described at INPUT_COUNT.
+    pub fn input_mappings(&self, input: usize) -> Option<&[Mapping; 4]> {
+        match input {
+            0..=20 => Some(&self.key_mappings[input]),
+            21 => Some(&self.joy_button_mappings),
+            22 => Some(&self.scroll_button_mappings),
+            23..=26 => Some(&self.wasd_mappings[input - 23]),
+            _ => None,
+        }
+    }
+
+    pub fn input_mappings_mut(&mut self, input: usize) -> Option<&mut [Mapping; 4]> {
+        match input {
+            0..=20 => Some(&mut self.key_mappings[input]),
+            21 => Some(&mut self.joy_button_mappings),
+            22 => Some(&mut self.scroll_button_mappings),
+            23..=26 => Some(&mut self.wasd_mappings[input - 23]),
+            _ => None,
+        }
+    }
+
+    pub fn joy_x_calibration(&self) -> AxisCalibration {
+        AxisCalibration {
+            min: self.joy_x_min,
+            center: self.joy_x_center,
+            max: self.joy_x_max,
+        }
+    }
+
+    pub fn joy_y_calibration(&self) -> AxisCalibration {
+        AxisCalibration {
+            min: self.joy_y_min,
+            center: self.joy_y_center,
+            max: self.joy_y_max,
+        }
+    }
+
+    pub fn set_joy_calibration(&mut self, x: AxisCalibration, y: AxisCalibration) {
+        (self.joy_x_min, self.joy_x_center, self.joy_x_max) = (x.min, x.center, x.max);
+        (self.joy_y_min, self.joy_y_center, self.joy_y_max) = (y.min, y.center, y.max);
+    }
+
+    pub fn joystick_pipeline(&self) -> StickPipeline {
+        StickPipeline {
+            x: self.joy_x_calibration(),
+            y: self.joy_y_calibration(),
+            rotation: self.joy_x_y_rotation,
+            response: self.joy_response,
+        }
+    }
+
+    /// Layer 0 unmapped, every other layer transparent and default calibration. This is what
+    /// keymap.txt is laid over to build `Keymap::default()`.
+    pub const BLANK: Keymap = Keymap {
+        key_mappings: [BLANK_LAYERS; 21],
+        joy_button_mappings: BLANK_LAYERS,
+        scroll_button_mappings: BLANK_LAYERS,
+        wasd_mappings: [BLANK_LAYERS; 4],
+        wasd_run_mappings: [BLANK_LAYERS; 4],
+        joy_x_center: DEFAULT_JOY_X_CENTER,
+        joy_y_center: DEFAULT_JOY_Y_CENTER,
+        joy_x_min: DEFAULT_JOY_MIN,
+        joy_x_max: DEFAULT_JOY_MAX,
+        joy_y_min: DEFAULT_JOY_MIN,
+        joy_y_max: DEFAULT_JOY_MAX,
+        joy_x_y_rotation: DEFAULT_JOY_X_Y_ROTATION,
+        joy_response: StickResponse::LINEAR,
+        wasd: WasdConfig::DEFAULT,
+        wasd_run: [WasdRun::OFF; LAYER_COUNT],
+        layer_colors: DEFAULT_LAYER_COLORS,
+        version: KEYMAP_VERSION,
+    };
+}
+
+// Binary keymap format, all multi-byte values little endian:
+//
+//   offset  size  field
+//   0       4     magic, b"PTKM"
+//   4       1     Keymap::version the payload was written with
+//   5       1     reserved, always 0
+//   6       2     payload length in bytes
+//   8       n     payload
+//   8 + n   4     CRC-32 (IEEE) of bytes 0..8 + n
+//
+// The payload is, in order:
+//   - the number of layers stored per input, as u8
+//   - every Mapping in key_mappings (key-major), joy_button_mappings, scroll_button_mappings and
+//     wasd_mappings (direction-major), 4 bytes each: action u8, button u8, consumer_button u16
+//   - joy_x_center, joy_y_center, joy_x_y_rotation as u16
+//   - joy_x_min, joy_x_max, joy_y_min, joy_y_max as u16
+//   - joy_response: axial_deadzone, radial_deadzone, outer_deadzone, anti_deadzone as u16, the
+//     curve as kind u8, expo u16 and CURVE_POINTS u16 points, see ResponseCurve::to_raw
+//   - wasd: sectors as u8 (4 or 8), engage, release, diagonal_overlap, hysteresis,
+//     pulse_period_ms, min_pulse_ms as u16
+//   - wasd_run, one per stored layer: threshold as u16, mode as u8 (see RunMode::to_raw)
+//   - wasd_run_mappings (direction-major), as the other mappings
+//   - layer_colors as h, s, v bytes, one per stored layer
+//
+// Version history, decoding upgrades every older version to the current layout:
+//   1 - no layer count, always four layers
+//   2 - payload starts with the layer count
+//   3 - joystick axis limits; older keymaps get the full raw range
+//   4 - joystick response; older keymaps get the default one
+//   5 - WASD direction settings replace the two u16 WASD deadzones after joy_x_y_rotation; the
+//       larger deadzone becomes the engage threshold
+//   6 - WASD running; older keymaps never run
+//   7 - WASD pulsing; older keymaps hold directions down
+const KEYMAP_MAGIC: [u8; 4] = *b"PTKM";
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 4;
+const MAPPING_LEN: usize = 4;
+const MAPPING_COUNT: usize = (21 + 1 + 1 + 4) * LAYER_COUNT;
+const RESPONSE_LEN: usize = 4 * 2 + 1 + 2 + CURVE_POINTS * 2;
+const WASD_LEN: usize = 1 + 6 * 2;
+const RUN_LEN: usize = LAYER_COUNT * 3 + 4 * LAYER_COUNT * MAPPING_LEN;
+const PAYLOAD_LEN: usize =
+    1 + MAPPING_COUNT * MAPPING_LEN + 7 * 2 + RESPONSE_LEN + WASD_LEN + RUN_LEN + LAYER_COUNT * 3;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EncodeError {
+    BufferTooSmall,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DecodeError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    LengthMismatch,
+    BadChecksum,
+    UnknownAction(u8),
+    UnknownKeyboard(u8),
+    UnknownConsumer(u16),
+    UnknownCurve(u8),
+    UnknownSectors(u8),
+    UnknownRunMode(u8),
+}
+
+/// CRC-32 with the IEEE polynomial, as used by zlib and friends.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+impl Mapping {
+    /// Wire representation used by the keymap format and the configuration protocol.
+    pub fn to_raw(self) -> (u8, u8, u16) {
+        (
+            self.action as u8,
+            self.button as u8,
+            self.consumer_button as u16,
+        )
+    }
+
+    pub fn from_raw(action: u8, button: u8, consumer_button: u16) -> Result<Mapping, DecodeError> {
+        Ok(Mapping {
+            action: KeyboardAction::from_repr(action).ok_or(DecodeError::UnknownAction(action))?,
+            button: Keyboard::from_repr(button).ok_or(DecodeError::UnknownKeyboard(button))?,
+            consumer_button: Consumer::from_repr(consumer_button)
+                .ok_or(DecodeError::UnknownConsumer(consumer_button))?,
+        })
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, value: u8) {
+        self.buf[self.pos] = value;
+        self.pos += 1;
+    }
+    fn u16(&mut self, value: u16) {
+        self.buf[self.pos..self.pos + 2].copy_from_slice(&value.to_le_bytes());
+        self.pos += 2;
+    }
+    fn mapping(&mut self, mapping: &Mapping) {
+        let (action, button, consumer_button) = mapping.to_raw();
+        self.u8(action);
+        self.u8(button);
+        self.u16(consumer_button);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        let value = *self.buf.get(self.pos).ok_or(DecodeError::LengthMismatch)?;
+        self.pos += 1;
+        Ok(value)
+    }
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+    fn mapping(&mut self) -> Result<Mapping, DecodeError> {
+        let action = self.u8()?;
+        let button = self.u8()?;
+        let consumer_button = self.u16()?;
+        Mapping::from_raw(action, button, consumer_button)
+    }
+    /// Reads one mapping per stored layer. Layers the keymap doesn't have are dropped, layers
+    /// that weren't stored become transparent.
+    fn layers(
+        &mut self,
+        stored_layers: usize,
+        mappings: &mut [Mapping; LAYER_COUNT],
+    ) -> Result<(), DecodeError> {
+        for (layer, mapping) in mappings.iter_mut().enumerate() {
+            *mapping = if layer < stored_layers {
+                self.mapping()?
+            } else {
+                Mapping::from_action(KeyboardAction::Transparent)
+            };
+        }
+        for _ in LAYER_COUNT..stored_layers {
+            self.mapping()?;
+        }
+        Ok(())
+    }
 }
 
 impl Keymap {
-    pub fn default() -> Keymap {
-        Keymap {
-            key_mappings: [
-                [
-                    Mapping::from_button(Keyboard::Clear),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad0),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadDot),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadAdd),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadEqual),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad7),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad8),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad9),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadSubtract),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadMultiply),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad4),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad5),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad6),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadDivide),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadNumLockAndClear),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad1),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad2),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Keypad3),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::KeypadEnter),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::Space),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_action(KeyboardAction::Layer1Set),
-                    Mapping::from_action(KeyboardAction::Layer2Set),
-                    Mapping::from_action(KeyboardAction::Layer3Set),
-                    Mapping::from_action(KeyboardAction::Layer0Set),
-                ],
-            ],
-            joy_button_mappings: [
-                Mapping::from_action(KeyboardAction::JoystickButton),
-                Mapping::from_action(KeyboardAction::Transparent),
-                Mapping::from_action(KeyboardAction::Transparent),
-                Mapping::from_action(KeyboardAction::Transparent),
-            ],
-            scroll_button_mappings: [
-                Mapping::from_action(KeyboardAction::MouseScrollButton),
-                Mapping::from_action(KeyboardAction::Transparent),
-                Mapping::from_action(KeyboardAction::Transparent),
-                Mapping::from_action(KeyboardAction::Transparent),
-            ],
-            wasd_mappings: [
-                [
-                    Mapping::from_button(Keyboard::W),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::A),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::S),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-                [
-                    Mapping::from_button(Keyboard::D),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                    Mapping::from_action(KeyboardAction::Transparent),
-                ],
-            ],
-            joy_x_center: DEFAULT_JOY_X_CENTER,
-            joy_y_center: DEFAULT_JOY_Y_CENTER,
-            joy_x_y_rotation: DEFAULT_JOY_X_Y_ROTATION,
-            joy_x_deadzone: DEFAULT_JOY_X_DEAD_ZONE,
-            joy_y_deadzone: DEFAULT_JOY_Y_DEAD_ZONE,
-            version: 1
-        }
-    }
-}
\ No newline at end of file
+    /// Size of a serialized keymap, header and checksum included.
+    pub const ENCODED_LEN: usize = HEADER_LEN + PAYLOAD_LEN + CRC_LEN;
+
+    /// Serializes the keymap into `buf`, returning the number of bytes written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        if buf.len() < Keymap::ENCODED_LEN {
+            return Err(EncodeError::BufferTooSmall);
+        }
+        let mut w = Writer { buf, pos: 0 };
+        for byte in KEYMAP_MAGIC {
+            w.u8(byte);
+        }
+        w.u8(KEYMAP_VERSION);
+        w.u8(0);
+        w.u16(PAYLOAD_LEN as u16);
+        w.u8(LAYER_COUNT as u8);
+        for key in self.key_mappings.iter() {
+            for mapping in key.iter() {
+                w.mapping(mapping);
+            }
+        }
+        for mapping in self.joy_button_mappings.iter() {
+            w.mapping(mapping);
+        }
+        for mapping in self.scroll_button_mappings.iter() {
+            w.mapping(mapping);
+        }
+        for direction in self.wasd_mappings.iter() {
+            for mapping in direction.iter() {
+                w.mapping(mapping);
+            }
+        }
+        w.u16(self.joy_x_center);
+        w.u16(self.joy_y_center);
+        w.u16(self.joy_x_y_rotation);
+        w.u16(self.joy_x_min);
+        w.u16(self.joy_x_max);
+        w.u16(self.joy_y_min);
+        w.u16(self.joy_y_max);
+        let response = &self.joy_response;
+        w.u16(response.axial_deadzone);
+        w.u16(response.radial_deadzone);
+        w.u16(response.outer_deadzone);
+        w.u16(response.anti_deadzone);
+        let (kind, expo, points) = response.curve.to_raw();
+        w.u8(kind);
+        w.u16(expo);
+        for point in points {
+            w.u16(point);
+        }
+        let wasd = &self.wasd;
+        w.u8(wasd.sectors.count());
+        w.u16(wasd.engage);
+        w.u16(wasd.release);
+        w.u16(wasd.diagonal_overlap);
+        w.u16(wasd.hysteresis);
+        w.u16(wasd.pulse_period_ms);
+        w.u16(wasd.min_pulse_ms);
+        for run in self.wasd_run.iter() {
+            w.u16(run.threshold);
+            w.u8(run.mode.to_raw());
+        }
+        for direction in self.wasd_run_mappings.iter() {
+            for mapping in direction.iter() {
+                w.mapping(mapping);
+            }
+        }
+        for color in self.layer_colors.iter() {
+            w.u8(color.h);
+            w.u8(color.s);
+            w.u8(color.v);
+        }
+        let crc = crc32(&w.buf[..w.pos]);
+        w.buf[w.pos..w.pos + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        Ok(w.pos + CRC_LEN)
+    }
+
+    /// Parses a keymap written by `encode`. Trailing bytes after the checksum are ignored.
+    pub fn decode(buf: &[u8]) -> Result<Keymap, DecodeError> {
+        if buf.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::TooShort);
+        }
+        if buf[0..4] != KEYMAP_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = buf[4];
+        let payload_len = u16::from_le_bytes([buf[6], buf[7]]) as usize;
+        let end = HEADER_LEN + payload_len;
+        if buf.len() < end + CRC_LEN {
+            return Err(DecodeError::TooShort);
+        }
+        let crc = u32::from_le_bytes([buf[end], buf[end + 1], buf[end + 2], buf[end + 3]]);
+        if crc != crc32(&buf[..end]) {
+            return Err(DecodeError::BadChecksum);
+        }
+        if version == 0 || version > KEYMAP_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let mut r = Reader {
+            buf: &buf[HEADER_LEN..end],
+            pos: 0,
+        };
+        // anything an older version didn't store keeps its default
+        let mut keymap = Keymap::default();
+        let layers = if version >= 2 { r.u8()? as usize } else { 4 };
+        if layers > LAYER_COUNT {
+            log::warn!(
+                "dropping {} layers this firmware doesn't have",
+                layers - LAYER_COUNT
+            );
+        }
+        for key in keymap.key_mappings.iter_mut() {
+            r.layers(layers, key)?;
+        }
+        r.layers(layers, &mut keymap.joy_button_mappings)?;
+        r.layers(layers, &mut keymap.scroll_button_mappings)?;
+        for direction in keymap.wasd_mappings.iter_mut() {
+            r.layers(layers, direction)?;
+        }
+        keymap.joy_x_center = r.u16()?;
+        keymap.joy_y_center = r.u16()?;
+        keymap.joy_x_y_rotation = r.u16()?;
+        if version < 5 {
+            // the old per-axis WASD deadzones, in report units from the centre
+            let deadzone = r.u16()?.max(r.u16()?);
+            let engage = deadzone as u32 * PERMILLE as u32 / AXIS_CENTER as u32;
+            keymap.wasd.engage = engage.min(PERMILLE as u32) as u16;
+            keymap.wasd.release = keymap.wasd.engage * 3 / 4;
+        }
+        if version >= 3 {
+            keymap.joy_x_min = r.u16()?;
+            keymap.joy_x_max = r.u16()?;
+            keymap.joy_y_min = r.u16()?;
+            keymap.joy_y_max = r.u16()?;
+        }
+        if version >= 4 {
+            let response = &mut keymap.joy_response;
+            response.axial_deadzone = r.u16()?;
+            response.radial_deadzone = r.u16()?;
+            response.outer_deadzone = r.u16()?;
+            response.anti_deadzone = r.u16()?;
+            let kind = r.u8()?;
+            let expo = r.u16()?;
+            let mut points = [0; CURVE_POINTS];
+            for point in points.iter_mut() {
+                *point = r.u16()?;
+            }
+            response.curve = ResponseCurve::from_raw(kind, expo, points)
+                .ok_or(DecodeError::UnknownCurve(kind))?;
+        }
+        if version >= 5 {
+            let count = r.u8()?;
+            let wasd = &mut keymap.wasd;
+            wasd.sectors = Sectors::from_count(count).ok_or(DecodeError::UnknownSectors(count))?;
+            wasd.engage = r.u16()?;
+            wasd.release = r.u16()?;
+            wasd.diagonal_overlap = r.u16()?;
+            wasd.hysteresis = r.u16()?;
+        }
+        if version >= 7 {
+            keymap.wasd.pulse_period_ms = r.u16()?;
+            keymap.wasd.min_pulse_ms = r.u16()?;
+        }
+        if version >= 6 {
+            for layer in 0..layers {
+                let threshold = r.u16()?;
+                let mode = r.u8()?;
+                let mode = RunMode::from_raw(mode).ok_or(DecodeError::UnknownRunMode(mode))?;
+                if layer < LAYER_COUNT {
+                    keymap.wasd_run[layer] = WasdRun { threshold, mode };
+                }
+            }
+            for direction in keymap.wasd_run_mappings.iter_mut() {
+                r.layers(layers, direction)?;
+            }
+        }
+        for layer in 0..layers {
+            let color = Hsv::new(r.u8()?, r.u8()?, r.u8()?);
+            if layer < LAYER_COUNT {
+                keymap.layer_colors[layer] = color;
+            }
+        }
+        if !r.is_empty() {
+            return Err(DecodeError::LengthMismatch);
+        }
+        if version < KEYMAP_VERSION {
+            log::info!(
+                "migrated keymap from version {} to {}",
+                version,
+                KEYMAP_VERSION
+            );
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(keymap: &Keymap) -> Vec<u8> {
+        let mut buf = [0; Keymap::ENCODED_LEN];
+        let len = keymap.encode(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Wraps a payload in a header and checksum, as `encode` would
+    fn sign(version: u8, payload: &[u8]) -> Vec<u8> {
+        let mut blob = KEYMAP_MAGIC.to_vec();
+        blob.extend([version, 0]);
+        blob.extend((payload.len() as u16).to_le_bytes());
+        blob.extend(payload);
+        let crc = crc32(&blob);
+        blob.extend(crc.to_le_bytes());
+        blob
+    }
+
+    /// The payload of `keymap` with `edit` applied, signed again
+    fn edited(keymap: &Keymap, edit: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut payload = encoded(keymap)[HEADER_LEN..HEADER_LEN + PAYLOAD_LEN].to_vec();
+        edit(&mut payload);
+        sign(KEYMAP_VERSION, &payload)
+    }
+
+    const MAPPINGS_END: usize = 1 + MAPPING_COUNT * MAPPING_LEN;
+    const CURVE_KIND: usize = MAPPINGS_END + 7 * 2 + 4 * 2;
+    const SECTORS: usize = MAPPINGS_END + 7 * 2 + RESPONSE_LEN;
+    const RUN_MODE: usize = SECTORS + WASD_LEN + 2;
+
+    #[test]
+    fn round_trip() {
+        let mut keymap = Keymap::default();
+        keymap.key_mappings[3][1] = Mapping::from_button(Keyboard::Z);
+        keymap.joy_button_mappings[2] = Mapping::from_consumer(Consumer::Mute);
+        keymap.wasd_run_mappings[1][3] = Mapping::from_action(KeyboardAction::Layer2Set);
+        keymap.set_joy_calibration(
+            AxisCalibration {
+                min: 10,
+                center: 400,
+                max: 900,
+            },
+            AxisCalibration {
+                min: 30,
+                center: 600,
+                max: 1000,
+            },
+        );
+        keymap.joy_x_y_rotation = 345;
+        keymap.joy_response.curve = ResponseCurve::Custom([0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        keymap.wasd.sectors = Sectors::Four;
+        keymap.wasd.pulse_period_ms = 250;
+        keymap.wasd_run[3] = WasdRun {
+            threshold: 900,
+            mode: RunMode::Replace,
+        };
+        keymap.layer_colors[1] = Hsv::new(1, 2, 3);
+
+        let bytes = encoded(&keymap);
+        assert_eq!(bytes.len(), Keymap::ENCODED_LEN);
+        let decoded = Keymap::decode(&bytes).unwrap();
+        assert_eq!(encoded(&decoded), bytes);
+        assert_eq!(decoded.version, KEYMAP_VERSION);
+        // trailing bytes, such as the rest of a flash slot, are ignored
+        let mut padded = bytes.clone();
+        padded.extend([0xFF; 16]);
+        assert_eq!(encoded(&Keymap::decode(&padded).unwrap()), bytes);
+    }
+
+    #[test]
+    fn damaged_keymaps_are_rejected() {
+        let bytes = encoded(&Keymap::default());
+        let decode = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = bytes.clone();
+            edit(&mut bytes);
+            Keymap::decode(&bytes).err()
+        };
+        assert_eq!(decode(&|b| b[0] = b'X'), Some(DecodeError::BadMagic));
+        assert_eq!(
+            decode(&|b| b[HEADER_LEN + 5] ^= 1),
+            Some(DecodeError::BadChecksum)
+        );
+        assert_eq!(decode(&|b| b.truncate(10)), Some(DecodeError::TooShort));
+        assert_eq!(
+            decode(&|b| b.truncate(b.len() - 1)),
+            Some(DecodeError::TooShort)
+        );
+        assert_eq!(
+            Keymap::decode(&sign(0, &[])).err(),
+            Some(DecodeError::UnsupportedVersion(0))
+        );
+        assert_eq!(
+            Keymap::decode(&sign(KEYMAP_VERSION + 1, &[])).err(),
+            Some(DecodeError::UnsupportedVersion(KEYMAP_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn payload_length_must_match() {
+        let keymap = Keymap::default();
+        let longer = edited(&keymap, |p| p.push(0));
+        assert_eq!(
+            Keymap::decode(&longer).err(),
+            Some(DecodeError::LengthMismatch)
+        );
+        let shorter = edited(&keymap, |p| {
+            p.pop();
+        });
+        assert_eq!(
+            Keymap::decode(&shorter).err(),
+            Some(DecodeError::LengthMismatch)
+        );
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        let keymap = Keymap::default();
+        let decode = |edit: fn(&mut Vec<u8>)| Keymap::decode(&edited(&keymap, edit)).err();
+        assert_eq!(
+            decode(|p| p[1] = 0xFF),
+            Some(DecodeError::UnknownAction(0xFF))
+        );
+        assert_eq!(
+            decode(|p| p[2] = 0xFF),
+            Some(DecodeError::UnknownKeyboard(0xFF))
+        );
+        assert_eq!(
+            decode(|p| p[3..5].copy_from_slice(&[0xFF, 0xFF])),
+            Some(DecodeError::UnknownConsumer(0xFFFF))
+        );
+        assert_eq!(
+            decode(|p| p[CURVE_KIND] = 7),
+            Some(DecodeError::UnknownCurve(7))
+        );
+        assert_eq!(
+            decode(|p| p[SECTORS] = 6),
+            Some(DecodeError::UnknownSectors(6))
+        );
+        assert_eq!(
+            decode(|p| p[RUN_MODE] = 2),
+            Some(DecodeError::UnknownRunMode(2))
+        );
+    }
+
+    /// The mapping an old keymap stores for `input` on `layer`, different for every pair
+    fn old_mapping(input: usize, layer: usize) -> Mapping {
+        Mapping::from_raw(0, 4 + (input + INPUT_COUNT * layer) as u8, 0).unwrap()
+    }
+
+    /// A keymap as `version` wrote it, with `layers` layers where the version stores a count
+    fn old_keymap(version: u8, layers: usize) -> Vec<u8> {
+        let mut p = Vec::new();
+        let u16 = |p: &mut Vec<u8>, value: u16| p.extend(value.to_le_bytes());
+        let mapping = |p: &mut Vec<u8>, mapping: Mapping| {
+            let (action, button, consumer) = mapping.to_raw();
+            p.extend([action, button]);
+            p.extend(consumer.to_le_bytes());
+        };
+        if version >= 2 {
+            p.push(layers as u8);
+        }
+        for input in 0..INPUT_COUNT {
+            for layer in 0..layers {
+                mapping(&mut p, old_mapping(input, layer));
+            }
+        }
+        for value in [510, 490, 12] {
+            u16(&mut p, value);
+        }
+        if version < 5 {
+            // per-axis WASD deadzones in report units
+            u16(&mut p, 100);
+            u16(&mut p, 256);
+        }
+        if version >= 3 {
+            for value in [10, 1000, 20, 990] {
+                u16(&mut p, value);
+            }
+        }
+        if version >= 4 {
+            for value in [50, 60, 70, 80] {
+                u16(&mut p, value);
+            }
+            p.push(1);
+            u16(&mut p, 300);
+            for point in ResponseCurve::LINEAR_POINTS {
+                u16(&mut p, point);
+            }
+        }
+        if version >= 5 {
+            p.push(4);
+            for value in [450, 350, 5, 8] {
+                u16(&mut p, value);
+            }
+        }
+        if version >= 6 {
+            for layer in 0..layers {
+                u16(&mut p, 700 + layer as u16);
+                p.push(1);
+            }
+            for direction in 0..4 {
+                for layer in 0..layers {
+                    mapping(&mut p, old_mapping(direction, layer));
+                }
+            }
+        }
+        for layer in 0..layers {
+            p.extend([layer as u8 * 10, 200, 100]);
+        }
+        sign(version, &p)
+    }
+
+    fn same(a: Mapping, b: Mapping) -> bool {
+        a.to_raw() == b.to_raw()
+    }
+
+    #[test]
+    fn older_versions_are_upgraded() {
+        let defaults = Keymap::default();
+        for version in 1..KEYMAP_VERSION {
+            let keymap = Keymap::decode(&old_keymap(version, 4)).unwrap();
+            assert_eq!(keymap.version, KEYMAP_VERSION);
+            for layer in 0..LAYER_COUNT {
+                for key in 0..21 {
+                    assert!(same(
+                        keymap.key_mappings[key][layer],
+                        old_mapping(key, layer)
+                    ));
+                }
+                assert!(same(
+                    keymap.joy_button_mappings[layer],
+                    old_mapping(21, layer)
+                ));
+                assert!(same(
+                    keymap.scroll_button_mappings[layer],
+                    old_mapping(22, layer)
+                ));
+                for direction in 0..4 {
+                    let mapping = keymap.wasd_mappings[direction][layer];
+                    assert!(same(mapping, old_mapping(23 + direction, layer)));
+                }
+                assert_eq!(
+                    keymap.layer_colors[layer],
+                    Hsv::new(layer as u8 * 10, 200, 100)
+                );
+            }
+            assert_eq!(
+                (
+                    keymap.joy_x_center,
+                    keymap.joy_y_center,
+                    keymap.joy_x_y_rotation
+                ),
+                (510, 490, 12)
+            );
+
+            let limits = (
+                keymap.joy_x_min,
+                keymap.joy_x_max,
+                keymap.joy_y_min,
+                keymap.joy_y_max,
+            );
+            if version >= 3 {
+                assert_eq!(limits, (10, 1000, 20, 990));
+            } else {
+                let default = (
+                    defaults.joy_x_min,
+                    defaults.joy_x_max,
+                    defaults.joy_y_min,
+                    defaults.joy_y_max,
+                );
+                assert_eq!(limits, default);
+            }
+
+            if version >= 4 {
+                assert_eq!(keymap.joy_response.anti_deadzone, 80);
+                assert_eq!(keymap.joy_response.curve, ResponseCurve::Exponential(300));
+            } else {
+                assert_eq!(keymap.joy_response, defaults.joy_response);
+            }
+
+            if version >= 5 {
+                assert_eq!(keymap.wasd.sectors, Sectors::Four);
+                assert_eq!((keymap.wasd.engage, keymap.wasd.release), (450, 350));
+            } else {
+                // the larger deadzone, 256 of 512, becomes the engage threshold
+                assert_eq!((keymap.wasd.engage, keymap.wasd.release), (500, 375));
+                assert_eq!(keymap.wasd.sectors, defaults.wasd.sectors);
+                assert_eq!(keymap.wasd.hysteresis, defaults.wasd.hysteresis);
+            }
+            assert_eq!(keymap.wasd.pulse_period_ms, defaults.wasd.pulse_period_ms);
+
+            if version >= 6 {
+                assert_eq!(keymap.wasd_run[2].threshold, 702);
+                assert_eq!(keymap.wasd_run[2].mode, RunMode::Replace);
+                assert!(same(keymap.wasd_run_mappings[1][3], old_mapping(1, 3)));
+            } else {
+                assert_eq!(keymap.wasd_run, defaults.wasd_run);
+                let (mapping, default) = (
+                    keymap.wasd_run_mappings[1][3],
+                    defaults.wasd_run_mappings[1][3],
+                );
+                assert!(same(mapping, default));
+            }
+        }
+    }
+
+    #[test]
+    fn extra_layers_are_dropped() {
+        for version in 2..KEYMAP_VERSION {
+            let keymap = Keymap::decode(&old_keymap(version, 5)).unwrap();
+            assert!(same(keymap.key_mappings[20][3], old_mapping(20, 3)));
+            assert!(same(keymap.wasd_mappings[3][0], old_mapping(26, 0)));
+            assert_eq!(keymap.layer_colors[3], Hsv::new(30, 200, 100));
+            if version >= 6 {
+                assert_eq!(keymap.wasd_run[3].threshold, 703);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_layers_are_transparent() {
+        let keymap = Keymap::decode(&old_keymap(2, 2)).unwrap();
+        let transparent = Mapping::from_action(KeyboardAction::Transparent);
+        assert!(same(keymap.key_mappings[0][1], old_mapping(0, 1)));
+        assert!(same(keymap.key_mappings[0][2], transparent));
+        assert!(same(keymap.scroll_button_mappings[3], transparent));
+        assert_eq!(keymap.layer_colors[2], Keymap::default().layer_colors[2]);
+    }
+
+    #[test]
+    fn names_and_values_match() {
+        for key in Keyboard::ALL {
+            assert!(Keyboard::from_name(key.name()) == Some(*key));
+            assert!(Keyboard::from_repr(*key as u8) == Some(*key));
+        }
+        for consumer in Consumer::ALL {
+            assert!(Consumer::lookup(consumer.name()) == Some(*consumer));
+        }
+        for action in KeyboardAction::ALL {
+            assert!(KeyboardAction::lookup(action.name()) == Some(*action));
+        }
+        assert_eq!(Keyboard::Return.name(), "Return");
+    }
+
+    #[test]
+    fn aliases_resolve() {
+        assert!(Keyboard::lookup("KC_A") == Some(Keyboard::A));
+        assert!(Keyboard::lookup("KC_ENT") == Some(Keyboard::Return));
+        assert!(Consumer::lookup("KC_VOLU") == Some(Consumer::VolumeIncrement));
+        assert!(KeyboardAction::lookup("_______") == Some(KeyboardAction::Transparent));
+        // aliases never stand in for a variant name
+        assert!(Keyboard::from_name("KC_A").is_none());
+        for (alias, key) in Keyboard::ALIASES {
+            assert!(Keyboard::from_name(alias).is_none());
+            assert!(Keyboard::lookup(alias) == Some(*key));
+        }
+        assert!(
+            Mapping::from_keycode("KC_VOLU").unwrap().consumer_button == Consumer::VolumeIncrement
+        );
+        assert!(Mapping::from_keycode("KC_NOPE").is_none());
+    }
+
+    #[test]
+    fn names_are_case_sensitive() {
+        assert!(Keyboard::lookup("a").is_none());
+        assert!(Keyboard::lookup("kc_a").is_none());
+        assert!(Keyboard::lookup("leftshift").is_none());
+        assert!(Keyboard::lookup("").is_none());
+        assert!(str_eq("KC_A", "KC_A"));
+        assert!(!str_eq("KC_A", "kc_a"));
+        assert!(!str_eq("KC_A", "KC_AB"));
+    }
+
+    #[test]
+    fn numbers_convert_checked() {
+        assert!(Keyboard::try_from(0x04).ok() == Some(Keyboard::A));
+        assert_eq!(Keyboard::try_from(0xA5).err(), Some(UnknownValue(0xA5)));
+        assert_eq!(Keyboard::try_from(0xE8).err(), Some(UnknownValue(0xE8)));
+        assert!(Consumer::try_from(0xE2).ok() == Some(Consumer::Mute));
+        assert_eq!(Consumer::try_from(0xFFFF).err(), Some(UnknownValue(0xFFFF)));
+        assert!(KeyboardAction::try_from(0).ok() == Some(KeyboardAction::None));
+        let past_end = KeyboardAction::ALL.len() as u8;
+        assert_eq!(
+            KeyboardAction::try_from(past_end).err(),
+            Some(UnknownValue(past_end))
+        );
+    }
+}
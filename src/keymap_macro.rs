@@ -0,0 +1,174 @@
+use crate::keymap_common::{Mapping, LAYER_COUNT};
+
+/// Builds a `Keymap` from `Keymap::BLANK` and a grid per layer, usable in const context:
+///
+/// ```
+/// use padtarust::color::Hsv;
+/// use padtarust::keymap;
+/// use padtarust::keymap_common::{Keyboard, KeyboardAction, Keymap};
+///
+/// const KEYMAP: Keymap = keymap! {
+///     layer 0 {
+///         [ Clear   Keypad0 KeypadDot KeypadAdd      KeypadEqual           ]
+///         [ Keypad7 Keypad8 Keypad9   KeypadSubtract KeypadMultiply        ]
+///         [ Keypad4 Keypad5 Keypad6   KeypadDivide   KeypadNumLockAndClear ]
+///         [ Keypad1 Keypad2 Keypad3   KeypadEnter                          ]
+///         thumb: [ Space Layer1Set ],
+///         joystick: JoystickButton,
+///         wheel: MouseScrollButton,
+///         wasd: [ W A S D ],
+///         color: (0, 0, 255),
+///     }
+///     layer 1 {
+///         [ _ _ _ _ _ ]
+///         [ _ _ _ _ _ ]
+///         [ _ _ _ _ _ ]
+///         [ KC_MPRV KC_MPLY KC_MNXT _ ]
+///         thumb: [ _ Layer0Set ],
+///     }
+/// };
+///
+/// assert!(KEYMAP.key_mappings[1][0].button == Keyboard::Keypad0);
+/// assert!(KEYMAP.key_mappings[20][1].action == KeyboardAction::Layer0Set);
+/// assert!(KEYMAP.key_mappings[0][1].action == KeyboardAction::Transparent);
+/// assert!(KEYMAP.wasd_mappings[3][0].button == Keyboard::D);
+/// assert_eq!(KEYMAP.layer_colors[0], Hsv::new(0, 0, 255));
+/// ```
+///
+/// Rows follow the key numbering: three rows of five, one of four, then the two thumb keys. Each
+/// key is a single keycode name as accepted by `Mapping::from_keycode`, or `_` for transparent.
+/// `joystick`, `wheel`, `wasd` and `color` are optional and anything not given stays as in
+/// `Keymap::BLANK`. Rows of the wrong length and unknown keycodes fail to compile:
+///
+/// ```compile_fail
+/// # use padtarust::keymap;
+/// # use padtarust::keymap_common::Keymap;
+/// const KEYMAP: Keymap = keymap! {
+///     layer 0 {
+///         [ A B C D E ]
+///         [ F G H I J ]
+///         [ K L M N   ]
+///         [ P Q R S   ]
+///         thumb: [ T U ],
+///     }
+/// };
+/// ```
+///
+/// ```compile_fail
+/// # use padtarust::keymap;
+/// # use padtarust::keymap_common::Keymap;
+/// const KEYMAP: Keymap = keymap! {
+///     layer 0 {
+///         [ A B C D E ]
+///         [ F G H I J ]
+///         [ K L M N O ]
+///         [ P Q R Bogus ]
+///         thumb: [ T U ],
+///     }
+/// };
+/// ```
+#[macro_export]
+macro_rules! keymap {
+    ( $( layer $layer:literal { $($body:tt)* } )* ) => {{
+        let mut keymap = $crate::keymap_common::Keymap::BLANK;
+        $( $crate::__keymap_layer!(keymap, $layer, $($body)*); )*
+        keymap
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __keymap_layer {
+    (
+        $keymap:ident, $layer:literal,
+        [ $k0:tt $k1:tt $k2:tt $k3:tt $k4:tt ]
+        [ $k5:tt $k6:tt $k7:tt $k8:tt $k9:tt ]
+        [ $k10:tt $k11:tt $k12:tt $k13:tt $k14:tt ]
+        [ $k15:tt $k16:tt $k17:tt $k18:tt ]
+        thumb: [ $k19:tt $k20:tt ]
+        $(, $slot:ident: $value:tt)* $(,)?
+    ) => {
+        let layer = $crate::keymap_macro::layer($layer);
+        let keys = [
+            $crate::__keymap_key!($k0), $crate::__keymap_key!($k1), $crate::__keymap_key!($k2),
+            $crate::__keymap_key!($k3), $crate::__keymap_key!($k4), $crate::__keymap_key!($k5),
+            $crate::__keymap_key!($k6), $crate::__keymap_key!($k7), $crate::__keymap_key!($k8),
+            $crate::__keymap_key!($k9), $crate::__keymap_key!($k10), $crate::__keymap_key!($k11),
+            $crate::__keymap_key!($k12), $crate::__keymap_key!($k13), $crate::__keymap_key!($k14),
+            $crate::__keymap_key!($k15), $crate::__keymap_key!($k16), $crate::__keymap_key!($k17),
+            $crate::__keymap_key!($k18), $crate::__keymap_key!($k19), $crate::__keymap_key!($k20),
+        ];
+        let mut key = 0;
+        while key < keys.len() {
+            $keymap.key_mappings[key][layer] = keys[key];
+            key += 1;
+        }
+        $( $crate::__keymap_slot!($keymap, layer, $slot, $value); )*
+    };
+    ( $keymap:ident, $layer:literal, $($body:tt)* ) => {
+        compile_error!(concat!(
+            "layer ", stringify!($layer), " must list rows of 5, 5, 5 and 4 keys, then ",
+            "`thumb: [a b]`, then any of `joystick`, `wheel`, `wasd` and `color`"
+        ));
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __keymap_slot {
+    ($keymap:ident, $layer:ident, joystick, $key:tt) => {
+        $keymap.joy_button_mappings[$layer] = $crate::__keymap_key!($key);
+    };
+    ($keymap:ident, $layer:ident, wheel, $key:tt) => {
+        $keymap.scroll_button_mappings[$layer] = $crate::__keymap_key!($key);
+    };
+    ($keymap:ident, $layer:ident, wasd, [ $up:tt $left:tt $down:tt $right:tt ]) => {
+        $keymap.wasd_mappings[0][$layer] = $crate::__keymap_key!($up);
+        $keymap.wasd_mappings[1][$layer] = $crate::__keymap_key!($left);
+        $keymap.wasd_mappings[2][$layer] = $crate::__keymap_key!($down);
+        $keymap.wasd_mappings[3][$layer] = $crate::__keymap_key!($right);
+    };
+    ($keymap:ident, $layer:ident, color, ($h:expr, $s:expr, $v:expr)) => {
+        $keymap.layer_colors[$layer] = $crate::color::Hsv::new($h, $s, $v);
+    };
+    ($keymap:ident, $layer:ident, wasd, $value:tt) => {
+        compile_error!("`wasd` takes four keys: [up left down right]");
+    };
+    ($keymap:ident, $layer:ident, $slot:ident, $value:tt) => {
+        compile_error!(concat!(
+            "unknown slot `", stringify!($slot), "`, expected `joystick`, `wheel`, `wasd` or `color`"
+        ));
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __keymap_key {
+    (_) => {
+        $crate::keymap_common::Mapping::from_action(
+            $crate::keymap_common::KeyboardAction::Transparent,
+        )
+    };
+    ($key:ident) => {
+        $crate::keymap_macro::keycode(
+            stringify!($key),
+            concat!("unknown keycode `", stringify!($key), "`"),
+        )
+    };
+}
+
+#[doc(hidden)]
+pub const fn keycode(name: &str, error: &'static str) -> Mapping {
+    match Mapping::from_keycode(name) {
+        Some(mapping) => mapping,
+        None => panic!("{}", error),
+    }
+}
+
+#[doc(hidden)]
+pub const fn layer(layer: usize) -> usize {
+    if layer >= LAYER_COUNT {
+        panic!("layer out of range");
+    }
+    layer
+}
@@ -0,0 +1,928 @@
+use crate::color::Hsv;
+use crate::joystick::{ResponseCurve, CURVE_POINTS, PERMILLE, RAW_MAX};
+use crate::keymap_common::{Consumer, Keyboard, KeyboardAction, Keymap, Mapping, LAYER_COUNT};
+use crate::wasd::{RunMode, Sectors, MAX_DIAGONAL_OVERLAP};
+use core::fmt;
+
+// Human-readable keymap format, loosely modelled on TOML:
+//
+//   # comments run to the end of the line
+//   [joystick]
+//   x_center = 500
+//   y_center = 500
+//   x_min = 0
+//   x_max = 1023
+//   y_min = 0
+//   y_max = 1023
+//   rotation = 15
+//   radial_deadzone = 50
+//   curve = exponential(300)
+//
+//   [wasd]
+//   sectors = 8
+//   engage = 400
+//   release = 300
+//   diagonal_overlap = 10
+//   hysteresis = 10
+//   pulse_period = 100
+//   min_pulse = 20
+//
+//   [layer.0]
+//   keys = [
+//       Clear, Keypad0, KeypadDot, KeypadAdd, KeypadEqual,
+//       ...21 mappings in total, in key order...
+//   ]
+//   joystick_button = Action::JoystickButton
+//   scroll_button = Action::MouseScrollButton
+//   wasd = [W, A, S, D]
+//   wasd_run = [R, R, R, R]
+//   wasd_run_threshold = 850
+//   wasd_run_mode = add
+//   color = hsv(0, 0, 255)
+//
+// A mapping is `_` (transparent), `none`, or one or more of a Keyboard name (optionally written
+// `Keyboard::Name`), `Action::Name` and `Consumer::Name` joined with `+`. QMK names such as `KC_A`,
+// `KC_VOLU` or `KC_TRNS` work anywhere, see Mapping::from_keycode. Anything a file leaves out
+// keeps its value from Keymap::default(). Settings that have to agree with each other, such as an
+// axis's min, center and max, are checked once the whole file is read.
+//
+// The joystick's response sizes (axial_deadzone, radial_deadzone, outer_deadzone and
+// anti_deadzone) are thousandths of full deflection. Its curve is `linear`, `exponential(EXPO)`
+// or `custom(P0, ..., P8)`, see joystick::ResponseCurve. WASD mode's engage and release are
+// thousandths of full deflection too, its diagonal_overlap and hysteresis degrees, see
+// wasd::WasdConfig; pulse_period and min_pulse are milliseconds. A layer's wasd_run mappings are sent once the stick tilts past its
+// wasd_run_threshold, along with the wasd ones (`add`) or instead of them (`replace`); a
+// threshold of 0 never runs.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    /// Found something other than the named token
+    Expected(&'static str),
+    UnknownSection,
+    UnknownLayer,
+    UnknownField,
+    UnknownKeycode,
+    WrongCount {
+        expected: usize,
+        found: usize,
+    },
+    NumberOutOfRange,
+    /// The file's settings, together, break the named rule
+    Invalid(&'static str),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub line: u32,
+    pub column: u32,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of file"),
+            ParseErrorKind::Expected(what) => write!(f, "expected {}", what),
+            ParseErrorKind::UnknownSection => write!(f, "unknown section"),
+            ParseErrorKind::UnknownLayer => write!(f, "layer out of range"),
+            ParseErrorKind::UnknownField => write!(f, "unknown field"),
+            ParseErrorKind::UnknownKeycode => write!(f, "unknown keycode"),
+            ParseErrorKind::WrongCount { expected, found } => {
+                write!(f, "expected {} entries, found {}", expected, found)
+            }
+            ParseErrorKind::NumberOutOfRange => write!(f, "number out of range"),
+            ParseErrorKind::Invalid(rule) => write!(f, "needs {}", rule),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Token<'a> {
+    Ident(&'a str),
+    Number(u32),
+    Punct(char),
+    End,
+}
+
+#[derive(Copy, Clone)]
+struct Position {
+    line: u32,
+    column: u32,
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    offset: usize,
+    position: Position,
+    peeked: Option<(Token<'a>, Position)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Lexer<'a> {
+        Lexer {
+            text,
+            offset: 0,
+            position: Position { line: 1, column: 1 },
+            peeked: None,
+        }
+    }
+
+    fn error(position: Position, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: position.line,
+            column: position.column,
+            kind,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.text[self.offset..].chars().next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn lex(&mut self) -> Result<(Token<'a>, Position), ParseError> {
+        // skip whitespace and comments
+        loop {
+            match self.peek_char() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => {
+                    while !matches!(self.peek_char(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = self.position;
+        let start_offset = self.offset;
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return Ok((Token::End, start)),
+        };
+        if c.is_ascii_alphabetic() || c == '_' {
+            while matches!(self.peek_char(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                self.bump();
+            }
+            // keep `Namespace::Name` together
+            if self.text[self.offset..].starts_with("::") {
+                self.bump();
+                self.bump();
+                while matches!(self.peek_char(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+            }
+            return Ok((Token::Ident(&self.text[start_offset..self.offset]), start));
+        }
+        if c.is_ascii_digit() {
+            let mut value: u32 = 0;
+            while let Some(digit) = self.peek_char().and_then(|c| c.to_digit(10)) {
+                self.bump();
+                value = value
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add(digit))
+                    .ok_or(Lexer::error(start, ParseErrorKind::NumberOutOfRange))?;
+            }
+            return Ok((Token::Number(value), start));
+        }
+        if "[]=,+().".contains(c) {
+            self.bump();
+            return Ok((Token::Punct(c), start));
+        }
+        Err(Lexer::error(start, ParseErrorKind::UnexpectedCharacter(c)))
+    }
+
+    fn peek(&mut self) -> Result<Token<'a>, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex()?);
+        }
+        Ok(self.peeked.unwrap().0)
+    }
+
+    fn next(&mut self) -> Result<(Token<'a>, Position), ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lex(),
+        }
+    }
+
+    fn expect_punct(&mut self, punct: char, what: &'static str) -> Result<(), ParseError> {
+        match self.next()? {
+            (Token::Punct(c), _) if c == punct => Ok(()),
+            (_, position) => Err(Lexer::error(position, ParseErrorKind::Expected(what))),
+        }
+    }
+
+    fn number(&mut self, max: u32) -> Result<u32, ParseError> {
+        match self.next()? {
+            (Token::Number(n), _) if n <= max => Ok(n),
+            (Token::Number(_), position) => {
+                Err(Lexer::error(position, ParseErrorKind::NumberOutOfRange))
+            }
+            (_, position) => Err(Lexer::error(position, ParseErrorKind::Expected("a number"))),
+        }
+    }
+
+    fn mapping(&mut self) -> Result<Mapping, ParseError> {
+        let mut mapping = Mapping::from_action(KeyboardAction::None);
+        loop {
+            let (name, position) = match self.next()? {
+                (Token::Ident(name), position) => (name, position),
+                (Token::End, position) => {
+                    return Err(Lexer::error(position, ParseErrorKind::UnexpectedEnd))
+                }
+                (_, position) => {
+                    return Err(Lexer::error(
+                        position,
+                        ParseErrorKind::Expected("a keycode"),
+                    ))
+                }
+            };
+            let unknown = Lexer::error(position, ParseErrorKind::UnknownKeycode);
+            match name {
+                "_" => mapping.action = KeyboardAction::Transparent,
+                "none" => {}
+                _ => {
+                    let part = if let Some(action) = name.strip_prefix("Action::") {
+                        KeyboardAction::lookup(action).map(Mapping::from_action)
+                    } else if let Some(consumer) = name.strip_prefix("Consumer::") {
+                        Consumer::lookup(consumer).map(Mapping::from_consumer)
+                    } else if let Some(button) = name.strip_prefix("Keyboard::") {
+                        Keyboard::lookup(button).map(Mapping::from_button)
+                    } else {
+                        Mapping::from_keycode(name)
+                    };
+                    let part = part.ok_or(unknown)?;
+                    if part.action != KeyboardAction::None {
+                        mapping.action = part.action;
+                    }
+                    if part.button != Keyboard::NoEventIndicated {
+                        mapping.button = part.button;
+                    }
+                    if part.consumer_button != Consumer::Unassigned {
+                        mapping.consumer_button = part.consumer_button;
+                    }
+                }
+            }
+            if self.peek()? != Token::Punct('+') {
+                return Ok(mapping);
+            }
+            self.next()?;
+        }
+    }
+
+    /// Parses `[mapping, ...]`, handing each entry to `set`. Exactly `len` entries are required.
+    fn mapping_list(
+        &mut self,
+        len: usize,
+        mut set: impl FnMut(usize, Mapping),
+    ) -> Result<(), ParseError> {
+        self.expect_punct('[', "'['")?;
+        let mut count = 0;
+        let end = loop {
+            if let Token::Punct(']') = self.peek()? {
+                break self.next()?.1;
+            }
+            let mapping = self.mapping()?;
+            if count < len {
+                set(count, mapping);
+            }
+            count += 1;
+            match self.next()? {
+                (Token::Punct(','), _) => {}
+                (Token::Punct(']'), position) => break position,
+                (_, position) => {
+                    return Err(Lexer::error(
+                        position,
+                        ParseErrorKind::Expected("',' or ']'"),
+                    ))
+                }
+            }
+        };
+        if count != len {
+            return Err(Lexer::error(
+                end,
+                ParseErrorKind::WrongCount {
+                    expected: len,
+                    found: count,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `peek`, but also returns where the token starts.
+    fn peek_position(&mut self) -> Result<Position, ParseError> {
+        self.peek()?;
+        Ok(self.peeked.unwrap().1)
+    }
+
+    fn color(&mut self) -> Result<Hsv, ParseError> {
+        match self.next()? {
+            (Token::Ident("hsv"), _) => {}
+            (_, position) => {
+                return Err(Lexer::error(
+                    position,
+                    ParseErrorKind::Expected("hsv(h, s, v)"),
+                ))
+            }
+        }
+        self.expect_punct('(', "'('")?;
+        let h = self.number(255)? as u8;
+        self.expect_punct(',', "','")?;
+        let s = self.number(255)? as u8;
+        self.expect_punct(',', "','")?;
+        let v = self.number(255)? as u8;
+        self.expect_punct(')', "')'")?;
+        Ok(Hsv::new(h, s, v))
+    }
+
+    fn curve(&mut self) -> Result<ResponseCurve, ParseError> {
+        let expected = "linear, exponential(expo) or custom(points)";
+        match self.next()? {
+            (Token::Ident("linear"), _) => Ok(ResponseCurve::Linear),
+            (Token::Ident("exponential"), _) => {
+                self.expect_punct('(', "'('")?;
+                let expo = self.number(PERMILLE as u32)? as u16;
+                self.expect_punct(')', "')'")?;
+                Ok(ResponseCurve::Exponential(expo))
+            }
+            (Token::Ident("custom"), _) => {
+                self.expect_punct('(', "'('")?;
+                let mut points = [0; CURVE_POINTS];
+                for (i, point) in points.iter_mut().enumerate() {
+                    if i > 0 {
+                        self.expect_punct(',', "','")?;
+                    }
+                    *point = self.number(PERMILLE as u32)? as u16;
+                }
+                self.expect_punct(')', "')'")?;
+                Ok(ResponseCurve::Custom(points))
+            }
+            (_, position) => Err(Lexer::error(position, ParseErrorKind::Expected(expected))),
+        }
+    }
+
+    fn sectors(&mut self) -> Result<Sectors, ParseError> {
+        let position = self.peek_position()?;
+        let count = self.number(u8::MAX as u32)? as u8;
+        Sectors::from_count(count)
+            .ok_or_else(|| Lexer::error(position, ParseErrorKind::Expected("4 or 8")))
+    }
+
+    fn run_mode(&mut self) -> Result<RunMode, ParseError> {
+        match self.next()? {
+            (Token::Ident("add"), _) => Ok(RunMode::Add),
+            (Token::Ident("replace"), _) => Ok(RunMode::Replace),
+            (_, position) => Err(Lexer::error(
+                position,
+                ParseErrorKind::Expected("add or replace"),
+            )),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Section {
+    None,
+    Joystick,
+    Wasd,
+    Layer(usize),
+}
+
+/// Where the file last set each group of settings that has to agree with itself. They are
+/// written one field at a time, so they're only checked once the whole file is read.
+#[derive(Default)]
+struct LastSet {
+    x: Option<Position>,
+    y: Option<Position>,
+    response: Option<Position>,
+    wasd: Option<Position>,
+}
+
+impl LastSet {
+    fn record(&mut self, section: Section, field: &str, position: Position) {
+        let group = match (section, field) {
+            (Section::Joystick, "x_center" | "x_min" | "x_max") => &mut self.x,
+            (Section::Joystick, "y_center" | "y_min" | "y_max") => &mut self.y,
+            (Section::Joystick, "rotation") => return,
+            (Section::Joystick, _) => &mut self.response,
+            (Section::Wasd, _) => &mut self.wasd,
+            _ => return,
+        };
+        *group = Some(position);
+    }
+
+    /// Runs the same checks the configuration protocol does on each group the file set.
+    fn check(&self, keymap: &Keymap) -> Result<(), ParseError> {
+        let checks = [
+            (
+                self.x,
+                keymap.joy_x_calibration().is_valid(),
+                "x_min < x_center < x_max",
+            ),
+            (
+                self.y,
+                keymap.joy_y_calibration().is_valid(),
+                "y_min < y_center < y_max",
+            ),
+            (
+                self.response,
+                keymap.joy_response.is_valid(),
+                "a valid joystick response",
+            ),
+            (self.wasd, keymap.wasd.is_valid(), "release <= engage"),
+        ];
+        for (position, valid, rule) in checks {
+            if let (Some(position), false) = (position, valid) {
+                return Err(Lexer::error(position, ParseErrorKind::Invalid(rule)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a single response curve, such as `exponential(300)`.
+pub fn parse_curve(text: &str) -> Result<ResponseCurve, ParseError> {
+    let mut lexer = Lexer::new(text);
+    let curve = lexer.curve()?;
+    match lexer.next()? {
+        (Token::End, _) => Ok(curve),
+        (_, position) => Err(Lexer::error(
+            position,
+            ParseErrorKind::Expected("end of curve"),
+        )),
+    }
+}
+
+/// Parses a single run mode, `add` or `replace`.
+pub fn parse_run_mode(text: &str) -> Result<RunMode, ParseError> {
+    let mut lexer = Lexer::new(text);
+    let mode = lexer.run_mode()?;
+    match lexer.next()? {
+        (Token::End, _) => Ok(mode),
+        (_, position) => Err(Lexer::error(
+            position,
+            ParseErrorKind::Expected("end of run mode"),
+        )),
+    }
+}
+
+/// Parses a single mapping, such as `Action::Layer1Momentary + KC_A`.
+pub fn parse_mapping(text: &str) -> Result<Mapping, ParseError> {
+    let mut lexer = Lexer::new(text);
+    let mapping = lexer.mapping()?;
+    match lexer.next()? {
+        (Token::End, _) => Ok(mapping),
+        (_, position) => Err(Lexer::error(
+            position,
+            ParseErrorKind::Expected("end of mapping"),
+        )),
+    }
+}
+
+pub fn parse(text: &str) -> Result<Keymap, ParseError> {
+    let mut keymap = Keymap::default();
+    let mut lexer = Lexer::new(text);
+    let mut section = Section::None;
+    let mut last_set = LastSet::default();
+    loop {
+        let (token, position) = lexer.next()?;
+        let field = match token {
+            Token::End => {
+                last_set.check(&keymap)?;
+                return Ok(keymap);
+            }
+            Token::Punct('[') => {
+                section = match lexer.next()? {
+                    (Token::Ident("joystick"), _) => Section::Joystick,
+                    (Token::Ident("wasd"), _) => Section::Wasd,
+                    (Token::Ident("layer"), _) => {
+                        lexer.expect_punct('.', "'.'")?;
+                        let layer_position = lexer.peek_position()?;
+                        let layer = lexer.number(u32::MAX)? as usize;
+                        if layer >= LAYER_COUNT {
+                            return Err(Lexer::error(layer_position, ParseErrorKind::UnknownLayer));
+                        }
+                        Section::Layer(layer)
+                    }
+                    (_, position) => {
+                        return Err(Lexer::error(position, ParseErrorKind::UnknownSection))
+                    }
+                };
+                lexer.expect_punct(']', "']'")?;
+                continue;
+            }
+            Token::Ident(field) => field,
+            _ => {
+                return Err(Lexer::error(
+                    position,
+                    ParseErrorKind::Expected("a field name"),
+                ))
+            }
+        };
+        lexer.expect_punct('=', "'='")?;
+        last_set.record(section, field, position);
+        match (section, field) {
+            (Section::Joystick, "x_center") => keymap.joy_x_center = lexer.number(0xFFFF)? as u16,
+            (Section::Joystick, "y_center") => keymap.joy_y_center = lexer.number(0xFFFF)? as u16,
+            (Section::Joystick, "x_min") => keymap.joy_x_min = lexer.number(RAW_MAX as u32)? as u16,
+            (Section::Joystick, "x_max") => keymap.joy_x_max = lexer.number(RAW_MAX as u32)? as u16,
+            (Section::Joystick, "y_min") => keymap.joy_y_min = lexer.number(RAW_MAX as u32)? as u16,
+            (Section::Joystick, "y_max") => keymap.joy_y_max = lexer.number(RAW_MAX as u32)? as u16,
+            (Section::Joystick, "rotation") => {
+                keymap.joy_x_y_rotation = lexer.number(0xFFFF)? as u16
+            }
+            (Section::Joystick, "axial_deadzone") => {
+                keymap.joy_response.axial_deadzone = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Joystick, "radial_deadzone") => {
+                keymap.joy_response.radial_deadzone = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Joystick, "outer_deadzone") => {
+                keymap.joy_response.outer_deadzone = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Joystick, "anti_deadzone") => {
+                keymap.joy_response.anti_deadzone = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Joystick, "curve") => keymap.joy_response.curve = lexer.curve()?,
+            (Section::Wasd, "sectors") => keymap.wasd.sectors = lexer.sectors()?,
+            (Section::Wasd, "engage") => keymap.wasd.engage = lexer.number(PERMILLE as u32)? as u16,
+            (Section::Wasd, "release") => {
+                keymap.wasd.release = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Wasd, "diagonal_overlap") => {
+                keymap.wasd.diagonal_overlap = lexer.number(MAX_DIAGONAL_OVERLAP as u32)? as u16
+            }
+            (Section::Wasd, "hysteresis") => keymap.wasd.hysteresis = lexer.number(89)? as u16,
+            (Section::Wasd, "pulse_period") => {
+                keymap.wasd.pulse_period_ms = lexer.number(0xFFFF)? as u16
+            }
+            (Section::Wasd, "min_pulse") => keymap.wasd.min_pulse_ms = lexer.number(0xFFFF)? as u16,
+            (Section::Layer(layer), "keys") => {
+                lexer.mapping_list(21, |i, m| keymap.key_mappings[i][layer] = m)?
+            }
+            (Section::Layer(layer), "joystick_button") => {
+                keymap.joy_button_mappings[layer] = lexer.mapping()?
+            }
+            (Section::Layer(layer), "scroll_button") => {
+                keymap.scroll_button_mappings[layer] = lexer.mapping()?
+            }
+            (Section::Layer(layer), "wasd") => {
+                lexer.mapping_list(4, |i, m| keymap.wasd_mappings[i][layer] = m)?
+            }
+            (Section::Layer(layer), "wasd_run") => {
+                lexer.mapping_list(4, |i, m| keymap.wasd_run_mappings[i][layer] = m)?
+            }
+            (Section::Layer(layer), "wasd_run_threshold") => {
+                keymap.wasd_run[layer].threshold = lexer.number(PERMILLE as u32)? as u16
+            }
+            (Section::Layer(layer), "wasd_run_mode") => {
+                keymap.wasd_run[layer].mode = lexer.run_mode()?
+            }
+            (Section::Layer(layer), "color") => keymap.layer_colors[layer] = lexer.color()?,
+            _ => return Err(Lexer::error(position, ParseErrorKind::UnknownField)),
+        }
+    }
+}
+
+/// Formats a run mode the way `print` writes it.
+pub struct DisplayRunMode(pub RunMode);
+
+impl fmt::Display for DisplayRunMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            RunMode::Add => f.write_str("add"),
+            RunMode::Replace => f.write_str("replace"),
+        }
+    }
+}
+
+/// Formats a response curve the way `print` writes it.
+pub struct DisplayCurve<'a>(pub &'a ResponseCurve);
+
+impl fmt::Display for DisplayCurve<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            ResponseCurve::Linear => write!(f, "linear"),
+            ResponseCurve::Exponential(expo) => write!(f, "exponential({})", expo),
+            ResponseCurve::Custom(points) => {
+                write!(f, "custom(")?;
+                for (i, point) in points.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", point)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Formats a single mapping the way `print` writes it.
+pub struct DisplayMapping<'a>(pub &'a Mapping);
+
+impl fmt::Display for DisplayMapping<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mapping = self.0;
+        let has_button = mapping.button != Keyboard::NoEventIndicated;
+        let has_consumer = mapping.consumer_button != Consumer::Unassigned;
+        let mut separator = "";
+        match mapping.action {
+            KeyboardAction::Transparent if !has_button && !has_consumer => return write!(f, "_"),
+            KeyboardAction::None if !has_button && !has_consumer => return write!(f, "none"),
+            KeyboardAction::None => {}
+            action => {
+                write!(f, "Action::{}", action.name())?;
+                separator = " + ";
+            }
+        }
+        if has_button {
+            write!(f, "{}{}", separator, mapping.button.name())?;
+            separator = " + ";
+        }
+        if has_consumer {
+            write!(
+                f,
+                "{}Consumer::{}",
+                separator,
+                mapping.consumer_button.name()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_list<W: fmt::Write>(
+    out: &mut W,
+    mappings: impl Iterator<Item = Mapping>,
+    row_lengths: &[usize],
+) -> fmt::Result {
+    let mut mappings = mappings;
+    writeln!(out, "[")?;
+    for row in row_lengths {
+        write!(out, "   ")?;
+        for mapping in mappings.by_ref().take(*row) {
+            write!(out, " {},", DisplayMapping(&mapping))?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "]")
+}
+
+/// Writes `keymap` in the format understood by `parse`.
+pub fn print<W: fmt::Write>(keymap: &Keymap, out: &mut W) -> fmt::Result {
+    writeln!(out, "[joystick]")?;
+    writeln!(out, "x_center = {}", keymap.joy_x_center)?;
+    writeln!(out, "y_center = {}", keymap.joy_y_center)?;
+    writeln!(out, "x_min = {}", keymap.joy_x_min)?;
+    writeln!(out, "x_max = {}", keymap.joy_x_max)?;
+    writeln!(out, "y_min = {}", keymap.joy_y_min)?;
+    writeln!(out, "y_max = {}", keymap.joy_y_max)?;
+    writeln!(out, "rotation = {}", keymap.joy_x_y_rotation)?;
+    let response = &keymap.joy_response;
+    writeln!(out, "axial_deadzone = {}", response.axial_deadzone)?;
+    writeln!(out, "radial_deadzone = {}", response.radial_deadzone)?;
+    writeln!(out, "outer_deadzone = {}", response.outer_deadzone)?;
+    writeln!(out, "anti_deadzone = {}", response.anti_deadzone)?;
+    writeln!(out, "curve = {}", DisplayCurve(&response.curve))?;
+    writeln!(out)?;
+    writeln!(out, "[wasd]")?;
+    let wasd = &keymap.wasd;
+    writeln!(out, "sectors = {}", wasd.sectors.count())?;
+    writeln!(out, "engage = {}", wasd.engage)?;
+    writeln!(out, "release = {}", wasd.release)?;
+    writeln!(out, "diagonal_overlap = {}", wasd.diagonal_overlap)?;
+    writeln!(out, "hysteresis = {}", wasd.hysteresis)?;
+    writeln!(out, "pulse_period = {}", wasd.pulse_period_ms)?;
+    writeln!(out, "min_pulse = {}", wasd.min_pulse_ms)?;
+    for layer in 0..LAYER_COUNT {
+        writeln!(out)?;
+        writeln!(out, "[layer.{}]", layer)?;
+        write!(out, "keys = ")?;
+        // rows as they sit on the pad: four rows and the thumb keys
+        write_list(
+            out,
+            keymap.key_mappings.iter().map(|key| key[layer]),
+            &[5, 5, 5, 4, 2],
+        )?;
+        writeln!(
+            out,
+            "joystick_button = {}",
+            DisplayMapping(&keymap.joy_button_mappings[layer])
+        )?;
+        writeln!(
+            out,
+            "scroll_button = {}",
+            DisplayMapping(&keymap.scroll_button_mappings[layer])
+        )?;
+        write!(out, "wasd = ")?;
+        write_list(
+            out,
+            keymap
+                .wasd_mappings
+                .iter()
+                .map(|direction| direction[layer]),
+            &[4],
+        )?;
+        write!(out, "wasd_run = ")?;
+        write_list(
+            out,
+            keymap
+                .wasd_run_mappings
+                .iter()
+                .map(|direction| direction[layer]),
+            &[4],
+        )?;
+        let run = &keymap.wasd_run[layer];
+        writeln!(out, "wasd_run_threshold = {}", run.threshold)?;
+        writeln!(out, "wasd_run_mode = {}", DisplayRunMode(run.mode))?;
+        let color = keymap.layer_colors[layer];
+        writeln!(out, "color = hsv({}, {}, {})", color.h, color.s, color.v)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joystick::{AxisCalibration, StickResponse};
+    use crate::wasd::{WasdConfig, WasdRun};
+
+    fn printed(keymap: &Keymap) -> String {
+        let mut text = String::new();
+        print(keymap, &mut text).unwrap();
+        text
+    }
+
+    fn encoded(keymap: &Keymap) -> Vec<u8> {
+        let mut buf = vec![0; Keymap::ENCODED_LEN];
+        keymap.encode(&mut buf).unwrap();
+        buf
+    }
+
+    fn error(text: &str) -> (u32, u32, ParseErrorKind) {
+        let error = parse(text).err().unwrap();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn default_round_trips() {
+        let keymap = Keymap::default();
+        let text = printed(&keymap);
+        assert_eq!(encoded(&parse(&text).unwrap()), encoded(&keymap));
+    }
+
+    #[test]
+    fn edited_round_trips() {
+        let mut keymap = Keymap::default();
+        keymap.key_mappings[3][1] = Mapping {
+            action: KeyboardAction::Layer1Momentary,
+            button: Keyboard::A,
+            consumer_button: Consumer::VolumeIncrement,
+        };
+        keymap.key_mappings[20][3] = Mapping::from_action(KeyboardAction::Transparent);
+        keymap.wasd_run_mappings[2][0] = Mapping::from_button(Keyboard::R);
+        keymap.set_joy_calibration(
+            AxisCalibration {
+                min: 10,
+                center: 498,
+                max: 1013,
+            },
+            AxisCalibration {
+                min: 3,
+                center: 530,
+                max: 1020,
+            },
+        );
+        keymap.joy_x_y_rotation = 345;
+        keymap.joy_response = StickResponse {
+            axial_deadzone: 20,
+            radial_deadzone: 50,
+            outer_deadzone: 30,
+            anti_deadzone: 100,
+            curve: ResponseCurve::Custom([0, 50, 100, 200, 300, 450, 600, 800, 1000]),
+        };
+        keymap.wasd = WasdConfig {
+            sectors: Sectors::Four,
+            engage: 500,
+            release: 250,
+            diagonal_overlap: 0,
+            hysteresis: 5,
+            pulse_period_ms: 120,
+            min_pulse_ms: 15,
+        };
+        keymap.wasd_run[2] = WasdRun {
+            threshold: 900,
+            mode: RunMode::Replace,
+        };
+        keymap.layer_colors[1] = Hsv::new(12, 34, 56);
+
+        let text = printed(&keymap);
+        let parsed = parse(&text).unwrap();
+        assert_eq!(encoded(&parsed), encoded(&keymap));
+        assert_eq!(printed(&parsed), text);
+    }
+
+    #[test]
+    fn single_values_parse() {
+        let mapping = parse_mapping("Action::Layer1Momentary + KC_A").unwrap();
+        assert_eq!(
+            mapping.to_raw(),
+            Mapping {
+                action: KeyboardAction::Layer1Momentary,
+                button: Keyboard::A,
+                consumer_button: Consumer::Unassigned,
+            }
+            .to_raw()
+        );
+        assert_eq!(
+            parse_curve("exponential(300)"),
+            Ok(ResponseCurve::Exponential(300))
+        );
+        assert_eq!(parse_run_mode("replace"), Ok(RunMode::Replace));
+        assert_eq!(parse_run_mode("replace add").err().unwrap().column, 9);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        assert_eq!(
+            error("[joystick]\nx_min = 5000\n"),
+            (2, 9, ParseErrorKind::NumberOutOfRange)
+        );
+        assert_eq!(error("[layer.4]\n"), (1, 8, ParseErrorKind::UnknownLayer));
+        assert_eq!(error("[mouse]\n"), (1, 2, ParseErrorKind::UnknownSection));
+        assert_eq!(
+            error("[layer.0]\nwasd = [W, A, S]\n"),
+            (
+                2,
+                16,
+                ParseErrorKind::WrongCount {
+                    expected: 4,
+                    found: 3
+                }
+            )
+        );
+        assert_eq!(
+            error("# comment\n[layer.0]\njoystick_button = Bogus\n"),
+            (3, 19, ParseErrorKind::UnknownKeycode)
+        );
+        assert_eq!(
+            error("[wasd]\n  colour = 3\n"),
+            (2, 3, ParseErrorKind::UnknownField)
+        );
+        assert_eq!(
+            error("[layer.0]\ncolor = hsv(1, 2)\n"),
+            (2, 17, ParseErrorKind::Expected("','"))
+        );
+        assert_eq!(
+            error("[joystick]\ncurve = exponential(300\n"),
+            (3, 1, ParseErrorKind::Expected("')'"))
+        );
+        let error = parse("[joystick]\nx_min = 5000\n").err().unwrap();
+        assert_eq!(format!("{}", error), "2:9: number out of range");
+    }
+
+    #[test]
+    fn settings_are_checked_together() {
+        assert_eq!(
+            error("[joystick]\nx_center = 500\nx_min = 600\n"),
+            (3, 1, ParseErrorKind::Invalid("x_min < x_center < x_max"))
+        );
+        assert_eq!(
+            error("[joystick]\ny_min = 10\ny_max = 10\ny_center = 10\n"),
+            (4, 1, ParseErrorKind::Invalid("y_min < y_center < y_max"))
+        );
+        assert_eq!(
+            error("[wasd]\nengage = 200\nrelease = 300\n"),
+            (3, 1, ParseErrorKind::Invalid("release <= engage"))
+        );
+        // only the finished file counts, not the order the fields come in
+        let keymap = parse("[wasd]\nrelease = 500\nengage = 600\n").unwrap();
+        assert_eq!((keymap.wasd.engage, keymap.wasd.release), (600, 500));
+        let keymap = parse("[joystick]\nx_min = 900\nx_max = 1000\nx_center = 950\n").unwrap();
+        assert!(keymap.joy_x_calibration().is_valid());
+    }
+}
@@ -0,0 +1,145 @@
+/// Maximum number of LEDs a single physical input can light up.
+pub const MAX_LEDS_PER_INPUT: usize = 2;
+
+/// LED indices lit by one physical input, unused slots are `None`.
+pub type LedIndices = [Option<u8>; MAX_LEDS_PER_INPUT];
+
+pub const NO_LEDS: LedIndices = [None; MAX_LEDS_PER_INPUT];
+
+pub const fn one_led(index: u8) -> LedIndices {
+    [Some(index), None]
+}
+
+pub const fn two_leds(first: u8, second: u8) -> LedIndices {
+    [Some(first), Some(second)]
+}
+
+/// Byte order expected by the LED chain on the wire.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorOrder {
+    Grb,
+    Rgb,
+    /// RGB plus a dedicated white channel, which takes over the part common to all three colors
+    Rgbw,
+}
+
+impl ColorOrder {
+    pub const fn channels(self) -> usize {
+        match self {
+            ColorOrder::Grb | ColorOrder::Rgb => 3,
+            ColorOrder::Rgbw => 4,
+        }
+    }
+
+    /// Rearranges an RGB color into wire order. Only the first `channels()` bytes are meaningful.
+    pub fn arrange(self, color: [u8; 3]) -> [u8; 4] {
+        match self {
+            ColorOrder::Grb => [color[1], color[0], color[2], 0],
+            ColorOrder::Rgb => [color[0], color[1], color[2], 0],
+            ColorOrder::Rgbw => {
+                let white = color[0].min(color[1]).min(color[2]);
+                [color[0] - white, color[1] - white, color[2] - white, white]
+            }
+        }
+    }
+}
+
+/// A physical input on the keypad that can have LEDs attached to it.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Input {
+    /// Index into `Keymap::key_mappings` (0-20)
+    Key(usize),
+    Joystick,
+    Wheel,
+}
+
+/// Board-level table linking physical inputs to positions in the LED chain.
+pub struct LedMap {
+    pub keys: [LedIndices; 21],
+    pub joystick: LedIndices,
+    pub wheel: LedIndices,
+}
+
+impl LedMap {
+    /// Checks that every index in the table is within a chain of `led_count` LEDs.
+    pub const fn fits(&self, led_count: usize) -> bool {
+        let mut i = 0;
+        while i < self.keys.len() {
+            if !indices_fit(&self.keys[i], led_count) {
+                return false;
+            }
+            i += 1;
+        }
+        indices_fit(&self.joystick, led_count) && indices_fit(&self.wheel, led_count)
+    }
+
+    pub fn leds_for(&self, input: Input) -> impl Iterator<Item = usize> + '_ {
+        let indices = match input {
+            Input::Key(i) => self.keys.get(i).unwrap_or(&NO_LEDS),
+            Input::Joystick => &self.joystick,
+            Input::Wheel => &self.wheel,
+        };
+        indices.iter().flatten().map(|i| *i as usize)
+    }
+}
+
+const fn indices_fit(indices: &LedIndices, led_count: usize) -> bool {
+    let mut i = 0;
+    while i < indices.len() {
+        if let Some(index) = indices[i] {
+            if index as usize >= led_count {
+                return false;
+            }
+        }
+        i += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> LedMap {
+        LedMap {
+            keys: [one_led(0); 21],
+            joystick: two_leds(1, 2),
+            wheel: NO_LEDS,
+        }
+    }
+
+    #[test]
+    fn fits_checks_every_index() {
+        assert!(map().fits(3));
+        assert!(!map().fits(2));
+        let mut far_key = map();
+        far_key.keys[20] = one_led(9);
+        assert!(far_key.fits(10));
+        assert!(!far_key.fits(9));
+    }
+
+    #[test]
+    fn leds_for_lists_attached_leds() {
+        let map = map();
+        assert!(map.leds_for(Input::Key(3)).eq([0]));
+        assert!(map.leds_for(Input::Joystick).eq([1, 2]));
+        assert_eq!(map.leds_for(Input::Wheel).count(), 0);
+        assert_eq!(map.leds_for(Input::Key(21)).count(), 0);
+    }
+
+    #[test]
+    fn color_orders() {
+        assert_eq!(ColorOrder::Grb.channels(), 3);
+        assert_eq!(ColorOrder::Rgb.channels(), 3);
+        assert_eq!(ColorOrder::Rgbw.channels(), 4);
+        assert_eq!(ColorOrder::Grb.arrange([1, 2, 3]), [2, 1, 3, 0]);
+        assert_eq!(ColorOrder::Rgb.arrange([1, 2, 3]), [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn rgbw_moves_the_common_part_to_white() {
+        assert_eq!(ColorOrder::Rgbw.arrange([200, 100, 50]), [150, 50, 0, 50]);
+        assert_eq!(ColorOrder::Rgbw.arrange([255, 255, 255]), [0, 0, 0, 255]);
+        assert_eq!(ColorOrder::Rgbw.arrange([255, 0, 0]), [255, 0, 0, 0]);
+    }
+}
@@ -1,2 +1,18 @@
-#![no_std]
-pub mod keymap_common;
\ No newline at end of file
+#![cfg_attr(not(test), no_std)]
+pub mod color;
+pub mod config_protocol;
+pub mod crash_log;
+pub mod default_keymap;
+pub mod idle;
+pub mod joystick;
+pub mod keymap_common;
+pub mod keymap_macro;
+pub mod keymap_text;
+pub mod led_map;
+pub mod log_ring;
+pub mod report_sender;
+pub mod shell;
+pub mod storage;
+pub mod usb_lifecycle;
+pub mod via;
+pub mod wasd;
@@ -0,0 +1,222 @@
+use core::fmt::{self, Write};
+
+// Log records kept as text in a fixed ring. Every byte ever written has a position, a u32 that
+// only grows (wrapping after 4 GiB), so any number of readers can follow the log by remembering
+// where they stopped. A reader that falls more than N bytes behind skips ahead to the oldest whole
+// line still held.
+
+/// Something the log can be read from, a position at a time.
+pub trait LogRead {
+    /// Copies bytes from position `from` onwards into `buf`. Returns the position of the first
+    /// byte copied, later than `from` if those bytes were overwritten, and how many were copied.
+    /// After skipping ahead the copy starts at a line, never partway through one.
+    fn read(&self, from: u32, buf: &mut [u8]) -> (u32, usize);
+}
+
+pub struct LogRing<const N: usize> {
+    data: [u8; N],
+    /// Position one past the newest byte
+    end: u32,
+    /// Bytes held, at most N
+    held: usize,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> LogRing<N> {
+        LogRing {
+            data: [0; N],
+            end: 0,
+            held: 0,
+        }
+    }
+
+    /// Position of the oldest byte held
+    pub fn start(&self) -> u32 {
+        self.end.wrapping_sub(self.held as u32)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.data[self.end as usize % N] = *byte;
+            self.end = self.end.wrapping_add(1);
+        }
+        self.held = (self.held + bytes.len()).min(N);
+    }
+
+    /// Position of the oldest byte that starts a line. Once the ring has wrapped, the line holding
+    /// the oldest byte may have lost its beginning, so that line is passed over.
+    fn oldest_line(&self) -> u32 {
+        let start = self.start();
+        if self.held < N {
+            return start;
+        }
+        (0..self.held as u32)
+            .map(|i| start.wrapping_add(i))
+            .find(|&position| self.data[position as usize % N] == b'\n')
+            .map_or(start, |line_end| line_end.wrapping_add(1))
+    }
+
+    /// Writes out the newest `lines` lines, with anything outside printable ASCII as `?`.
+    pub fn write_last_lines<W: Write>(&self, lines: usize, out: &mut W) -> fmt::Result {
+        if lines == 0 {
+            return Ok(());
+        }
+        // walk back past `lines` line ends, not counting the one closing the newest line
+        let mut start = None;
+        let mut seen = 0;
+        let mut position = self.end;
+        while position != self.start() {
+            position = position.wrapping_sub(1);
+            if self.data[position as usize % N] == b'\n' && position != self.end.wrapping_sub(1) {
+                seen += 1;
+                start = Some(position.wrapping_add(1));
+                if seen == lines {
+                    break;
+                }
+            }
+        }
+        // fewer lines than asked for: all of them, unless the oldest has been partly overwritten
+        if seen < lines && self.held < N {
+            start = Some(self.start());
+        }
+        let Some(mut start) = start else {
+            return Ok(());
+        };
+        while start != self.end {
+            let byte = self.data[start as usize % N];
+            match byte {
+                b' '..=b'~' | b'\r' | b'\n' => out.write_char(byte as char)?,
+                _ => out.write_char('?')?,
+            }
+            start = start.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    /// Appends one record as a line: `[seconds.millis] LEVEL message`.
+    pub fn record(&mut self, uptime_us: u64, level: log::Level, args: &fmt::Arguments) {
+        let millis = uptime_us / 1000;
+        let _ = write!(
+            self,
+            "[{:6}.{:03}] {:<5} {}\r\n",
+            millis / 1000,
+            millis % 1000,
+            level,
+            args
+        );
+    }
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogRead for LogRing<N> {
+    fn read(&self, from: u32, buf: &mut [u8]) -> (u32, usize) {
+        let behind = self.end.wrapping_sub(from) as usize;
+        let from = if behind > self.held {
+            self.oldest_line()
+        } else {
+            from
+        };
+        let len = (self.end.wrapping_sub(from) as usize).min(buf.len());
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.data[from.wrapping_add(i as u32) as usize % N];
+        }
+        (from, len)
+    }
+}
+
+impl<const N: usize> Write for LogRing<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all<const N: usize>(ring: &LogRing<N>, from: u32) -> (u32, String) {
+        let mut buf = [0; 64];
+        let (start, len) = ring.read(from, &mut buf);
+        (start, String::from_utf8(buf[..len].to_vec()).unwrap())
+    }
+
+    fn last_lines<const N: usize>(ring: &LogRing<N>, lines: usize) -> String {
+        let mut out = String::new();
+        ring.write_last_lines(lines, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn reads_follow_the_log_before_it_wraps() {
+        let mut ring = LogRing::<32>::new();
+        ring.write(b"one\r\ntwo\r\n");
+        assert_eq!(read_all(&ring, 0), (0, "one\r\ntwo\r\n".into()));
+
+        // a short buffer, then on from where it stopped
+        let mut buf = [0; 4];
+        assert_eq!(ring.read(0, &mut buf), (0, 4));
+        assert_eq!(&buf, b"one\r");
+        assert_eq!(read_all(&ring, 4), (4, "\ntwo\r\n".into()));
+        assert_eq!(read_all(&ring, 10), (10, "".into()));
+
+        ring.write(b"three\r\n");
+        assert_eq!(read_all(&ring, 10), (10, "three\r\n".into()));
+    }
+
+    #[test]
+    fn readers_left_behind_skip_to_the_oldest_whole_line() {
+        let mut ring = LogRing::<16>::new();
+        ring.write(b"first\r\nsecond\r\nthird\r\n");
+        // "first" and the "\r" after it are gone, the "\n" is the oldest byte held
+        assert_eq!(ring.start(), 6);
+        assert_eq!(read_all(&ring, 0), (7, "second\r\nthird\r\n".into()));
+        // a reader that kept up isn't moved
+        assert_eq!(read_all(&ring, 15), (15, "third\r\n".into()));
+
+        // positions keep counting past the 4 GiB wrap
+        let mut ring = LogRing::<16>::new();
+        ring.end = u32::MAX - 3;
+        ring.write(b"abc\r\nde\r\n");
+        assert_eq!(
+            read_all(&ring, u32::MAX - 3),
+            (u32::MAX - 3, "abc\r\nde\r\n".into())
+        );
+    }
+
+    #[test]
+    fn last_lines_are_whole() {
+        let mut ring = LogRing::<64>::new();
+        assert_eq!(last_lines(&ring, 3), "");
+        ring.write(b"one\r\ntwo\r\nthree\r\n");
+        assert_eq!(last_lines(&ring, 0), "");
+        assert_eq!(last_lines(&ring, 2), "two\r\nthree\r\n");
+        // more than are stored
+        assert_eq!(last_lines(&ring, 5), "one\r\ntwo\r\nthree\r\n");
+
+        // once wrapped, the oldest line has lost its start and is left out
+        let mut ring = LogRing::<16>::new();
+        ring.write(b"first\r\nsecond\r\nthird\r\n");
+        assert_eq!(last_lines(&ring, 5), "second\r\nthird\r\n");
+        assert_eq!(last_lines(&ring, 1), "third\r\n");
+    }
+
+    #[test]
+    fn unprintable_bytes_are_masked() {
+        let mut ring = LogRing::<32>::new();
+        ring.write(b"bad\x00byte \xc3\xa9\r\n");
+        assert_eq!(last_lines(&ring, 1), "bad?byte ??\r\n");
+    }
+
+    #[test]
+    fn records_carry_the_uptime_and_level() {
+        let mut ring = LogRing::<64>::new();
+        ring.record(12_345_678, log::Level::Warn, &format_args!("low {}", 1));
+        assert_eq!(last_lines(&ring, 1), "[    12.345] WARN  low 1\r\n");
+    }
+}
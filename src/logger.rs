@@ -0,0 +1,67 @@
+use crate::clock;
+use crate::log_ring::{LogRead, LogRing};
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::{self, Mutex};
+use log::{LevelFilter, Log, Metadata, Record};
+
+// `log` output goes into a ring in RAM rather than straight out, so logging never waits on the
+// host. main.rs copies it to the serial console and GET_LOG hands it to the configuration tool,
+// each following the ring with its own position.
+pub const LOG_BUFFER_LEN: usize = 4096;
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+struct RingLogger {
+    ring: Mutex<RefCell<LogRing<LOG_BUFFER_LEN>>>,
+}
+
+static LOGGER: RingLogger = RingLogger {
+    ring: Mutex::new(RefCell::new(LogRing::new())),
+};
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = clock::micros();
+        interrupt::free(|cs| {
+            // a record whose arguments log again while being formatted loses the inner record
+            if let Ok(mut ring) = self.ring.borrow(cs).try_borrow_mut() {
+                ring.record(now, record.level(), record.args());
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger. Call once, after `clock::init` so records get real timestamps.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(DEFAULT_LEVEL);
+    }
+}
+
+/// Reads the log, holding it only for the copy.
+pub struct Reader;
+
+impl LogRead for Reader {
+    fn read(&self, from: u32, buf: &mut [u8]) -> (u32, usize) {
+        interrupt::free(|cs| LOGGER.ring.borrow(cs).borrow().read(from, buf))
+    }
+}
+
+/// Writes the newest `lines` lines of the log to `out`, unless the log is busy, as it may be if
+/// the caller is the panic handler.
+pub fn write_last_lines<W: Write>(lines: usize, out: &mut W) {
+    interrupt::free(|cs| {
+        if let Ok(ring) = LOGGER.ring.borrow(cs).try_borrow() {
+            let _ = ring.write_last_lines(lines, out);
+        }
+    });
+}
@@ -1,20 +1,43 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
+mod board_config;
+mod clock;
+#[allow(dead_code)]
+mod color;
+#[allow(dead_code)]
+mod config_protocol;
+#[allow(dead_code)]
+mod crash_log;
+mod default_keymap;
+mod flash;
+mod idle;
+#[allow(dead_code)]
+mod joystick;
 mod keymap;
+#[allow(dead_code)]
 mod keymap_common;
+#[allow(dead_code)]
+mod led_map;
+mod log_ring;
+mod logger;
+mod report_sender;
+mod shell;
+#[allow(dead_code)]
+mod storage;
+mod usb_lifecycle;
+mod via;
+#[allow(dead_code)]
+mod wasd;
 mod ws2812;
 
-use bsp::{
-    board,
-    hal::{adc::ResolutionBits, iomuxc},
-};
+use bsp::hal::{adc::ResolutionBits, iomuxc};
 use imxrt_usbd::BusAdapter;
 use teensy4_bsp as bsp;
 use teensy4_panic as _;
 use usb_device::{
     bus::UsbBusAllocator,
-    class_prelude::UsbBus,
     prelude::{UsbDeviceBuilder, UsbVidPid},
     UsbError,
 };
@@ -23,9 +46,32 @@ use usbd_hid::{
     descriptor::generator_prelude::*,
     hid_class::{HidClassSettings, ProtocolModeConfig},
 };
-//use usbd_serial::SerialPort;
+use usbd_serial::SerialPort;
+
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::config_protocol::{REPORT_LEN, USB_PID, USB_VID};
+use crate::crash_log::CrashRecord;
+use crate::flash::TeensyFlash;
+use crate::idle::IdleTracker;
+use crate::joystick::{CalibrationPhase, Calibrator};
 use crate::keymap::{Keymap, KeymapIOPoints, KeymapState};
+use crate::keymap_common::{InputName, INPUT_COUNT};
+use crate::log_ring::LogRead;
+use crate::report_sender::{ReportSender, SendResult};
+use crate::shell::{Calibrate, Command, OutBuffer, Shell};
+use crate::storage::{Flash, KeymapStore};
+use crate::usb_lifecycle::{LinkState, Transition, UsbLifecycle};
+use crate::via::ViaState;
+
+/// Console output waiting for the host
+const CONSOLE_BUFFER_LEN: usize = 2048;
+/// Log lines kept with a crash record
+const CRASH_LOG_LINES: usize = 8;
+/// Crashing this soon after restarting from a crash blinks SOS rather than resetting again
+const CRASH_LOOP_US: u64 = 10_000_000;
 
 #[gen_hid_descriptor(
     (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = MOUSE) = {
@@ -93,6 +139,24 @@ struct ConsumerReport {
     pub consumer_keycode: u16,
 }
 
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = 0xFF60, usage = 0x61) = {
+        (usage = 0x62,) = {
+            #[item_settings data,variable,absolute] input_buffer=input;
+        };
+        (usage = 0x63,) = {
+            #[item_settings data,variable,absolute] output_buffer=output;
+        };
+    }
+)]
+struct ConfigReport {
+    pub input_buffer: [u8; 32],
+    pub output_buffer: [u8; 32],
+}
+
+// gen_hid_descriptor only takes literal array lengths
+const _: () = assert!(REPORT_LEN == 32);
+
 pub struct KeypadReport {
     pub mouse_buttons: u8,
     pub wheel: i8,
@@ -140,10 +204,154 @@ static PIN_CONFIG: [Option<iomuxc::PullKeeper>; 31] = [
 static EP_MEMORY: imxrt_usbd::EndpointMemory<4096> = imxrt_usbd::EndpointMemory::new();
 static EP_STATE: imxrt_usbd::EndpointState = imxrt_usbd::EndpointState::max_endpoints();
 
+// Startup code leaves `.uninit` alone, so a crash record written here is still there after reset
+#[link_section = ".uninit.crash_record"]
+static mut CRASH_RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+static RESTARTED_AFTER_CRASH: AtomicBool = AtomicBool::new(false);
+
+// Only the panic handler and start-up touch the record, never at the same time. Neither makes a
+// reference to it before it holds a value.
+fn crash_record_ptr() -> *mut CrashRecord {
+    core::ptr::addr_of_mut!(CRASH_RECORD).cast::<CrashRecord>()
+}
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    log::error!("{:?}", info);
-    teensy4_panic::sos();
+    let record = unsafe {
+        crash_record_ptr().write(CrashRecord::new());
+        &mut *crash_record_ptr()
+    };
+    let _ = writeln!(record, "{}", info);
+    let _ = writeln!(record, "last log lines:");
+    logger::write_last_lines(CRASH_LOG_LINES, record);
+    record.seal();
+    if RESTARTED_AFTER_CRASH.load(Ordering::Relaxed) && clock::micros() < CRASH_LOOP_US {
+        teensy4_panic::sos();
+    }
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+// USB_OTG1's USBCMD register, whose Run/Stop bit connects the D+ pull-up in device mode
+const USB1_USBCMD: *mut u32 = 0x402E_0140 as *mut u32;
+const USBCMD_RS: u32 = 1 << 0;
+
+// USB_OTG1's PORTSC1 register, whose Force Port Resume bit signals remote wakeup to the host
+const USB1_PORTSC1: *mut u32 = 0x402E_0184 as *mut u32;
+const PORTSC1_FPR: u32 = 1 << 6;
+const PORTSC1_SUSP: u32 = 1 << 7;
+/// Write-one-to-clear status bits, not to be set back when modifying the register
+const PORTSC1_W1C: u32 = (1 << 1) | (1 << 3) | (1 << 5);
+
+/// Starts resume signalling on a suspended bus; the controller ends it by itself.
+fn signal_remote_wakeup() {
+    unsafe {
+        let portsc = core::ptr::read_volatile(USB1_PORTSC1);
+        core::ptr::write_volatile(USB1_PORTSC1, (portsc & !PORTSC1_W1C) | PORTSC1_FPR);
+    }
+}
+
+/// Whether the host has suspended the bus. imxrt-usbd never reports suspend to usb-device, so
+/// this reads the port's status instead.
+fn bus_suspended() -> bool {
+    unsafe { core::ptr::read_volatile(USB1_PORTSC1) & PORTSC1_SUSP != 0 }
+}
+
+/// Connects to or disconnects from the bus. imxrt-usbd can't force a reset, so a stuck bus is
+/// recovered by dropping off it: once attached again, the host resets the bus and enumerates anew.
+fn set_usb_attached(attached: bool) {
+    unsafe {
+        let usbcmd = core::ptr::read_volatile(USB1_USBCMD);
+        let usbcmd = if attached {
+            usbcmd | USBCMD_RS
+        } else {
+            usbcmd & !USBCMD_RS
+        };
+        core::ptr::write_volatile(USB1_USBCMD, usbcmd);
+    }
+}
+
+/// Reboots into the Teensy's HalfKay bootloader, ready for a new firmware image.
+fn enter_bootloader() -> ! {
+    unsafe { core::arch::asm!("bkpt #251") };
+    unreachable!("the bootloader does not return")
+}
+
+fn write_held<W: Write>(out: &mut W, pressed: u32) {
+    let _ = write!(out, "held:");
+    for input in (0..INPUT_COUNT).filter(|i| pressed & (1 << i) != 0) {
+        let _ = write!(out, " {}", InputName(input));
+    }
+    let _ = write!(out, "\r\n");
+}
+
+/// Carries out a console command. Reboot and bootloader entry are handed back, to happen once
+/// the console has sent everything before them.
+fn run_shell_command<F: Flash, W: Write>(
+    command: Command,
+    out: &mut W,
+    keymap: &mut Keymap,
+    keymap_state: &mut KeymapState,
+    keymap_store: &mut KeymapStore<F>,
+    calibrator: &mut Calibrator,
+    monitor: &mut bool,
+) -> Option<Command> {
+    let live = *keymap_state.live();
+    match command {
+        Command::Help => {
+            for line in shell::HELP.lines() {
+                let _ = write!(out, "{}\r\n", line);
+            }
+        }
+        Command::Status => {
+            let _ = write!(
+                out,
+                "layer {}, WASD {}, joystick {} {}\r\n",
+                live.layer,
+                if live.wasd_mode { "on" } else { "off" },
+                live.joy_x,
+                live.joy_y
+            );
+            write_held(out, live.pressed);
+        }
+        Command::Monitor(on) => {
+            *monitor = on.unwrap_or(!*monitor);
+            let _ = write!(out, "monitor {}\r\n", if *monitor { "on" } else { "off" });
+        }
+        Command::Layer(layer) => keymap_state.set_layer(layer),
+        // the routine reports its progress through the log
+        Command::Calibrate(Calibrate::Start) => calibrator.start(),
+        Command::Calibrate(Calibrate::Finish) => calibrator.finish(),
+        Command::Calibrate(setting) => {
+            // checked as the configuration protocol checks it, so neither saves a broken axis
+            if let Err(e) = setting.apply(keymap, (live.joy_x, live.joy_y)) {
+                let _ = write!(out, "{}\r\n", e);
+                return None;
+            }
+            let _ = write!(
+                out,
+                "x {}..{}..{}, y {}..{}..{}, rotation {}\r\n",
+                keymap.joy_x_min,
+                keymap.joy_x_center,
+                keymap.joy_x_max,
+                keymap.joy_y_min,
+                keymap.joy_y_center,
+                keymap.joy_y_max,
+                keymap.joy_x_y_rotation
+            );
+            if let Err(e) = keymap_store.save(keymap) {
+                let _ = write!(out, "failed to save: {:?}\r\n", e);
+            }
+        }
+        Command::LedTest => keymap_state.start_led_test(),
+        Command::Log(level) => {
+            if let Some(level) = level {
+                log::set_max_level(level);
+            }
+            let _ = write!(out, "log level {}\r\n", log::max_level());
+        }
+        Command::Reboot | Command::Bootloader => return Some(command),
+    }
+    None
 }
 
 macro_rules! configure_pin {
@@ -163,12 +371,13 @@ macro_rules! configure_pin {
 fn main() -> ! {
     // These are peripheral instances. Let the board configure these for us.
     // This function can only be called once!
-    let instances = board::instances();
+    let instances = bsp::board::instances();
 
     // Driver resources that are configured by the board. For more information,
     // see the `board` documentation.
-    let board::Resources {
+    let bsp::board::Resources {
         pit,
+        gpt1,
         mut pins,
         mut adc1,
         usb,
@@ -177,7 +386,19 @@ fn main() -> ! {
         mut gpio3,
         mut gpio4,
         ..
-    } = board::t41(instances);
+    } = bsp::board::t41(instances);
+
+    clock::init(gpt1);
+    logger::init();
+    log::info!("padtarust {} starting", env!("CARGO_PKG_VERSION"));
+    let mut crash = unsafe { CrashRecord::recover(crash_record_ptr()) };
+    if let Some(report) = crash.take() {
+        RESTARTED_AFTER_CRASH.store(true, Ordering::Relaxed);
+        log::error!("restarted after a crash:");
+        for line in report.lines() {
+            log::error!("  {}", line.trim_end());
+        }
+    }
 
     // Set up pullup/pulldown
     configure_pin!(0, pins);
@@ -220,12 +441,14 @@ fn main() -> ! {
     let mut keymap_io =
         KeymapIOPoints::new(&mut gpio1, &mut gpio2, &mut gpio3, &mut gpio4, pins, pit.1);
 
-    // set up keymap TODO: load from flash (teensy4-fcb?)
-    let mut keymap = Keymap::default();
+    // set up keymap from the newest valid copy in flash
+    let mut keymap_store = KeymapStore::new(TeensyFlash::new());
+    let mut keymap = keymap_store.load_or_default();
     let mut keymap_state = KeymapState::default();
 
     // Polling
-    let mut delay = bsp::hal::timer::Blocking::<_, { board::PERCLK_FREQUENCY }>::from_pit(pit.0);
+    let mut delay =
+        bsp::hal::timer::Blocking::<_, { bsp::board::PERCLK_FREQUENCY }>::from_pit(pit.0);
 
     // set up USB HID device
     let bus_adapter = BusAdapter::with_speed(usb, &EP_MEMORY, &EP_STATE, imxrt_usbd::Speed::High);
@@ -239,8 +462,9 @@ fn main() -> ! {
     let mut keyboard_class =
         HIDClass::new_ep_in_with_settings(&usb_alloc, KeyboardReport::desc(), 10, hid_settings);
     let mut consumer_class = HIDClass::new_ep_in(&usb_alloc, ConsumerReport::desc(), 10);
-    //let mut serial_port = SerialPort::new(&usb_alloc);
-    let mut keypad_dev = UsbDeviceBuilder::new(&usb_alloc, UsbVidPid(0x1209, 0x0001)) // TODO: fork pid.codes accordingly; finish stuff first
+    let mut config_class = HIDClass::new(&usb_alloc, ConfigReport::desc(), 10);
+    let mut serial_port = SerialPort::new(&usb_alloc);
+    let mut keypad_dev = UsbDeviceBuilder::new(&usb_alloc, UsbVidPid(USB_VID, USB_PID)) // TODO: fork pid.codes accordingly; finish stuff first
         .manufacturer("kitknacks")
         .product("padtarust keypad")
         .device_class(0x03)
@@ -248,138 +472,248 @@ fn main() -> ! {
         .max_power(500)
         .composite_with_iads()
         .max_packet_size_0(64)
+        .supports_remote_wakeup(true)
         .build();
 
-    loop {
-        if !keypad_dev.poll(&mut [
-            &mut mouse_class,
-            &mut joystick_class,
-            &mut keyboard_class,
-            &mut consumer_class,
-        ]) {
-            continue;
-        }
-        let state = keypad_dev.state();
-        if state == usb_device::device::UsbDeviceState::Configured {
-            break;
-        }
-    }
-    keypad_dev.bus().configure();
-
+    let mut usb = UsbLifecycle::new();
     let mut report_written = false;
-    let mut report = keymap_state.update(&mut adc1, &mut keymap_io, &mut keymap);
-    let mut mouse_written = false;
-    let mut joystick_written = false;
-    let mut keyboard_written = false;
-    let mut consumer_written = false;
+    let mut report = keymap_state.update(&mut adc1, &mut keymap_io, &mut keymap, clock::micros());
+    let mut mouse_sender = ReportSender::new("mouse");
+    let mut joystick_sender = ReportSender::new("joystick");
+    let mut keyboard_sender = ReportSender::new("keyboard");
+    let mut consumer_sender = ReportSender::new("consumer");
+    let mut config_response: Option<[u8; REPORT_LEN]> = None;
+    let mut via_state = ViaState::new();
+    let mut shell = Shell::new();
+    let mut console = OutBuffer::<CONSOLE_BUFFER_LEN>::new();
+    let mut monitor = false;
+    let mut monitor_pressed = 0;
+    let mut pending_reset: Option<Command> = None;
+    let mut log_position = 0u32;
+    let mut idle = IdleTracker::new(board_config::IDLE_CONFIG, clock::micros());
+    let mut last_scan = 0u64;
+    let mut calibrator = Calibrator::new();
+    let mut calibration_phase = CalibrationPhase::Idle;
 
     loop {
-        if report_written {
-            report = keymap_state.update(&mut adc1, &mut keymap_io, &mut keymap);
-            report_written = false;
-            mouse_written = false;
-            joystick_written = false;
-            keyboard_written = false;
-            consumer_written = false;
+        // read every pass, which also keeps the clock's wrap count current
+        let now = clock::micros();
+
+        match usb.update(keypad_dev.state(), bus_suspended(), now) {
+            Transition::Configured => {
+                log::info!("USB configured");
+                // the endpoints need setting up again each time the host configures us
+                keypad_dev.bus().configure();
+                report_written = true;
+            }
+            Transition::Resumed => {
+                log::info!("USB resumed");
+                // whatever was waiting from before the suspend is stale
+                report_written = true;
+            }
+            Transition::Suspended => log::info!("USB suspended"),
+            Transition::Reset => log::info!("USB reset by the host"),
+            Transition::None => {}
         }
 
-        if !mouse_written {
+        // scans slow down while nobody is using the pad
+        let scan_due = now.wrapping_sub(last_scan) >= idle.scan_interval_us();
+        let mut scanned = false;
+        if !usb.is_active() {
+            // nobody to report to, but keep scanning so a keypress can wake the host
+            if scan_due {
+                report = keymap_state.update(&mut adc1, &mut keymap_io, &mut keymap, now);
+                scanned = true;
+            }
+            if keymap_state.live().pressed != 0
+                && usb.request_wakeup(keypad_dev.remote_wakeup_enabled(), now)
+            {
+                log::info!("waking the host");
+                signal_remote_wakeup();
+            }
+        } else {
+            if report_written && scan_due {
+                report = keymap_state.update(&mut adc1, &mut keymap_io, &mut keymap, now);
+                scanned = true;
+                report_written = false;
+                for sender in [
+                    &mut mouse_sender,
+                    &mut joystick_sender,
+                    &mut keyboard_sender,
+                    &mut consumer_sender,
+                ] {
+                    sender.queue(now);
+                }
+            }
+
             let mouse_report = MouseReport {
                 mouse_buttons: report.mouse_buttons,
                 wheel: report.wheel,
             };
-            if let Err(e) = mouse_class.push_input(&mouse_report) {
-                match e {
-                    UsbError::WouldBlock => {
-                        mouse_written = false;
-                    }
-                    UsbError::BufferOverflow => {
-                        mouse_written = false;
-                    }
-                    _ => {
-                        panic!("Failed to write mouse report: {:?}", e);
-                    }
-                }
-            } else {
-                mouse_written = true;
-            }
-        }
-
-        if !joystick_written {
             let joystick_report = JoystickReport {
                 joy_buttons: report.joy_buttons,
                 x: report.x,
                 y: report.y,
             };
-            if let Err(e) = joystick_class.push_input(&joystick_report) {
-                match e {
-                    UsbError::WouldBlock => {
-                        joystick_written = false;
-                    }
-                    UsbError::BufferOverflow => {
-                        joystick_written = false;
-                    }
-                    _ => {
-                        panic!("Failed to write joystick report: {:?}", e);
-                    }
-                }
-            } else {
-                joystick_written = true;
-            }
-        }
-
-        if !keyboard_written {
             let keyboard_report = KeyboardReport {
                 modifier: report.modifier,
                 keycodes: report.keycodes,
             };
-            if let Err(e) = keyboard_class.push_input(&keyboard_report) {
-                match e {
-                    UsbError::WouldBlock => {
-                        keyboard_written = false;
-                    }
-                    UsbError::BufferOverflow => {
-                        keyboard_written = false;
+            let consumer_report = ConsumerReport {
+                consumer_keycode: report.consumer_keycode,
+            };
+            let results = [
+                mouse_sender.send(&mut mouse_class, &mouse_report, now),
+                joystick_sender.send(&mut joystick_class, &joystick_report, now),
+                keyboard_sender.send(&mut keyboard_class, &keyboard_report, now),
+                consumer_sender.send(&mut consumer_class, &consumer_report, now),
+            ];
+            if results.contains(&SendResult::ResetBus) {
+                log::error!("too many USB errors, reconnecting");
+                set_usb_attached(false);
+                // long enough for the host to see the disconnect
+                delay.block_ms(100);
+                set_usb_attached(true);
+            }
+        }
+
+        if scanned {
+            last_scan = now;
+            // a suspend the controller saw, see bus_suspended
+            let suspended = matches!(usb.state(), LinkState::Suspended | LinkState::Waking(_));
+            let wheel_moved = report.wheel != 0;
+            if let Some(level) = idle.update(keymap_state.live(), wheel_moved, suspended, now) {
+                log::debug!("power level {:?}", level);
+                keymap_state.set_led_brightness(idle.led_brightness());
+            }
+
+            if keymap_state.take_calibration_request() {
+                calibrator.advance();
+            }
+            let live = keymap_state.live();
+            let current = (keymap.joy_x_calibration(), keymap.joy_y_calibration());
+            if let Some((x, y)) = calibrator.sample(live.joy_x, live.joy_y, now, current) {
+                keymap.set_joy_calibration(x, y);
+                log::info!(
+                    "joystick calibrated: x {}..{}..{}, y {}..{}..{}",
+                    x.min,
+                    x.center,
+                    x.max,
+                    y.min,
+                    y.center,
+                    y.max
+                );
+                if let Err(e) = keymap_store.save(&keymap) {
+                    log::error!("failed to save keymap: {:?}", e);
+                }
+            }
+            if calibrator.phase() != calibration_phase {
+                calibration_phase = calibrator.phase();
+                match calibration_phase {
+                    CalibrationPhase::Centering => {
+                        log::info!("calibrating the joystick, leave it alone")
                     }
-                    _ => {
-                        panic!("Failed to write keyboard report: {:?}", e);
+                    CalibrationPhase::Sweeping => {
+                        log::info!("now move the joystick all the way round, then finish")
                     }
+                    CalibrationPhase::Idle => {}
                 }
-            } else {
-                keyboard_written = true;
             }
         }
 
-        if !consumer_written {
-            let consumer_report = ConsumerReport {
-                consumer_keycode: report.consumer_keycode,
-            };
-            if let Err(e) = consumer_class.push_input(&consumer_report) {
-                match e {
-                    UsbError::WouldBlock => {
-                        consumer_written = false;
-                    }
-                    UsbError::BufferOverflow => {
-                        consumer_written = false;
-                    }
-                    _ => {
-                        panic!("Failed to write consumer report: {:?}", e);
-                    }
+        // configuration requests, answered one at a time
+        if config_response.is_none() {
+            let mut request = [0u8; REPORT_LEN];
+            if let Ok(len) = config_class.pull_raw_output(&mut request) {
+                if len > 0 {
+                    config_response = Some(config_protocol::handle(
+                        &request,
+                        &mut keymap,
+                        &mut keymap_store,
+                        &mut via_state,
+                        keymap_state.live(),
+                        &mut calibrator,
+                        &logger::Reader,
+                    ));
+                }
+            }
+        }
+        if let Some(response) = config_response {
+            match config_class.push_raw_input(&response) {
+                Ok(_) => config_response = None,
+                Err(UsbError::WouldBlock) => {}
+                Err(e) => {
+                    log::warn!("dropping configuration response: {:?}", e);
+                    config_response = None;
                 }
-            } else {
-                consumer_written = true;
             }
         }
 
-        if mouse_written && joystick_written && keyboard_written && consumer_written {
+        if via_state.bootloader_requested && config_response.is_none() {
+            enter_bootloader();
+        }
+
+        // serial console
+        let mut received = [0u8; 64];
+        if let Ok(len) = serial_port.read(&mut received) {
+            for byte in &received[..len] {
+                let command = match shell.feed(*byte, &mut console) {
+                    Some(Ok(command)) => command,
+                    Some(Err(e)) => {
+                        let _ = write!(console, "{}\r\n{}", e, shell::PROMPT);
+                        continue;
+                    }
+                    None => continue,
+                };
+                pending_reset = run_shell_command(
+                    command,
+                    &mut console,
+                    &mut keymap,
+                    &mut keymap_state,
+                    &mut keymap_store,
+                    &mut calibrator,
+                    &mut monitor,
+                )
+                .or(pending_reset);
+                let _ = console.write_str(shell::PROMPT);
+            }
+        }
+        let pressed = keymap_state.live().pressed;
+        if monitor && pressed != monitor_pressed {
+            write_held(&mut console, pressed);
+        }
+        monitor_pressed = pressed;
+        let mut log_text = [0u8; 64];
+        let (start, len) = logger::Reader.read(log_position, &mut log_text);
+        log_position = start.wrapping_add(console.write_bytes(&log_text[..len]) as u32);
+        console.drain(|bytes| serial_port.write(bytes).unwrap_or(0));
+        match pending_reset {
+            Some(Command::Reboot) if console.is_empty() => cortex_m::peripheral::SCB::sys_reset(),
+            Some(Command::Bootloader) if console.is_empty() => enter_bootloader(),
+            _ => {}
+        }
+
+        if !(mouse_sender.is_pending()
+            || joystick_sender.is_pending()
+            || keyboard_sender.is_pending()
+            || consumer_sender.is_pending())
+        {
             report_written = true;
         }
 
+        if via_state.tick(now) {
+            if let Err(e) = keymap_store.save(&keymap) {
+                log::error!("failed to save keymap: {:?}", e);
+            }
+        }
+
         if !keypad_dev.poll(&mut [
             &mut mouse_class,
             &mut joystick_class,
             &mut keyboard_class,
             &mut consumer_class,
+            &mut config_class,
+            &mut serial_port,
         ]) {
             continue;
         }
@@ -0,0 +1,232 @@
+use usb_device::class_prelude::UsbBus;
+use usb_device::UsbError;
+use usbd_hid::descriptor::AsInputReport;
+use usbd_hid::hid_class::HIDClass;
+
+// Delivery of one kind of HID report, and what to do when the bus refuses it. A busy endpoint
+// is waited on, but not forever: a report the host hasn't taken after STALE_US is dropped so one
+// stuck interface can't hold up the others. Any other error drops the report and counts against
+// the bus; enough of them in a row and the caller should reset it so the host enumerates us anew.
+pub const STALE_US: u64 = 500_000;
+pub const RESET_AFTER_ERRORS: u16 = 8;
+
+/// Somewhere reports of type `R` can be pushed, normally a HID class on the USB bus.
+pub trait ReportSink<R> {
+    fn push(&mut self, report: &R) -> Result<usize, UsbError>;
+}
+
+impl<B: UsbBus, R: AsInputReport> ReportSink<R> for HIDClass<'_, B> {
+    fn push(&mut self, report: &R) -> Result<usize, UsbError> {
+        self.push_input(report)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SendResult {
+    /// Nothing left to send
+    Done,
+    /// The endpoint is busy, try again later
+    Waiting,
+    /// Too many errors in a row; drop off the bus and attach again
+    ResetBus,
+}
+
+pub struct ReportSender {
+    name: &'static str,
+    /// When the unsent report was queued
+    queued_at: Option<u64>,
+    /// Errors since the last report went through
+    errors_in_row: u16,
+    pub errors: u32,
+    pub dropped: u32,
+}
+
+impl ReportSender {
+    pub fn new(name: &'static str) -> ReportSender {
+        ReportSender {
+            name,
+            queued_at: None,
+            errors_in_row: 0,
+            errors: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Marks a new report as ready to send, replacing any unsent one.
+    pub fn queue(&mut self, now_us: u64) {
+        if self.queued_at.is_some() {
+            self.dropped += 1;
+        }
+        self.queued_at = Some(now_us);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.queued_at.is_some()
+    }
+
+    /// Tries to push the queued report, `report` being its current contents.
+    pub fn send<R, S: ReportSink<R>>(
+        &mut self,
+        sink: &mut S,
+        report: &R,
+        now_us: u64,
+    ) -> SendResult {
+        let Some(queued_at) = self.queued_at else {
+            return SendResult::Done;
+        };
+        match sink.push(report) {
+            Ok(_) => {
+                self.queued_at = None;
+                self.errors_in_row = 0;
+                SendResult::Done
+            }
+            Err(UsbError::WouldBlock) if now_us.wrapping_sub(queued_at) < STALE_US => {
+                SendResult::Waiting
+            }
+            Err(UsbError::WouldBlock) => {
+                log::debug!("dropping stale {} report", self.name);
+                self.queued_at = None;
+                self.dropped += 1;
+                SendResult::Done
+            }
+            Err(e) => {
+                self.queued_at = None;
+                self.dropped += 1;
+                self.errors += 1;
+                self.errors_in_row += 1;
+                log::warn!(
+                    "failed to send {} report ({} in a row, {} in all): {:?}",
+                    self.name,
+                    self.errors_in_row,
+                    self.errors,
+                    e
+                );
+                if self.errors_in_row >= RESET_AFTER_ERRORS {
+                    self.errors_in_row = 0;
+                    SendResult::ResetBus
+                } else {
+                    SendResult::Done
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // UsbError is neither Copy nor Clone, so scripts name the answer instead
+    #[derive(Copy, Clone)]
+    enum Answer {
+        Sent,
+        Busy,
+        Broken,
+    }
+
+    /// Answers pushes from a script, sending once it runs out
+    #[derive(Default)]
+    struct FakeSink {
+        script: VecDeque<Answer>,
+        sent: Vec<u8>,
+    }
+
+    impl FakeSink {
+        fn new(script: &[Answer]) -> FakeSink {
+            FakeSink {
+                script: script.iter().copied().collect(),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl ReportSink<u8> for FakeSink {
+        fn push(&mut self, report: &u8) -> Result<usize, UsbError> {
+            match self.script.pop_front().unwrap_or(Answer::Sent) {
+                Answer::Sent => {
+                    self.sent.push(*report);
+                    Ok(1)
+                }
+                Answer::Busy => Err(UsbError::WouldBlock),
+                Answer::Broken => Err(UsbError::InvalidState),
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_queued_sends_nothing() {
+        let mut sender = ReportSender::new("test");
+        let mut sink = FakeSink::default();
+        assert_eq!(sender.send(&mut sink, &1, 0), SendResult::Done);
+        assert!(sink.sent.is_empty());
+    }
+
+    #[test]
+    fn busy_endpoint_waits_then_sends_the_latest_report() {
+        let mut sender = ReportSender::new("test");
+        let mut sink = FakeSink::new(&[Answer::Busy, Answer::Busy]);
+        sender.queue(1_000);
+        assert_eq!(sender.send(&mut sink, &1, 1_000), SendResult::Waiting);
+        assert_eq!(sender.send(&mut sink, &2, 2_000), SendResult::Waiting);
+        assert!(sender.is_pending());
+        assert_eq!(sender.send(&mut sink, &3, 3_000), SendResult::Done);
+        assert!(!sender.is_pending());
+        assert_eq!(sink.sent, [3]);
+        assert_eq!((sender.errors, sender.dropped), (0, 0));
+    }
+
+    #[test]
+    fn stale_report_is_dropped() {
+        let mut sender = ReportSender::new("test");
+        let mut sink = FakeSink::new(&[Answer::Busy, Answer::Busy]);
+        sender.queue(1_000);
+        let stale = 1_000 + STALE_US;
+        assert_eq!(sender.send(&mut sink, &1, stale - 1), SendResult::Waiting);
+        assert_eq!(sender.send(&mut sink, &1, stale), SendResult::Done);
+        assert!(!sender.is_pending());
+        assert!(sink.sent.is_empty());
+        assert_eq!((sender.errors, sender.dropped), (0, 1));
+    }
+
+    #[test]
+    fn requeueing_drops_the_unsent_report() {
+        let mut sender = ReportSender::new("test");
+        sender.queue(0);
+        sender.queue(10);
+        assert_eq!(sender.dropped, 1);
+        assert!(sender.is_pending());
+    }
+
+    #[test]
+    fn errors_in_a_row_reset_the_bus() {
+        let mut sender = ReportSender::new("test");
+        let mut sink = FakeSink::new(&[Answer::Broken; RESET_AFTER_ERRORS as usize + 1]);
+        for now in 1..RESET_AFTER_ERRORS as u64 {
+            sender.queue(now);
+            assert_eq!(sender.send(&mut sink, &0, now), SendResult::Done);
+        }
+        sender.queue(100);
+        assert_eq!(sender.send(&mut sink, &0, 100), SendResult::ResetBus);
+        assert_eq!(sender.errors, RESET_AFTER_ERRORS as u32);
+        assert_eq!(sender.dropped, RESET_AFTER_ERRORS as u32);
+
+        // the count starts again after a reset
+        sender.queue(200);
+        assert_eq!(sender.send(&mut sink, &0, 200), SendResult::Done);
+    }
+
+    #[test]
+    fn a_sent_report_clears_the_error_count() {
+        let mut sender = ReportSender::new("test");
+        let mut script = vec![Answer::Broken; RESET_AFTER_ERRORS as usize - 1];
+        script.push(Answer::Sent);
+        script.extend(vec![Answer::Broken; RESET_AFTER_ERRORS as usize - 1]);
+        let mut sink = FakeSink::new(&script);
+        for now in 0..script.len() as u64 {
+            sender.queue(now);
+            assert_eq!(sender.send(&mut sink, &0, now), SendResult::Done);
+        }
+        assert_eq!(sink.sent, [0]);
+    }
+}
@@ -0,0 +1,489 @@
+use crate::keymap_common::{Keymap, LAYER_COUNT};
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use log::LevelFilter;
+
+// Line-oriented console on the CDC serial port. Received bytes go through `Shell::feed`, which
+// echoes them and parses each finished line into a `Command`. Carrying commands out is left to
+// main.rs, since nearly all of them need hardware.
+pub const LINE_LEN: usize = 64;
+pub const PROMPT: &str = "> ";
+pub const HELP: &str = "\
+commands:
+  status                 layer, WASD mode, raw joystick and held inputs
+  monitor [on|off]       print held inputs whenever they change
+  layer N                switch to layer N
+  calibrate              calibrate the joystick: leave it alone, then sweep it all the way round
+  calibrate done         end the sweep early
+  calibrate center       take the joystick's current position as its centre
+  calibrate NAME VALUE   set x_center, y_center, x_min, x_max, y_min, y_max or rotation
+  ledtest                sweep every LED through the colour wheel
+  log [LEVEL]            show or set the log level: off, error, warn, info, debug, trace
+  reboot                 restart the firmware
+  bootloader             wait for a new firmware image
+";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CalibrationValue {
+    XCenter,
+    YCenter,
+    XMin,
+    XMax,
+    YMin,
+    YMax,
+    Rotation,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Calibrate {
+    /// Runs the routine in joystick::Calibrator
+    Start,
+    /// Ends the sweep early
+    Finish,
+    /// Takes the joystick's current position as its centre
+    Center,
+    Set(CalibrationValue, u16),
+}
+
+impl Calibrate {
+    /// Carries out Center or Set on `keymap`, with the joystick now at `joystick`. Leaves the
+    /// keymap alone if either axis would end up without min < center < max.
+    pub fn apply(self, keymap: &mut Keymap, joystick: (u16, u16)) -> Result<(), ShellError> {
+        let (mut x, mut y) = (keymap.joy_x_calibration(), keymap.joy_y_calibration());
+        let mut rotation = keymap.joy_x_y_rotation;
+        match self {
+            Calibrate::Center => (x.center, y.center) = joystick,
+            Calibrate::Set(CalibrationValue::XCenter, value) => x.center = value,
+            Calibrate::Set(CalibrationValue::YCenter, value) => y.center = value,
+            Calibrate::Set(CalibrationValue::XMin, value) => x.min = value,
+            Calibrate::Set(CalibrationValue::XMax, value) => x.max = value,
+            Calibrate::Set(CalibrationValue::YMin, value) => y.min = value,
+            Calibrate::Set(CalibrationValue::YMax, value) => y.max = value,
+            Calibrate::Set(CalibrationValue::Rotation, value) => rotation = value,
+            Calibrate::Start | Calibrate::Finish => {}
+        }
+        if !(x.is_valid() && y.is_valid()) {
+            return Err(ShellError::BadCalibration);
+        }
+        keymap.set_joy_calibration(x, y);
+        keymap.joy_x_y_rotation = rotation;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
+    Help,
+    Status,
+    /// `None` toggles
+    Monitor(Option<bool>),
+    Layer(u8),
+    Calibrate(Calibrate),
+    LedTest,
+    /// `None` shows the current level
+    Log(Option<LevelFilter>),
+    Reboot,
+    Bootloader,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ShellError {
+    UnknownCommand,
+    MissingArgument,
+    BadArgument,
+    TooManyArguments,
+    LineTooLong,
+    BadCalibration,
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ShellError::UnknownCommand => "unknown command, try `help`",
+            ShellError::MissingArgument => "missing argument",
+            ShellError::BadArgument => "bad argument",
+            ShellError::TooManyArguments => "too many arguments",
+            ShellError::LineTooLong => "line too long",
+            ShellError::BadCalibration => "each axis needs min < center < max",
+        };
+        f.write_str(message)
+    }
+}
+
+pub fn parse(line: &str) -> Result<Command, ShellError> {
+    let mut words = line.split_whitespace();
+    let command = match words.next().ok_or(ShellError::UnknownCommand)? {
+        "help" | "?" => Command::Help,
+        "status" => Command::Status,
+        "monitor" => match words.next() {
+            None => Command::Monitor(None),
+            Some("on") => Command::Monitor(Some(true)),
+            Some("off") => Command::Monitor(Some(false)),
+            Some(_) => return Err(ShellError::BadArgument),
+        },
+        "layer" => {
+            let layer: u8 = words
+                .next()
+                .ok_or(ShellError::MissingArgument)?
+                .parse()
+                .map_err(|_| ShellError::BadArgument)?;
+            if layer as usize >= LAYER_COUNT {
+                return Err(ShellError::BadArgument);
+            }
+            Command::Layer(layer)
+        }
+        "calibrate" => match words.next() {
+            None => Command::Calibrate(Calibrate::Start),
+            Some("done") => Command::Calibrate(Calibrate::Finish),
+            Some("center") => Command::Calibrate(Calibrate::Center),
+            Some(name) => {
+                let value = match name {
+                    "x_center" => CalibrationValue::XCenter,
+                    "y_center" => CalibrationValue::YCenter,
+                    "x_min" => CalibrationValue::XMin,
+                    "x_max" => CalibrationValue::XMax,
+                    "y_min" => CalibrationValue::YMin,
+                    "y_max" => CalibrationValue::YMax,
+                    "rotation" => CalibrationValue::Rotation,
+                    _ => return Err(ShellError::BadArgument),
+                };
+                let number = words
+                    .next()
+                    .ok_or(ShellError::MissingArgument)?
+                    .parse()
+                    .map_err(|_| ShellError::BadArgument)?;
+                Command::Calibrate(Calibrate::Set(value, number))
+            }
+        },
+        "ledtest" => Command::LedTest,
+        "log" => match words.next() {
+            None => Command::Log(None),
+            Some(level) => Command::Log(Some(
+                LevelFilter::from_str(level).map_err(|_| ShellError::BadArgument)?,
+            )),
+        },
+        "reboot" => Command::Reboot,
+        "bootloader" => Command::Bootloader,
+        _ => return Err(ShellError::UnknownCommand),
+    };
+    match words.next() {
+        Some(_) => Err(ShellError::TooManyArguments),
+        None => Ok(command),
+    }
+}
+
+pub struct Shell {
+    line: [u8; LINE_LEN],
+    len: usize,
+    overflowed: bool,
+    /// The last byte was a carriage return, so a line feed after it ends nothing
+    after_cr: bool,
+}
+
+impl Shell {
+    pub fn new() -> Shell {
+        Shell {
+            line: [0; LINE_LEN],
+            len: 0,
+            overflowed: false,
+            after_cr: false,
+        }
+    }
+
+    /// Takes one received byte, echoing it to `out`. Returns the parsed command once Enter
+    /// finishes a non-empty line; the caller writes the next prompt after carrying it out.
+    pub fn feed<W: Write>(&mut self, byte: u8, out: &mut W) -> Option<Result<Command, ShellError>> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                let _ = out.write_str("\r\n");
+                let result = if self.overflowed {
+                    Some(Err(ShellError::LineTooLong))
+                } else {
+                    match core::str::from_utf8(&self.line[..self.len]) {
+                        Ok(line) if line.trim().is_empty() => None,
+                        Ok(line) => Some(parse(line)),
+                        Err(_) => Some(Err(ShellError::UnknownCommand)),
+                    }
+                };
+                self.len = 0;
+                self.overflowed = false;
+                if result.is_none() {
+                    let _ = out.write_str(PROMPT);
+                }
+                result
+            }
+            // backspace and delete
+            0x08 | 0x7F => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    let _ = out.write_str("\x08 \x08");
+                }
+                None
+            }
+            b' '..=b'~' => {
+                if self.len < LINE_LEN {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    let _ = out.write_char(byte as char);
+                } else {
+                    self.overflowed = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Output waiting for the host to read it. Text that doesn't fit is dropped, as a console with
+/// nobody listening must not stall the keypad.
+pub struct OutBuffer<const N: usize> {
+    data: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> OutBuffer<N> {
+    pub fn new() -> OutBuffer<N> {
+        OutBuffer {
+            data: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hands the buffered bytes to `write`, which returns how many it took.
+    pub fn drain(&mut self, mut write: impl FnMut(&[u8]) -> usize) {
+        // at most two runs: up to the end of `data`, then from its start
+        while self.len > 0 {
+            let end = (self.start + self.len).min(N);
+            let run = end - self.start;
+            let written = write(&self.data[self.start..end]).min(run);
+            self.start = (self.start + written) % N;
+            self.len -= written;
+            if written < run {
+                break;
+            }
+        }
+    }
+
+    /// Buffers as much of `bytes` as fits, returning how much that was.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> usize {
+        let len = bytes.len().min(N - self.len);
+        for byte in &bytes[..len] {
+            self.data[(self.start + self.len) % N] = *byte;
+            self.len += 1;
+        }
+        len
+    }
+}
+
+impl<const N: usize> Default for OutBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for OutBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.write_bytes(s.as_bytes()) < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `text` byte by byte, returning the echo and the last result
+    fn type_line(shell: &mut Shell, text: &str) -> (String, Option<Result<Command, ShellError>>) {
+        let mut echo = String::new();
+        let mut result = None;
+        for byte in text.bytes() {
+            result = shell.feed(byte, &mut echo);
+        }
+        (echo, result)
+    }
+
+    #[test]
+    fn parses_every_command() {
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("?"), Ok(Command::Help));
+        assert_eq!(parse("status"), Ok(Command::Status));
+        assert_eq!(parse("monitor"), Ok(Command::Monitor(None)));
+        assert_eq!(parse("monitor on"), Ok(Command::Monitor(Some(true))));
+        assert_eq!(parse("monitor off"), Ok(Command::Monitor(Some(false))));
+        assert_eq!(parse("layer 3"), Ok(Command::Layer(3)));
+        assert_eq!(parse("calibrate"), Ok(Command::Calibrate(Calibrate::Start)));
+        assert_eq!(
+            parse("calibrate done"),
+            Ok(Command::Calibrate(Calibrate::Finish))
+        );
+        assert_eq!(
+            parse("calibrate center"),
+            Ok(Command::Calibrate(Calibrate::Center))
+        );
+        assert_eq!(
+            parse("calibrate y_max 1000"),
+            Ok(Command::Calibrate(Calibrate::Set(
+                CalibrationValue::YMax,
+                1000
+            )))
+        );
+        assert_eq!(parse("ledtest"), Ok(Command::LedTest));
+        assert_eq!(parse("log"), Ok(Command::Log(None)));
+        assert_eq!(
+            parse("log debug"),
+            Ok(Command::Log(Some(LevelFilter::Debug)))
+        );
+        assert_eq!(parse("reboot"), Ok(Command::Reboot));
+        assert_eq!(parse("bootloader"), Ok(Command::Bootloader));
+    }
+
+    #[test]
+    fn splits_on_any_whitespace() {
+        assert_eq!(
+            parse("  calibrate\trotation   15 "),
+            Ok(Command::Calibrate(Calibrate::Set(
+                CalibrationValue::Rotation,
+                15
+            )))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(parse(""), Err(ShellError::UnknownCommand));
+        assert_eq!(parse("frobnicate"), Err(ShellError::UnknownCommand));
+        assert_eq!(parse("Status"), Err(ShellError::UnknownCommand));
+        assert_eq!(parse("layer"), Err(ShellError::MissingArgument));
+        assert_eq!(parse("layer 4"), Err(ShellError::BadArgument));
+        assert_eq!(parse("layer -1"), Err(ShellError::BadArgument));
+        assert_eq!(parse("monitor maybe"), Err(ShellError::BadArgument));
+        assert_eq!(parse("calibrate x_min"), Err(ShellError::MissingArgument));
+        assert_eq!(parse("calibrate x_min 70000"), Err(ShellError::BadArgument));
+        assert_eq!(parse("calibrate z_min 5"), Err(ShellError::BadArgument));
+        assert_eq!(parse("log loud"), Err(ShellError::BadArgument));
+        assert_eq!(parse("status now"), Err(ShellError::TooManyArguments));
+        assert_eq!(parse("layer 1 2"), Err(ShellError::TooManyArguments));
+    }
+
+    #[test]
+    fn feed_echoes_and_edits() {
+        let mut shell = Shell::new();
+        let (echo, result) = type_line(&mut shell, "layez\x7Fr 2\r");
+        assert_eq!(echo, "layez\x08 \x08r 2\r\n");
+        assert_eq!(result, Some(Ok(Command::Layer(2))));
+
+        // a blank line just prompts again
+        let (echo, result) = type_line(&mut shell, "   \n");
+        assert_eq!(echo, "   \r\n> ");
+        assert_eq!(result, None);
+
+        // control characters are ignored, backspace on an empty line does nothing
+        let (echo, result) = type_line(&mut shell, "\x08\x1bstatus\r");
+        assert_eq!(echo, "status\r\n");
+        assert_eq!(result, Some(Ok(Command::Status)));
+    }
+
+    #[test]
+    fn crlf_ends_one_line() {
+        let mut shell = Shell::new();
+        let (echo, result) = type_line(&mut shell, "status\r\n");
+        assert_eq!(echo, "status\r\n");
+        assert_eq!(result, None);
+        let (echo, result) = type_line(&mut shell, "\r\n\r\n");
+        assert_eq!(echo, "\r\n> \r\n> ");
+        assert_eq!(result, None);
+        // a bare line feed still ends a line
+        let (_, result) = type_line(&mut shell, "help\n");
+        assert_eq!(result, Some(Ok(Command::Help)));
+    }
+
+    #[test]
+    fn calibration_edits_are_checked() {
+        let mut keymap = Keymap::default();
+        let (x, y) = (keymap.joy_x_calibration(), keymap.joy_y_calibration());
+        let set = |value, number| Calibrate::Set(value, number);
+
+        assert_eq!(
+            set(CalibrationValue::XMin, 100).apply(&mut keymap, (0, 0)),
+            Ok(())
+        );
+        assert_eq!(keymap.joy_x_min, 100);
+        assert_eq!(
+            set(CalibrationValue::Rotation, 90).apply(&mut keymap, (0, 0)),
+            Ok(())
+        );
+        assert_eq!(keymap.joy_x_y_rotation, 90);
+        assert_eq!(Calibrate::Center.apply(&mut keymap, (510, 490)), Ok(()));
+        assert_eq!((keymap.joy_x_center, keymap.joy_y_center), (510, 490));
+
+        let bad = Err(ShellError::BadCalibration);
+        assert_eq!(
+            set(CalibrationValue::XMin, 510).apply(&mut keymap, (0, 0)),
+            bad
+        );
+        assert_eq!(
+            set(CalibrationValue::YMax, 100).apply(&mut keymap, (0, 0)),
+            bad
+        );
+        assert_eq!(
+            set(CalibrationValue::YCenter, y.max).apply(&mut keymap, (0, 0)),
+            bad
+        );
+        assert_eq!(Calibrate::Center.apply(&mut keymap, (x.max, 490)), bad);
+        // nothing changed
+        assert_eq!(keymap.joy_x_min, 100);
+        assert_eq!((keymap.joy_x_center, keymap.joy_y_center), (510, 490));
+        assert_eq!(keymap.joy_y_max, y.max);
+    }
+
+    #[test]
+    fn feed_reports_overlong_lines() {
+        let mut shell = Shell::new();
+        let long = "x".repeat(LINE_LEN + 1);
+        let (_, result) = type_line(&mut shell, &(long + "\r"));
+        assert_eq!(result, Some(Err(ShellError::LineTooLong)));
+        // and starts afresh afterwards
+        let (_, result) = type_line(&mut shell, "help\r");
+        assert_eq!(result, Some(Ok(Command::Help)));
+    }
+
+    #[test]
+    fn out_buffer_wraps_and_drops_overflow() {
+        let mut buffer = OutBuffer::<8>::new();
+        assert_eq!(buffer.write_bytes(b"abcdef"), 6);
+        let mut read = Vec::new();
+        buffer.drain(|bytes| {
+            read.extend_from_slice(&bytes[..4.min(bytes.len())]);
+            4.min(bytes.len())
+        });
+        assert_eq!(read, b"abcd");
+
+        // wraps round the end, then fills up
+        assert_eq!(buffer.write_bytes(b"ghijklmn"), 6);
+        assert!(write!(buffer, "o").is_err());
+        let mut read = Vec::new();
+        buffer.drain(|bytes| {
+            read.extend_from_slice(bytes);
+            bytes.len()
+        });
+        assert_eq!(read, b"efghijkl");
+        assert!(buffer.is_empty());
+    }
+}
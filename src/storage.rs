@@ -0,0 +1,374 @@
+use crate::keymap_common::{crc32, EncodeError, Keymap};
+
+// Keymaps are stored in a ring of flash sectors. Every save goes to the sector after the newest
+// valid record and carries the next sequence number, so the previous keymap is never erased
+// before the new one is fully written. With two sectors this is a plain A/B scheme, more sectors
+// spread the erase cycles further.
+//
+// Record layout, little endian:
+//   0       4   magic, b"PTSL"
+//   4       4   sequence number
+//   8       2   length of the encoded keymap
+//   10      2   reserved, 0xFFFF
+//   12      n   Keymap::encode output
+//   12 + n  4   CRC-32 of bytes 0..12 + n
+const RECORD_MAGIC: [u8; 4] = *b"PTSL";
+const RECORD_HEADER_LEN: usize = 12;
+const RECORD_CRC_LEN: usize = 4;
+pub const RECORD_LEN: usize = RECORD_HEADER_LEN + Keymap::ENCODED_LEN + RECORD_CRC_LEN;
+
+/// NOR-style flash: erasing sets a sector to 0xFF, programming can only clear bits.
+pub trait Flash {
+    type Error: core::fmt::Debug;
+    const SECTOR_SIZE: usize;
+
+    fn sector_count(&self) -> usize;
+    fn read(&mut self, sector: usize, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+    fn erase(&mut self, sector: usize) -> Result<(), Self::Error>;
+    fn program(&mut self, sector: usize, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StorageError<E> {
+    Flash(E),
+    Encode(EncodeError),
+    /// The record read back after programming did not match what was written
+    VerifyFailed,
+}
+
+#[derive(Copy, Clone)]
+struct Slot {
+    sector: usize,
+    sequence: u32,
+}
+
+pub struct KeymapStore<F: Flash> {
+    flash: F,
+    newest: Option<Slot>,
+}
+
+impl<F: Flash> KeymapStore<F> {
+    pub fn new(flash: F) -> KeymapStore<F> {
+        assert!(F::SECTOR_SIZE >= RECORD_LEN);
+        assert!(flash.sector_count() >= 2);
+        KeymapStore {
+            flash,
+            newest: None,
+        }
+    }
+
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Loads the keymap from the newest valid record, if there is one.
+    pub fn load(&mut self) -> Option<Keymap> {
+        let mut newest: Option<(Slot, Keymap)> = None;
+        for sector in 0..self.flash.sector_count() {
+            let Some((sequence, keymap)) = self.read_record(sector) else {
+                continue;
+            };
+            if newest.is_none_or(|(slot, _)| sequence > slot.sequence) {
+                newest = Some((Slot { sector, sequence }, keymap));
+            }
+        }
+        self.newest = newest.map(|(slot, _)| slot);
+        newest.map(|(_, keymap)| keymap)
+    }
+
+    /// Loads the stored keymap, falling back to `Keymap::default()` when nothing valid is stored.
+    pub fn load_or_default(&mut self) -> Keymap {
+        match self.load() {
+            Some(keymap) => keymap,
+            None => {
+                log::warn!("no valid keymap in flash, using defaults");
+                Keymap::default()
+            }
+        }
+    }
+
+    pub fn save(&mut self, keymap: &Keymap) -> Result<(), StorageError<F::Error>> {
+        let (sector, sequence) = match self.newest {
+            Some(slot) => (
+                (slot.sector + 1) % self.flash.sector_count(),
+                slot.sequence.wrapping_add(1),
+            ),
+            None => (0, 1),
+        };
+
+        let mut record = [0xFFu8; RECORD_LEN];
+        let len = keymap
+            .encode(&mut record[RECORD_HEADER_LEN..])
+            .map_err(StorageError::Encode)?;
+        record[0..4].copy_from_slice(&RECORD_MAGIC);
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        record[8..10].copy_from_slice(&(len as u16).to_le_bytes());
+        let end = RECORD_HEADER_LEN + len;
+        let crc = crc32(&record[..end]);
+        record[end..end + RECORD_CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        let record = &record[..end + RECORD_CRC_LEN];
+
+        self.flash.erase(sector).map_err(StorageError::Flash)?;
+        self.flash
+            .program(sector, 0, record)
+            .map_err(StorageError::Flash)?;
+
+        let mut readback = [0u8; RECORD_LEN];
+        let readback = &mut readback[..record.len()];
+        self.flash
+            .read(sector, 0, readback)
+            .map_err(StorageError::Flash)?;
+        if readback != record {
+            return Err(StorageError::VerifyFailed);
+        }
+        self.newest = Some(Slot { sector, sequence });
+        Ok(())
+    }
+
+    /// Erases every sector, so the next boot falls back to defaults.
+    pub fn clear(&mut self) -> Result<(), StorageError<F::Error>> {
+        for sector in 0..self.flash.sector_count() {
+            self.flash.erase(sector).map_err(StorageError::Flash)?;
+        }
+        self.newest = None;
+        Ok(())
+    }
+
+    fn read_record(&mut self, sector: usize) -> Option<(u32, Keymap)> {
+        let mut record = [0u8; RECORD_LEN];
+        self.flash
+            .read(sector, 0, &mut record[..RECORD_HEADER_LEN])
+            .ok()?;
+        if record[0..4] != RECORD_MAGIC {
+            return None;
+        }
+        let sequence = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        let len = u16::from_le_bytes([record[8], record[9]]) as usize;
+        let end = RECORD_HEADER_LEN + len;
+        if end + RECORD_CRC_LEN > RECORD_LEN {
+            return None;
+        }
+        self.flash
+            .read(
+                sector,
+                RECORD_HEADER_LEN,
+                &mut record[RECORD_HEADER_LEN..end + RECORD_CRC_LEN],
+            )
+            .ok()?;
+        let crc = u32::from_le_bytes([
+            record[end],
+            record[end + 1],
+            record[end + 2],
+            record[end + 3],
+        ]);
+        if crc != crc32(&record[..end]) {
+            log::warn!("corrupt keymap record in flash sector {}", sector);
+            return None;
+        }
+        match Keymap::decode(&record[RECORD_HEADER_LEN..end]) {
+            Ok(keymap) => Some((sequence, keymap)),
+            Err(e) => {
+                log::warn!("undecodable keymap in flash sector {}: {:?}", sector, e);
+                None
+            }
+        }
+    }
+}
+
+/// Flash held in RAM, for host-side use of `KeymapStore`.
+pub struct RamFlash<const SECTOR_SIZE: usize, const SECTORS: usize> {
+    pub data: [[u8; SECTOR_SIZE]; SECTORS],
+    /// Erase count of every sector
+    pub erases: [u32; SECTORS],
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RamFlashError {
+    OutOfBounds,
+}
+
+impl<const SECTOR_SIZE: usize, const SECTORS: usize> RamFlash<SECTOR_SIZE, SECTORS> {
+    pub fn new() -> RamFlash<SECTOR_SIZE, SECTORS> {
+        RamFlash {
+            data: [[0xFF; SECTOR_SIZE]; SECTORS],
+            erases: [0; SECTORS],
+        }
+    }
+
+    fn range(
+        sector: usize,
+        offset: usize,
+        len: usize,
+    ) -> Result<core::ops::Range<usize>, RamFlashError> {
+        if sector >= SECTORS || offset + len > SECTOR_SIZE {
+            return Err(RamFlashError::OutOfBounds);
+        }
+        Ok(offset..offset + len)
+    }
+}
+
+impl<const SECTOR_SIZE: usize, const SECTORS: usize> Default for RamFlash<SECTOR_SIZE, SECTORS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SECTOR_SIZE: usize, const SECTORS: usize> Flash for RamFlash<SECTOR_SIZE, SECTORS> {
+    type Error = RamFlashError;
+    const SECTOR_SIZE: usize = SECTOR_SIZE;
+
+    fn sector_count(&self) -> usize {
+        SECTORS
+    }
+
+    fn read(&mut self, sector: usize, offset: usize, buf: &mut [u8]) -> Result<(), RamFlashError> {
+        let range = Self::range(sector, offset, buf.len())?;
+        buf.copy_from_slice(&self.data[sector][range]);
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), RamFlashError> {
+        Self::range(sector, 0, 0)?;
+        self.data[sector] = [0xFF; SECTOR_SIZE];
+        self.erases[sector] += 1;
+        Ok(())
+    }
+
+    fn program(&mut self, sector: usize, offset: usize, data: &[u8]) -> Result<(), RamFlashError> {
+        let range = Self::range(sector, offset, data.len())?;
+        for (cell, byte) in self.data[sector][range].iter_mut().zip(data) {
+            *cell &= *byte;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestFlash = RamFlash<4096, 3>;
+
+    fn encoded(keymap: &Keymap) -> [u8; Keymap::ENCODED_LEN] {
+        let mut buf = [0; Keymap::ENCODED_LEN];
+        keymap.encode(&mut buf).unwrap();
+        buf
+    }
+
+    fn keymap(center: u16) -> Keymap {
+        let mut keymap = Keymap::default();
+        keymap.joy_x_center = center;
+        keymap
+    }
+
+    /// A store on a copy of `store`'s flash, as after a reboot
+    fn reopen(store: &mut KeymapStore<TestFlash>) -> KeymapStore<TestFlash> {
+        let mut flash = TestFlash::new();
+        flash.data = store.flash().data;
+        KeymapStore::new(flash)
+    }
+
+    #[test]
+    fn blank_flash_loads_nothing() {
+        let mut store = KeymapStore::new(TestFlash::new());
+        assert!(store.load().is_none());
+        assert_eq!(
+            encoded(&store.load_or_default()),
+            encoded(&Keymap::default())
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut store = KeymapStore::new(TestFlash::new());
+        store.save(&keymap(1234)).unwrap();
+        let loaded = reopen(&mut store).load().unwrap();
+        assert_eq!(encoded(&loaded), encoded(&keymap(1234)));
+    }
+
+    #[test]
+    fn ring_wraps_and_spreads_erases() {
+        let mut store = KeymapStore::new(TestFlash::new());
+        for center in 1..=5 {
+            store.save(&keymap(center)).unwrap();
+        }
+        assert_eq!(store.flash().erases, [2, 2, 1]);
+        assert_eq!(reopen(&mut store).load().unwrap().joy_x_center, 5);
+
+        // a store that found the newest record carries on after it
+        let mut reopened = reopen(&mut store);
+        reopened.load();
+        reopened.save(&keymap(6)).unwrap();
+        assert_eq!(reopened.flash().erases, [0, 0, 1]);
+        assert_eq!(reopen(&mut reopened).load().unwrap().joy_x_center, 6);
+    }
+
+    #[test]
+    fn torn_write_keeps_previous_keymap() {
+        let mut store = KeymapStore::new(TestFlash::new());
+        store.save(&keymap(1)).unwrap();
+        store.save(&keymap(2)).unwrap();
+
+        // power lost halfway through programming the second record
+        let mut torn = reopen(&mut store);
+        torn.flash().data[1][RECORD_LEN / 2..].fill(0xFF);
+        assert_eq!(torn.load().unwrap().joy_x_center, 1);
+
+        // or just after erasing its sector
+        let mut erased = reopen(&mut store);
+        erased.flash().erase(1).unwrap();
+        assert_eq!(erased.load().unwrap().joy_x_center, 1);
+        erased.save(&keymap(3)).unwrap();
+        assert_eq!(reopen(&mut erased).load().unwrap().joy_x_center, 3);
+    }
+
+    /// Flash whose programming doesn't stick past `limit` bytes
+    struct FailingFlash {
+        flash: TestFlash,
+        limit: usize,
+    }
+
+    impl Flash for FailingFlash {
+        type Error = RamFlashError;
+        const SECTOR_SIZE: usize = 4096;
+
+        fn sector_count(&self) -> usize {
+            self.flash.sector_count()
+        }
+
+        fn read(
+            &mut self,
+            sector: usize,
+            offset: usize,
+            buf: &mut [u8],
+        ) -> Result<(), Self::Error> {
+            self.flash.read(sector, offset, buf)
+        }
+
+        fn erase(&mut self, sector: usize) -> Result<(), Self::Error> {
+            self.flash.erase(sector)
+        }
+
+        fn program(
+            &mut self,
+            sector: usize,
+            offset: usize,
+            data: &[u8],
+        ) -> Result<(), Self::Error> {
+            let len = data.len().min(self.limit);
+            self.flash.program(sector, offset, &data[..len])
+        }
+    }
+
+    #[test]
+    fn failed_verify_keeps_previous_keymap() {
+        let mut store = KeymapStore::new(FailingFlash {
+            flash: TestFlash::new(),
+            limit: usize::MAX,
+        });
+        store.save(&keymap(1)).unwrap();
+        store.flash().limit = RECORD_LEN / 2;
+        assert_eq!(store.save(&keymap(2)), Err(StorageError::VerifyFailed));
+        assert_eq!(store.load().unwrap().joy_x_center, 1);
+    }
+}
@@ -0,0 +1,208 @@
+use usb_device::device::UsbDeviceState;
+
+// Where the pad stands with the host, following usb-device's view of the bus each pass. usb-device
+// speaks the protocol; this decides what the rest of the firmware does about it. Reports only go
+// out while Active, the LEDs go dark while Suspended, and a keypress while Suspended wakes the
+// host if it allowed remote wakeup. Not every bus driver tells usb-device about suspend (the
+// Teensy's doesn't), so the caller also passes in whether the controller sees the bus suspended.
+/// How long to wait for the host to resume after signalling wakeup before trying again
+pub const WAKEUP_RETRY_US: u64 = 1_000_000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LinkState {
+    /// Attached, or reset, and waiting for the host to configure us
+    Enumerating,
+    Active,
+    Suspended,
+    /// Remote wakeup signalled at the given time, waiting for the host to resume the bus
+    Waking(u64),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Transition {
+    None,
+    /// The host configured us: set up the endpoints and start reporting
+    Configured,
+    Suspended,
+    Resumed,
+    /// The host reset the bus or dropped our configuration
+    Reset,
+}
+
+pub struct UsbLifecycle {
+    state: LinkState,
+}
+
+impl UsbLifecycle {
+    pub fn new() -> UsbLifecycle {
+        UsbLifecycle {
+            state: LinkState::Enumerating,
+        }
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.state
+    }
+
+    /// Whether reports should be sent
+    pub fn is_active(&self) -> bool {
+        self.state == LinkState::Active
+    }
+
+    /// Follows the device state usb-device reports and whether the bus is `bus_suspended`,
+    /// returning what changed.
+    pub fn update(
+        &mut self,
+        device: UsbDeviceState,
+        bus_suspended: bool,
+        now_us: u64,
+    ) -> Transition {
+        use LinkState::*;
+        let suspended = bus_suspended || device == UsbDeviceState::Suspend;
+        let (state, transition) = match (self.state, device, suspended) {
+            (Enumerating, UsbDeviceState::Configured, false) => (Active, Transition::Configured),
+            (Enumerating, _, _) => (Enumerating, Transition::None),
+            (_, UsbDeviceState::Default | UsbDeviceState::Addressed, _) => {
+                (Enumerating, Transition::Reset)
+            }
+            (Active, _, true) => (Suspended, Transition::Suspended),
+            (Active, _, false) => (Active, Transition::None),
+            (Suspended | Waking(_), _, false) => (Active, Transition::Resumed),
+            (Waking(since), _, true) if now_us.wrapping_sub(since) >= WAKEUP_RETRY_US => {
+                (Suspended, Transition::None)
+            }
+            (state, _, true) => (state, Transition::None),
+        };
+        self.state = state;
+        transition
+    }
+
+    /// Asks to wake the host. Returns true if the caller should signal resume on the bus now,
+    /// which is when we are suspended and the host has `allowed` remote wakeup.
+    pub fn request_wakeup(&mut self, allowed: bool, now_us: u64) -> bool {
+        if self.state == LinkState::Suspended && allowed {
+            self.state = LinkState::Waking(now_us);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for UsbLifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use UsbDeviceState as Device;
+
+    fn configured() -> UsbLifecycle {
+        let mut usb = UsbLifecycle::new();
+        assert_eq!(
+            usb.update(Device::Configured, false, 0),
+            Transition::Configured
+        );
+        usb
+    }
+
+    #[test]
+    fn enumerates_then_reports() {
+        let mut usb = UsbLifecycle::new();
+        assert_eq!(usb.update(Device::Default, false, 0), Transition::None);
+        assert_eq!(usb.update(Device::Addressed, false, 1), Transition::None);
+        assert!(!usb.is_active());
+        // suspended before being configured: wait for the host
+        assert_eq!(usb.update(Device::Configured, true, 2), Transition::None);
+        assert_eq!(usb.state(), LinkState::Enumerating);
+        assert_eq!(
+            usb.update(Device::Configured, false, 3),
+            Transition::Configured
+        );
+        assert!(usb.is_active());
+        assert_eq!(usb.update(Device::Configured, false, 4), Transition::None);
+    }
+
+    #[test]
+    fn bus_suspend_and_resume() {
+        let mut usb = configured();
+        assert_eq!(
+            usb.update(Device::Configured, true, 10),
+            Transition::Suspended
+        );
+        assert_eq!(usb.state(), LinkState::Suspended);
+        assert!(!usb.is_active());
+        assert_eq!(usb.update(Device::Configured, true, 20), Transition::None);
+        assert_eq!(
+            usb.update(Device::Configured, false, 30),
+            Transition::Resumed
+        );
+        assert!(usb.is_active());
+    }
+
+    #[test]
+    fn usb_device_suspend_counts_too() {
+        let mut usb = configured();
+        assert_eq!(
+            usb.update(Device::Suspend, false, 10),
+            Transition::Suspended
+        );
+        assert_eq!(
+            usb.update(Device::Configured, false, 20),
+            Transition::Resumed
+        );
+    }
+
+    #[test]
+    fn reset_from_any_state() {
+        let mut usb = configured();
+        assert_eq!(usb.update(Device::Default, false, 10), Transition::Reset);
+        assert_eq!(usb.state(), LinkState::Enumerating);
+
+        let mut usb = configured();
+        usb.update(Device::Configured, true, 10);
+        assert_eq!(usb.update(Device::Default, true, 20), Transition::Reset);
+        assert_eq!(
+            usb.update(Device::Configured, false, 30),
+            Transition::Configured
+        );
+    }
+
+    #[test]
+    fn wakeup_only_when_suspended_and_allowed() {
+        let mut usb = configured();
+        assert!(!usb.request_wakeup(true, 5));
+        usb.update(Device::Configured, true, 10);
+        assert!(!usb.request_wakeup(false, 20));
+        assert!(usb.request_wakeup(true, 30));
+        assert_eq!(usb.state(), LinkState::Waking(30));
+        // already waking
+        assert!(!usb.request_wakeup(true, 40));
+        assert_eq!(
+            usb.update(Device::Configured, false, 50),
+            Transition::Resumed
+        );
+        assert!(usb.is_active());
+    }
+
+    #[test]
+    fn unanswered_wakeup_is_retried() {
+        let mut usb = configured();
+        usb.update(Device::Configured, true, 0);
+        assert!(usb.request_wakeup(true, 100));
+        assert_eq!(
+            usb.update(Device::Configured, true, 100 + WAKEUP_RETRY_US - 1),
+            Transition::None
+        );
+        assert_eq!(usb.state(), LinkState::Waking(100));
+        assert_eq!(
+            usb.update(Device::Configured, true, 100 + WAKEUP_RETRY_US),
+            Transition::None
+        );
+        assert_eq!(usb.state(), LinkState::Suspended);
+        assert!(usb.request_wakeup(true, 200 + WAKEUP_RETRY_US));
+    }
+}
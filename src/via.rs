@@ -0,0 +1,478 @@
+use crate::config_protocol::REPORT_LEN;
+use crate::keymap_common::{Consumer, KeyboardAction, Keymap, Mapping, INPUT_COUNT, LAYER_COUNT};
+
+// VIA protocol (version 12) on top of the raw HID interface. VIA addresses keys by matrix
+// position, we present the inputs as a 4x7 matrix in the order described at
+// keymap_common::INPUT_COUNT: rows 0-2 and row 3 column 0 are the 21 keys, followed by the
+// joystick button, the scroll button and the WASD directions; the last position is unused.
+// The matching keyboard definition for VIA lives in via/padtarust.json.
+pub const ROWS: usize = 4;
+pub const COLS: usize = 7;
+pub const PROTOCOL_VERSION: u16 = 0x000C;
+
+mod id {
+    pub const GET_PROTOCOL_VERSION: u8 = 0x01;
+    pub const GET_KEYBOARD_VALUE: u8 = 0x02;
+    pub const SET_KEYBOARD_VALUE: u8 = 0x03;
+    pub const DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+    pub const DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+    pub const DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+    pub const EEPROM_RESET: u8 = 0x0A;
+    pub const BOOTLOADER_JUMP: u8 = 0x0B;
+    pub const DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0C;
+    pub const DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+    pub const DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+    pub const DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+    pub const DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+    pub const DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+    pub const DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+    pub const DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+    pub const UNHANDLED: u8 = 0xFF;
+}
+
+mod keyboard_value {
+    pub const UPTIME: u8 = 0x01;
+    pub const LAYOUT_OPTIONS: u8 = 0x02;
+    pub const FIRMWARE_VERSION: u8 = 0x04;
+    pub const DEVICE_INDICATION: u8 = 0x05;
+}
+
+// QMK keycodes we translate to and from
+const KC_NO: u16 = 0x0000;
+const KC_TRANSPARENT: u16 = 0x0001;
+const KC_MS_BTN1: u16 = 0x00D1;
+const KC_MS_BTN2: u16 = 0x00D2;
+const KC_MS_BTN3: u16 = 0x00D3;
+const QK_TO: u16 = 0x5200;
+const QK_MOMENTARY: u16 = 0x5220;
+const QK_JOYSTICK: u16 = 0x7400;
+/// First keyboard-specific keycode, declared as customKeycodes in the definition
+const QK_KB: u16 = 0x7E00;
+
+// QMK's media keycodes and the consumer usages they stand for
+const QMK_CONSUMER_KEYCODES: [(u16, Consumer); 22] = [
+    (0xA8, Consumer::Mute),
+    (0xA9, Consumer::VolumeIncrement),
+    (0xAA, Consumer::VolumeDecrement),
+    (0xAB, Consumer::ScanNextTrack),
+    (0xAC, Consumer::ScanPreviousTrack),
+    (0xAD, Consumer::Stop),
+    (0xAE, Consumer::PlayPause),
+    (0xAF, Consumer::ALConsumerControlConfiguration),
+    (0xB0, Consumer::Eject),
+    (0xB1, Consumer::ALEmailReader),
+    (0xB2, Consumer::ALCalculator),
+    (0xB3, Consumer::ALLocalMachineBrowser),
+    (0xB4, Consumer::ACSearch),
+    (0xB5, Consumer::ACHome),
+    (0xB6, Consumer::ACBack),
+    (0xB7, Consumer::ACForward),
+    (0xB8, Consumer::ACStop),
+    (0xB9, Consumer::ACRefresh),
+    (0xBA, Consumer::ACBookmarks),
+    (0xBB, Consumer::FastForward),
+    (0xBC, Consumer::Rewind),
+    (0xBF, Consumer::ALControlPanel),
+];
+
+// padtarust actions exposed as customKeycodes, in QK_KB order
+const CUSTOM_ACTIONS: [KeyboardAction; 4] = [
+    KeyboardAction::WasdModeOn,
+    KeyboardAction::WasdModeOff,
+    KeyboardAction::WasdModeToggle,
+    KeyboardAction::CalibrateJoystick,
+];
+
+const LAYER_SET_ACTIONS: [KeyboardAction; 4] = [
+    KeyboardAction::Layer0Set,
+    KeyboardAction::Layer1Set,
+    KeyboardAction::Layer2Set,
+    KeyboardAction::Layer3Set,
+];
+
+const LAYER_MOMENTARY_ACTIONS: [KeyboardAction; 4] = [
+    KeyboardAction::Layer0Momentary,
+    KeyboardAction::Layer1Momentary,
+    KeyboardAction::Layer2Momentary,
+    KeyboardAction::Layer3Momentary,
+];
+
+/// Quiet time before edits are written to flash. VIA sends edits one keycode at a time and
+/// expects each of them to persist.
+const SAVE_DELAY_US: u64 = 1_000_000;
+
+const fn parse_version_part(part: &str) -> u32 {
+    let bytes = part.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
+
+const FIRMWARE_VERSION: u32 = parse_version_part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+    | parse_version_part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+    | parse_version_part(env!("CARGO_PKG_VERSION_PATCH"));
+
+/// Translates a mapping to the closest QMK keycode. A mapping can combine an action, a key and
+/// a consumer code while a keycode holds one, so only the first of those that is set survives.
+pub fn mapping_to_keycode(mapping: &Mapping) -> u16 {
+    match mapping.action {
+        KeyboardAction::None => {}
+        KeyboardAction::Transparent => return KC_TRANSPARENT,
+        KeyboardAction::MouseLeftButton => return KC_MS_BTN1,
+        KeyboardAction::MouseRightButton => return KC_MS_BTN2,
+        KeyboardAction::MouseScrollButton => return KC_MS_BTN3,
+        KeyboardAction::JoystickButton => return QK_JOYSTICK,
+        action => {
+            if let Some(layer) = LAYER_SET_ACTIONS.iter().position(|a| *a == action) {
+                return QK_TO | layer as u16;
+            }
+            if let Some(layer) = LAYER_MOMENTARY_ACTIONS.iter().position(|a| *a == action) {
+                return QK_MOMENTARY | layer as u16;
+            }
+            if let Some(i) = CUSTOM_ACTIONS.iter().position(|a| *a == action) {
+                return QK_KB + i as u16;
+            }
+        }
+    }
+    let button = mapping.button as u16;
+    if (0x04..=0xA4).contains(&button) || (0xE0..=0xE7).contains(&button) {
+        return button;
+    }
+    if mapping.consumer_button != Consumer::Unassigned {
+        if let Some((keycode, _)) = QMK_CONSUMER_KEYCODES
+            .iter()
+            .find(|(_, consumer)| *consumer == mapping.consumer_button)
+        {
+            return *keycode;
+        }
+    }
+    KC_NO
+}
+
+pub fn keycode_to_mapping(keycode: u16) -> Option<Mapping> {
+    let empty = Mapping::from_action(KeyboardAction::None);
+    let mapping = match keycode {
+        KC_NO => empty,
+        KC_TRANSPARENT => Mapping::from_action(KeyboardAction::Transparent),
+        KC_MS_BTN1 => Mapping::from_action(KeyboardAction::MouseLeftButton),
+        KC_MS_BTN2 => Mapping::from_action(KeyboardAction::MouseRightButton),
+        KC_MS_BTN3 => Mapping::from_action(KeyboardAction::MouseScrollButton),
+        QK_JOYSTICK => Mapping::from_action(KeyboardAction::JoystickButton),
+        0x04..=0xA4 | 0xE0..=0xE7 => Mapping::from_raw(0, keycode as u8, 0).ok()?,
+        _ if keycode & 0xFFE0 == QK_TO => {
+            Mapping::from_action(*LAYER_SET_ACTIONS.get((keycode & 0x1F) as usize)?)
+        }
+        _ if keycode & 0xFFE0 == QK_MOMENTARY => {
+            Mapping::from_action(*LAYER_MOMENTARY_ACTIONS.get((keycode & 0x1F) as usize)?)
+        }
+        _ if keycode >= QK_KB => {
+            Mapping::from_action(*CUSTOM_ACTIONS.get((keycode - QK_KB) as usize)?)
+        }
+        _ => {
+            let (_, consumer) = QMK_CONSUMER_KEYCODES.iter().find(|(k, _)| *k == keycode)?;
+            Mapping {
+                consumer_button: *consumer,
+                ..empty
+            }
+        }
+    };
+    Some(mapping)
+}
+
+pub struct ViaState {
+    /// Time of the last tick, in microseconds since boot
+    now_us: u64,
+    /// Time of the last unsaved edit
+    dirty_since_us: Option<u64>,
+    pub bootloader_requested: bool,
+}
+
+impl ViaState {
+    pub fn new() -> ViaState {
+        ViaState {
+            now_us: 0,
+            dirty_since_us: None,
+            bootloader_requested: false,
+        }
+    }
+
+    /// Advances to `now_us`, returns true once edits have settled and should be written to flash.
+    pub fn tick(&mut self, now_us: u64) -> bool {
+        self.now_us = now_us;
+        match self.dirty_since_us {
+            Some(since) if now_us.saturating_sub(since) >= SAVE_DELAY_US => {
+                self.dirty_since_us = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn uptime_ms(&self) -> u32 {
+        (self.now_us / 1000) as u32
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty_since_us = Some(self.now_us);
+    }
+}
+
+impl Default for ViaState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn input_index(row: u8, col: u8) -> Option<usize> {
+    let (row, col) = (row as usize, col as usize);
+    if row >= ROWS || col >= COLS {
+        return None;
+    }
+    Some(row * COLS + col)
+}
+
+fn get_keycode(keymap: &Keymap, layer: usize, input: usize) -> u16 {
+    match keymap.input_mappings(input) {
+        Some(mappings) if layer < LAYER_COUNT => mapping_to_keycode(&mappings[layer]),
+        _ => KC_NO,
+    }
+}
+
+fn set_keycode(keymap: &mut Keymap, layer: usize, input: usize, keycode: u16) {
+    let mapping = match keycode_to_mapping(keycode) {
+        Some(mapping) => mapping,
+        None => {
+            log::warn!("VIA keycode {:#06x} has no padtarust equivalent", keycode);
+            return;
+        }
+    };
+    if let Some(mappings) = keymap.input_mappings_mut(input) {
+        if layer < LAYER_COUNT {
+            mappings[layer] = mapping;
+        }
+    }
+}
+
+fn reset_mappings(keymap: &mut Keymap) {
+    let defaults = Keymap::default();
+    keymap.key_mappings = defaults.key_mappings;
+    keymap.joy_button_mappings = defaults.joy_button_mappings;
+    keymap.scroll_button_mappings = defaults.scroll_button_mappings;
+    keymap.wasd_mappings = defaults.wasd_mappings;
+    keymap.wasd_run_mappings = defaults.wasd_run_mappings;
+    keymap.wasd_run = defaults.wasd_run;
+}
+
+/// Answers a VIA request. VIA replies echo the request with the results filled in.
+pub fn handle(
+    request: &[u8; REPORT_LEN],
+    keymap: &mut Keymap,
+    state: &mut ViaState,
+) -> [u8; REPORT_LEN] {
+    let mut response = *request;
+    match request[0] {
+        id::GET_PROTOCOL_VERSION => {
+            response[1..3].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        }
+        id::GET_KEYBOARD_VALUE => match request[1] {
+            keyboard_value::UPTIME => {
+                response[2..6].copy_from_slice(&state.uptime_ms().to_be_bytes());
+            }
+            keyboard_value::LAYOUT_OPTIONS => {
+                response[2..6].copy_from_slice(&0u32.to_be_bytes());
+            }
+            keyboard_value::FIRMWARE_VERSION => {
+                response[2..6].copy_from_slice(&FIRMWARE_VERSION.to_be_bytes());
+            }
+            _ => response[0] = id::UNHANDLED,
+        },
+        id::SET_KEYBOARD_VALUE => match request[1] {
+            keyboard_value::LAYOUT_OPTIONS | keyboard_value::DEVICE_INDICATION => {}
+            _ => response[0] = id::UNHANDLED,
+        },
+        id::DYNAMIC_KEYMAP_GET_KEYCODE => {
+            let keycode = match input_index(request[2], request[3]) {
+                Some(input) => get_keycode(keymap, request[1] as usize, input),
+                None => KC_NO,
+            };
+            response[4..6].copy_from_slice(&keycode.to_be_bytes());
+        }
+        id::DYNAMIC_KEYMAP_SET_KEYCODE => {
+            if let Some(input) = input_index(request[2], request[3]) {
+                let keycode = u16::from_be_bytes([request[4], request[5]]);
+                set_keycode(keymap, request[1] as usize, input, keycode);
+                state.mark_dirty();
+            }
+        }
+        id::DYNAMIC_KEYMAP_RESET => {
+            reset_mappings(keymap);
+            state.mark_dirty();
+        }
+        id::EEPROM_RESET => {
+            *keymap = Keymap::default();
+            state.mark_dirty();
+        }
+        id::BOOTLOADER_JUMP => {
+            state.bootloader_requested = true;
+        }
+        // Mapping has no notion of macros, so there are none to edit
+        id::DYNAMIC_KEYMAP_MACRO_GET_COUNT => {
+            response[1] = 0;
+        }
+        id::DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => {
+            response[1..3].copy_from_slice(&0u16.to_be_bytes());
+        }
+        id::DYNAMIC_KEYMAP_MACRO_GET_BUFFER
+        | id::DYNAMIC_KEYMAP_MACRO_SET_BUFFER
+        | id::DYNAMIC_KEYMAP_MACRO_RESET => {}
+        id::DYNAMIC_KEYMAP_GET_LAYER_COUNT => {
+            response[1] = LAYER_COUNT as u8;
+        }
+        id::DYNAMIC_KEYMAP_GET_BUFFER | id::DYNAMIC_KEYMAP_SET_BUFFER => {
+            // the buffer is every layer's matrix as big endian keycodes
+            let first = u16::from_be_bytes([request[1], request[2]]) as usize / 2;
+            let count = (request[3] as usize).min(REPORT_LEN - 4) / 2;
+            for i in 0..count {
+                let (layer, input) = ((first + i) / (ROWS * COLS), (first + i) % (ROWS * COLS));
+                let data = 4 + i * 2;
+                if request[0] == id::DYNAMIC_KEYMAP_GET_BUFFER {
+                    let keycode = get_keycode(keymap, layer, input);
+                    response[data..data + 2].copy_from_slice(&keycode.to_be_bytes());
+                } else {
+                    let keycode = u16::from_be_bytes([request[data], request[data + 1]]);
+                    set_keycode(keymap, layer, input, keycode);
+                    state.mark_dirty();
+                }
+            }
+        }
+        _ => response[0] = id::UNHANDLED,
+    }
+    response
+}
+
+const _: () = assert!(ROWS * COLS >= INPUT_COUNT);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap_common::Keyboard;
+    use crate::wasd::{RunMode, WasdRun};
+
+    #[test]
+    fn modifiers_are_keycodes() {
+        for code in 0xE0..=0xE7 {
+            let mapping = keycode_to_mapping(code).unwrap();
+            assert_eq!(mapping.button as u16, code);
+            assert_eq!(mapping_to_keycode(&mapping), code);
+        }
+        assert_eq!(Keyboard::LeftControl.modifier_bit(), Some(0x01));
+        assert_eq!(Keyboard::LeftShift.modifier_bit(), Some(0x02));
+        assert_eq!(Keyboard::RightGUI.modifier_bit(), Some(0x80));
+        assert_eq!(Keyboard::KeypadHexadecimal.modifier_bit(), None);
+    }
+
+    fn request(bytes: &[u8]) -> [u8; REPORT_LEN] {
+        let mut request = [0; REPORT_LEN];
+        request[..bytes.len()].copy_from_slice(bytes);
+        request
+    }
+
+    #[test]
+    fn keycodes_by_matrix_position() {
+        let mut keymap = Keymap::default();
+        let mut state = ViaState::new();
+        // layer 1, row 1, column 2: input 9
+        let set = request(&[id::DYNAMIC_KEYMAP_SET_KEYCODE, 1, 1, 2, 0x00, 0x1D]);
+        assert_eq!(handle(&set, &mut keymap, &mut state), set);
+        let mapping = keymap.input_mappings(9).unwrap()[1];
+        assert!(mapping.to_raw() == Mapping::from_button(Keyboard::Z).to_raw());
+
+        let get = request(&[id::DYNAMIC_KEYMAP_GET_KEYCODE, 1, 1, 2]);
+        let response = handle(&get, &mut keymap, &mut state);
+        assert_eq!(
+            response[..6],
+            [id::DYNAMIC_KEYMAP_GET_KEYCODE, 1, 1, 2, 0x00, 0x1D]
+        );
+
+        // outside the matrix, or a layer that doesn't exist
+        let get = request(&[id::DYNAMIC_KEYMAP_GET_KEYCODE, 0, ROWS as u8, 0]);
+        assert_eq!(handle(&get, &mut keymap, &mut state)[4..6], [0, 0]);
+        let get = request(&[id::DYNAMIC_KEYMAP_GET_KEYCODE, LAYER_COUNT as u8, 1, 2]);
+        assert_eq!(handle(&get, &mut keymap, &mut state)[4..6], [0, 0]);
+
+        // a keycode without an equivalent leaves the mapping as it was
+        let set = request(&[id::DYNAMIC_KEYMAP_SET_KEYCODE, 1, 1, 2, 0x0F, 0xFF]);
+        handle(&set, &mut keymap, &mut state);
+        let mapping = keymap.input_mappings(9).unwrap()[1];
+        assert!(mapping.to_raw() == Mapping::from_button(Keyboard::Z).to_raw());
+    }
+
+    #[test]
+    fn keyboard_values() {
+        let mut keymap = Keymap::default();
+        let mut state = ViaState::new();
+        state.tick(12_345_678);
+        let uptime = request(&[id::GET_KEYBOARD_VALUE, keyboard_value::UPTIME]);
+        let response = handle(&uptime, &mut keymap, &mut state);
+        assert_eq!(response[2..6], 12_345u32.to_be_bytes());
+
+        // there are no layout options; setting them is accepted and changes nothing
+        let get = request(&[id::GET_KEYBOARD_VALUE, keyboard_value::LAYOUT_OPTIONS]);
+        assert_eq!(handle(&get, &mut keymap, &mut state)[2..6], [0; 4]);
+        let set = request(&[
+            id::SET_KEYBOARD_VALUE,
+            keyboard_value::LAYOUT_OPTIONS,
+            0,
+            0,
+            0,
+            1,
+        ]);
+        assert_eq!(
+            handle(&set, &mut keymap, &mut state)[0],
+            id::SET_KEYBOARD_VALUE
+        );
+        assert_eq!(handle(&get, &mut keymap, &mut state)[2..6], [0; 4]);
+        assert!(!state.tick(20_000_000));
+
+        let unknown = request(&[id::GET_KEYBOARD_VALUE, 0x7F]);
+        assert_eq!(handle(&unknown, &mut keymap, &mut state)[0], id::UNHANDLED);
+    }
+
+    #[test]
+    fn edits_are_saved_once_they_settle() {
+        let mut keymap = Keymap::default();
+        let mut state = ViaState::new();
+        assert!(!state.tick(5_000_000));
+        let set = request(&[id::DYNAMIC_KEYMAP_SET_KEYCODE, 0, 0, 0, 0x00, 0x04]);
+        handle(&set, &mut keymap, &mut state);
+        assert!(!state.tick(5_600_000));
+        // another edit starts the wait again
+        handle(&set, &mut keymap, &mut state);
+        assert!(!state.tick(6_400_000));
+        assert!(state.tick(6_600_000));
+        // saved once
+        assert!(!state.tick(9_000_000));
+    }
+
+    #[test]
+    fn reset_restores_run_mappings() {
+        let mut keymap = Keymap::default();
+        keymap.wasd_run[1] = WasdRun {
+            threshold: 800,
+            mode: RunMode::Replace,
+        };
+        keymap.wasd_run_mappings[0][1] = Mapping::from_button(Keyboard::LeftShift);
+        let mut request = [0; REPORT_LEN];
+        request[0] = id::DYNAMIC_KEYMAP_RESET;
+        handle(&request, &mut keymap, &mut ViaState::new());
+
+        let defaults = Keymap::default();
+        assert_eq!(keymap.wasd_run, defaults.wasd_run);
+        let mapping = keymap.wasd_run_mappings[0][1];
+        assert!(mapping.to_raw() == defaults.wasd_run_mappings[0][1].to_raw());
+    }
+}
@@ -0,0 +1,586 @@
+use crate::joystick::{StickVector, FULL_SCALE, PERMILLE};
+
+// WASD mode: the stick pressed as direction keys. The stick's magnitude and angle pick one of
+// four or eight sectors, and each sector holds one direction or, on a diagonal, two. Both the
+// magnitude and the angle have hysteresis, so a stick resting on a threshold or a sector edge
+// doesn't make the keys flicker. Past a second, per-layer threshold the stick runs: the layer's
+// run mappings are sent alongside or instead of the direction keys. Below that a direction can
+// pulse instead of being held, down for a share of each period that follows the tilt, for games
+// that only know walking at full speed.
+/// Directions in wasd_mappings order
+pub const UP: usize = 0;
+pub const LEFT: usize = 1;
+pub const DOWN: usize = 2;
+pub const RIGHT: usize = 3;
+pub const DIRECTION_COUNT: usize = 4;
+
+/// Widest a diagonal sector can grow, at which point the cardinal sectors vanish
+pub const MAX_DIAGONAL_OVERLAP: u16 = 45;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sectors {
+    /// Up, left, down and right only
+    Four,
+    /// Diagonals hold both neighbouring directions
+    Eight,
+}
+
+impl Sectors {
+    pub fn count(self) -> u8 {
+        match self {
+            Sectors::Four => 4,
+            Sectors::Eight => 8,
+        }
+    }
+
+    pub fn from_count(count: u8) -> Option<Sectors> {
+        match count {
+            4 => Some(Sectors::Four),
+            8 => Some(Sectors::Eight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WasdConfig {
+    pub sectors: Sectors,
+    /// Deflection, in thousandths, at which a direction is pressed
+    pub engage: u16,
+    /// Deflection, in thousandths, below which it is let go again
+    pub release: u16,
+    /// Degrees added to each diagonal sector's 45, taken from the cardinal ones. Eight-way only.
+    pub diagonal_overlap: u16,
+    /// Degrees past its edge the stick has to go before leaving a sector
+    pub hysteresis: u16,
+    /// Milliseconds per pulse while walking, 0 holds the direction down. Keys only change when
+    /// the inputs are scanned, at most 10 ms apart while reports are going out and further apart
+    /// once the pad idles, so each press and gap is rounded to whole scans.
+    pub pulse_period_ms: u16,
+    /// Shortest press or gap a pulse makes, so the host sees both. Below the 10 ms between scans
+    /// this can't be kept to, and a press or gap lasts a scan.
+    pub min_pulse_ms: u16,
+}
+
+impl WasdConfig {
+    pub const DEFAULT: WasdConfig = WasdConfig {
+        sectors: Sectors::Eight,
+        engage: 400,
+        release: 300,
+        diagonal_overlap: 10,
+        hysteresis: 10,
+        pulse_period_ms: 0,
+        min_pulse_ms: 20,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.engage <= PERMILLE
+            && self.release <= self.engage
+            && self.diagonal_overlap <= MAX_DIAGONAL_OVERLAP
+            && self.hysteresis < 90
+    }
+
+    /// Centre direction and width of `sector`, both in degrees as StickVector::angle has them
+    fn sector(&self, sector: u8) -> (u16, u16) {
+        match self.sectors {
+            Sectors::Four => (sector as u16 * 90, 90),
+            Sectors::Eight => {
+                let overlap = self.diagonal_overlap.min(MAX_DIAGONAL_OVERLAP);
+                let width = if sector.is_multiple_of(2) {
+                    45 - overlap
+                } else {
+                    45 + overlap
+                };
+                (sector as u16 * 45, width)
+            }
+        }
+    }
+
+    /// Whether `angle` is in `sector`, or within `margin` degrees past its edges
+    fn contains(&self, sector: u8, angle: u16, margin: u16) -> bool {
+        let (center, width) = self.sector(sector);
+        2 * angle_between(angle, center) <= width + 2 * margin
+    }
+
+    /// The first sector holding `angle`. The sectors cover the whole circle, so there always is
+    /// one.
+    fn sector_at(&self, angle: u16) -> Option<u8> {
+        (0..self.sectors.count()).find(|sector| self.contains(*sector, angle, 0))
+    }
+
+    /// Whether a walking direction is down `since_us` after it was pressed, with the stick at
+    /// `deflection` thousandths. The press lasts its share of the period but never less than
+    /// min_pulse_ms; if that leaves a gap shorter than min_pulse_ms the direction stays down.
+    fn pulse_is_down(&self, deflection: u16, since_us: u64) -> bool {
+        if self.pulse_period_ms == 0 {
+            return true;
+        }
+        let period = self.pulse_period_ms as u64 * 1000;
+        let min_pulse = self.min_pulse_ms as u64 * 1000;
+        let down = (period * deflection.min(PERMILLE) as u64 / PERMILLE as u64).max(min_pulse);
+        down + min_pulse > period || since_us % period < down
+    }
+
+    /// Directions held in `sector`
+    fn held(&self, sector: u8) -> [bool; DIRECTION_COUNT] {
+        // in angle order, starting along +x
+        const FOUR: [usize; 4] = [RIGHT, UP, LEFT, DOWN];
+        let mut held = [false; DIRECTION_COUNT];
+        match self.sectors {
+            Sectors::Four => held[FOUR[sector as usize]] = true,
+            Sectors::Eight => {
+                held[FOUR[sector as usize / 2]] = true;
+                if sector % 2 == 1 {
+                    held[FOUR[(sector as usize / 2 + 1) % 4]] = true;
+                }
+            }
+        }
+        held
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunMode {
+    /// Send the run mapping as well as the direction's own, such as a sprint key
+    Add,
+    /// Send the run mapping instead of the direction's own
+    Replace,
+}
+
+impl RunMode {
+    pub fn to_raw(self) -> u8 {
+        match self {
+            RunMode::Add => 0,
+            RunMode::Replace => 1,
+        }
+    }
+
+    pub fn from_raw(raw: u8) -> Option<RunMode> {
+        match raw {
+            0 => Some(RunMode::Add),
+            1 => Some(RunMode::Replace),
+            _ => None,
+        }
+    }
+}
+
+/// Walking and running on one layer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WasdRun {
+    /// Deflection, in thousandths, past which the stick runs; 0 never runs
+    pub threshold: u16,
+    pub mode: RunMode,
+}
+
+impl WasdRun {
+    pub const OFF: WasdRun = WasdRun {
+        threshold: 0,
+        mode: RunMode::Add,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.threshold <= PERMILLE
+    }
+}
+
+/// What the stick asks for in WASD mode
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Directions {
+    pub held: [bool; DIRECTION_COUNT],
+    /// Past the layer's run threshold
+    pub running: bool,
+}
+
+impl Directions {
+    pub const NONE: Directions = Directions {
+        held: [false; DIRECTION_COUNT],
+        running: false,
+    };
+}
+
+/// Degrees between two angles, 0..=180
+fn angle_between(a: u16, b: u16) -> u16 {
+    let difference = (a as i32 - b as i32).rem_euclid(360) as u16;
+    difference.min(360 - difference)
+}
+
+/// Follows the stick from scan to scan to turn it into directions.
+pub struct DirectionResolver {
+    sector: Option<u8>,
+    /// Stick deflection at the last update, in thousandths
+    magnitude: u16,
+    /// Whether the stick ran at the end of the previous scan
+    ran: bool,
+    running: bool,
+    /// When the current direction was pressed, pulses count from here
+    pressed_us: u64,
+}
+
+impl DirectionResolver {
+    pub fn new() -> DirectionResolver {
+        DirectionResolver {
+            sector: None,
+            magnitude: 0,
+            ran: false,
+            running: false,
+            pressed_us: 0,
+        }
+    }
+
+    /// Lets go of everything, as when WASD mode is turned off.
+    pub fn reset(&mut self) {
+        self.sector = None;
+        self.ran = false;
+        self.running = false;
+    }
+
+    /// Takes the latest stick position, centred and rotated, at the start of a scan.
+    pub fn update(&mut self, config: &WasdConfig, stick: StickVector, now_us: u64) {
+        let magnitude = stick.magnitude() as i64 * PERMILLE as i64 / FULL_SCALE as i64;
+        let threshold = match self.sector {
+            None => config.engage,
+            Some(_) => config.release.min(config.engage),
+        };
+        self.ran = self.running;
+        if magnitude < threshold as i64 || magnitude == 0 {
+            self.reset();
+            return;
+        }
+        self.magnitude = magnitude.min(PERMILLE as i64) as u16;
+
+        let angle = stick.angle();
+        let sector = match self.sector {
+            Some(sector)
+                if sector < config.sectors.count()
+                    && config.contains(sector, angle, config.hysteresis) =>
+            {
+                sector
+            }
+            _ => config.sector_at(angle).unwrap_or(0),
+        };
+        if self.sector.is_none() {
+            self.pressed_us = now_us;
+        }
+        self.sector = Some(sector);
+    }
+
+    /// Which directions are held at `now_us` with `run` as the layer's run settings. Running
+    /// stops as far below `run.threshold` as release is below engage. Asking again within a scan,
+    /// as after a layer change, answers afresh from where the previous scan left off.
+    pub fn directions(&mut self, config: &WasdConfig, run: &WasdRun, now_us: u64) -> Directions {
+        let Some(sector) = self.sector else {
+            self.running = false;
+            return Directions::NONE;
+        };
+        let run_threshold = if self.ran {
+            let gap = config.engage.saturating_sub(config.release);
+            run.threshold.saturating_sub(gap)
+        } else {
+            run.threshold
+        };
+        self.running = run.threshold > 0 && self.magnitude >= run_threshold;
+
+        let since = now_us.wrapping_sub(self.pressed_us);
+        let down = self.running || config.pulse_is_down(self.magnitude, since);
+        Directions {
+            held: if down {
+                config.held(sector)
+            } else {
+                [false; DIRECTION_COUNT]
+            },
+            running: self.running,
+        }
+    }
+}
+
+impl Default for DirectionResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN: WasdRun = WasdRun {
+        threshold: 800,
+        mode: RunMode::Add,
+    };
+
+    fn up(permille: i32) -> StickVector {
+        StickVector {
+            x: 0,
+            y: permille * FULL_SCALE / PERMILLE as i32,
+        }
+    }
+
+    /// The stick at `degrees`, as StickVector::angle has them, tilted `permille` of the way out
+    fn at(degrees: u16, permille: i32) -> StickVector {
+        let length = (permille * FULL_SCALE / PERMILLE as i32) as f64;
+        // angle rounds to whole degrees, so aim a little either side if need be
+        [0.0, -0.25, 0.25]
+            .into_iter()
+            .map(|nudge| {
+                let radians = (degrees as f64 + nudge).to_radians();
+                StickVector {
+                    x: (length * radians.cos()).round() as i32,
+                    y: (length * radians.sin()).round() as i32,
+                }
+            })
+            .find(|stick| stick.angle() == degrees)
+            .unwrap()
+    }
+
+    fn held_at(config: &WasdConfig, resolver: &mut DirectionResolver, degrees: u16) -> Vec<usize> {
+        resolver.update(config, at(degrees, 700), 0);
+        let held = resolver.directions(config, &WasdRun::OFF, 0).held;
+        (0..DIRECTION_COUNT).filter(|d| held[*d]).collect()
+    }
+
+    #[test]
+    fn four_way_sectors() {
+        let config = WasdConfig {
+            sectors: Sectors::Four,
+            ..WasdConfig::DEFAULT
+        };
+        for (degrees, direction) in [
+            (0, RIGHT),
+            (30, RIGHT),
+            (60, UP),
+            (90, UP),
+            (179, LEFT),
+            (250, DOWN),
+            (300, DOWN),
+            (330, RIGHT),
+        ] {
+            // from rest each time, so hysteresis doesn't come into it
+            let mut resolver = DirectionResolver::new();
+            assert_eq!(
+                held_at(&config, &mut resolver, degrees),
+                [direction],
+                "{degrees}"
+            );
+        }
+    }
+
+    #[test]
+    fn eight_way_diagonals_hold_two_keys() {
+        let config = WasdConfig {
+            diagonal_overlap: 0,
+            ..WasdConfig::DEFAULT
+        };
+        for (degrees, directions) in [
+            (0, vec![RIGHT]),
+            (45, vec![UP, RIGHT]),
+            (90, vec![UP]),
+            (135, vec![UP, LEFT]),
+            (180, vec![LEFT]),
+            (225, vec![LEFT, DOWN]),
+            (270, vec![DOWN]),
+            (315, vec![DOWN, RIGHT]),
+            (20, vec![RIGHT]),
+            (25, vec![UP, RIGHT]),
+        ] {
+            let mut resolver = DirectionResolver::new();
+            assert_eq!(
+                held_at(&config, &mut resolver, degrees),
+                directions,
+                "{degrees}"
+            );
+        }
+    }
+
+    #[test]
+    fn overlap_widens_the_diagonals() {
+        let narrow = WasdConfig {
+            diagonal_overlap: 0,
+            ..WasdConfig::DEFAULT
+        };
+        let wide = WasdConfig {
+            diagonal_overlap: 10,
+            ..WasdConfig::DEFAULT
+        };
+        // diagonals 55 wide, so a cardinal reaches 17 degrees either side
+        for (degrees, narrow_held, wide_held) in [
+            (17, vec![RIGHT], vec![RIGHT]),
+            (18, vec![RIGHT], vec![UP, RIGHT]),
+            (108, vec![UP], vec![UP, LEFT]),
+            (253, vec![DOWN], vec![DOWN]),
+            (288, vec![DOWN], vec![DOWN, RIGHT]),
+        ] {
+            let mut resolver = DirectionResolver::new();
+            assert_eq!(held_at(&narrow, &mut resolver, degrees), narrow_held);
+            let mut resolver = DirectionResolver::new();
+            assert_eq!(held_at(&wide, &mut resolver, degrees), wide_held);
+        }
+        // at the most, only the exact axes are left to the cardinals
+        let widest = WasdConfig {
+            diagonal_overlap: MAX_DIAGONAL_OVERLAP,
+            ..WasdConfig::DEFAULT
+        };
+        let mut resolver = DirectionResolver::new();
+        assert_eq!(held_at(&widest, &mut resolver, 0), [RIGHT]);
+        let mut resolver = DirectionResolver::new();
+        assert_eq!(held_at(&widest, &mut resolver, 1), [UP, RIGHT]);
+    }
+
+    #[test]
+    fn sector_edges_have_hysteresis() {
+        let config = WasdConfig {
+            diagonal_overlap: 0,
+            hysteresis: 10,
+            ..WasdConfig::DEFAULT
+        };
+        // the edge between right and up-right is at 22.5 degrees
+        let mut resolver = DirectionResolver::new();
+        assert_eq!(held_at(&config, &mut resolver, 0), [RIGHT]);
+        assert_eq!(held_at(&config, &mut resolver, 32), [RIGHT]);
+        assert_eq!(held_at(&config, &mut resolver, 33), [UP, RIGHT]);
+        assert_eq!(held_at(&config, &mut resolver, 13), [UP, RIGHT]);
+        assert_eq!(held_at(&config, &mut resolver, 12), [RIGHT]);
+
+        // letting go forgets the sector
+        resolver.update(&config, StickVector::ZERO, 0);
+        assert_eq!(held_at(&config, &mut resolver, 25), [UP, RIGHT]);
+    }
+
+    #[test]
+    fn every_angle_has_a_sector() {
+        // so the fallback to sector 0 never picks a direction the stick isn't pointing in
+        for sectors in [Sectors::Four, Sectors::Eight] {
+            for diagonal_overlap in 0..=MAX_DIAGONAL_OVERLAP {
+                let config = WasdConfig {
+                    sectors,
+                    diagonal_overlap,
+                    ..WasdConfig::DEFAULT
+                };
+                for angle in 0..360 {
+                    assert!(config.sector_at(angle).is_some(), "{angle}");
+                }
+            }
+        }
+
+        // a sector kept from an eight-way config is found again under four-way
+        let eight = WasdConfig::DEFAULT;
+        let four = WasdConfig {
+            sectors: Sectors::Four,
+            ..eight
+        };
+        let mut resolver = DirectionResolver::new();
+        assert_eq!(held_at(&eight, &mut resolver, 225), [LEFT, DOWN]);
+        assert_eq!(held_at(&four, &mut resolver, 225), [LEFT]);
+        assert_eq!(held_at(&four, &mut resolver, 240), [DOWN]);
+    }
+
+    const PULSING: WasdConfig = WasdConfig {
+        pulse_period_ms: 100,
+        min_pulse_ms: 20,
+        ..WasdConfig::DEFAULT
+    };
+
+    #[test]
+    fn pulses_follow_the_tilt() {
+        // half tilt: down for the first half of every period
+        for (since_us, down) in [
+            (0, true),
+            (49_999, true),
+            (50_000, false),
+            (99_999, false),
+            (100_000, true),
+            (250_000, false),
+        ] {
+            assert_eq!(PULSING.pulse_is_down(500, since_us), down, "{since_us}");
+        }
+        // no pulses without a period, and none past full tilt
+        let held = WasdConfig {
+            pulse_period_ms: 0,
+            ..PULSING
+        };
+        assert!(held.pulse_is_down(100, 50_000));
+        assert!(PULSING.pulse_is_down(2000, 50_000));
+    }
+
+    #[test]
+    fn pulses_last_at_least_min_pulse() {
+        // a tenth of the period is stretched to min_pulse
+        assert!(PULSING.pulse_is_down(100, 19_999));
+        assert!(!PULSING.pulse_is_down(100, 20_000));
+        assert!(PULSING.pulse_is_down(0, 10_000));
+        // a gap of exactly min_pulse is still made
+        assert!(PULSING.pulse_is_down(800, 79_999));
+        assert!(!PULSING.pulse_is_down(800, 80_000));
+        // a shorter gap isn't, so the direction stays down
+        for since_us in [0, 85_000, 95_000, 99_999, 185_000] {
+            assert!(PULSING.pulse_is_down(850, since_us));
+        }
+        // min_pulse past half the period leaves no room for a gap at all
+        let long = WasdConfig {
+            min_pulse_ms: 60,
+            ..PULSING
+        };
+        assert!(long.pulse_is_down(0, 70_000));
+    }
+
+    #[test]
+    fn pulses_count_from_the_press() {
+        let mut resolver = DirectionResolver::new();
+        resolver.update(&PULSING, up(500), 1_000_000);
+        assert!(resolver.directions(&PULSING, &RUN, 1_000_000).held[UP]);
+        resolver.update(&PULSING, up(500), 1_060_000);
+        assert!(!resolver.directions(&PULSING, &RUN, 1_060_000).held[UP]);
+        resolver.update(&PULSING, up(500), 1_100_000);
+        assert!(resolver.directions(&PULSING, &RUN, 1_100_000).held[UP]);
+        // running holds the direction down
+        resolver.update(&PULSING, up(850), 1_160_000);
+        let running = resolver.directions(&PULSING, &RUN, 1_160_000);
+        assert!(running.held[UP] && running.running);
+    }
+
+    #[test]
+    fn runs_past_the_layer_threshold() {
+        let config = WasdConfig::DEFAULT;
+        let mut resolver = DirectionResolver::new();
+        resolver.update(&config, up(500), 0);
+        let walking = resolver.directions(&config, &RUN, 0);
+        assert_eq!(walking.held, [true, false, false, false]);
+        assert!(!walking.running);
+
+        resolver.update(&config, up(850), 1);
+        assert!(resolver.directions(&config, &RUN, 1).running);
+        // back under the threshold but within the hysteresis
+        resolver.update(&config, up(750), 2);
+        assert!(resolver.directions(&config, &RUN, 2).running);
+        resolver.update(&config, up(650), 3);
+        assert!(!resolver.directions(&config, &RUN, 3).running);
+    }
+
+    #[test]
+    fn asking_again_uses_the_last_run_settings() {
+        let config = WasdConfig::DEFAULT;
+        let mut resolver = DirectionResolver::new();
+        resolver.update(&config, up(850), 0);
+        assert!(resolver.directions(&config, &RUN, 0).running);
+
+        // a layer change within the scan: the second answer stands
+        resolver.update(&config, up(750), 1);
+        assert!(resolver.directions(&config, &RUN, 1).running);
+        assert!(!resolver.directions(&config, &WasdRun::OFF, 1).running);
+        resolver.update(&config, up(750), 2);
+        assert!(!resolver.directions(&config, &RUN, 2).running);
+    }
+
+    #[test]
+    fn below_release_lets_go() {
+        let config = WasdConfig::DEFAULT;
+        let mut resolver = DirectionResolver::new();
+        resolver.update(&config, up(350), 0);
+        assert_eq!(resolver.directions(&config, &RUN, 0), Directions::NONE);
+        resolver.update(&config, up(450), 1);
+        assert!(resolver.directions(&config, &RUN, 1).held[UP]);
+        resolver.update(&config, up(350), 2);
+        assert!(resolver.directions(&config, &RUN, 2).held[UP]);
+        resolver.update(&config, up(250), 3);
+        assert_eq!(resolver.directions(&config, &RUN, 3), Directions::NONE);
+    }
+}
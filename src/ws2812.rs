@@ -1,3 +1,5 @@
+use crate::color::scale8;
+use crate::led_map::ColorOrder;
 use teensy4_bsp::board;
 use teensy4_bsp::hal;
 // borrowed from the NeoPixel library
@@ -24,64 +26,74 @@ const GAMMA_TABLE: [u8; 256] = [
 // max RGB value = 63.75
 // using gamma correction this amounts to 150
 const MAX_COLOR_VALUE: u8 = 150;
-const LED_COUNT: usize = 24;
 const T0H_NS: fugit::Duration<u32, 1, 1000000> = fugit::Duration::<u32, 1, 1000000>::nanos(275u32);
 const T1H_NS: fugit::Duration<u32, 1, 1000000> = fugit::Duration::<u32, 1, 1000000>::nanos(750u32);
 const T0L_NS: fugit::Duration<u32, 1, 1000000> = fugit::Duration::<u32, 1, 1000000>::nanos(750u32);
 const T1L_NS: fugit::Duration<u32, 1, 1000000> = fugit::Duration::<u32, 1, 1000000>::nanos(275u32);
 const RESET_US: u32 = 300;
 
-pub type LedPin = teensy4_pins::t41::P41;
-pub struct WS2812 {
-    colors: [[u8; 3]; LED_COUNT], // stored in GRB with each component in reversed bit order using reverse_bits()
-    output: hal::gpio::Output<LedPin>,
+pub struct WS2812<P, const N: usize> {
+    colors: [[u8; 4]; N], // stored in wire order with each component in reversed bit order using reverse_bits()
+    order: ColorOrder,
+    /// Scales MAX_COLOR_VALUE, 255 for full brightness
+    brightness: u8,
+    output: hal::gpio::Output<P>,
     timer: hal::timer::BlockingPit<1, { board::PERCLK_FREQUENCY }>,
 }
 
-impl WS2812 {
-    pub fn new(output: hal::gpio::Output<LedPin>, pit1: hal::pit::Pit<1>) -> WS2812 {
+impl<P, const N: usize> WS2812<P, N> {
+    pub fn new(
+        output: hal::gpio::Output<P>,
+        pit1: hal::pit::Pit<1>,
+        order: ColorOrder,
+    ) -> WS2812<P, N> {
         let timer = hal::timer::Blocking::<_, { board::PERCLK_FREQUENCY }>::from_pit(pit1);
-        WS2812 {
-            colors: [[
-                GAMMA_TABLE[MAX_COLOR_VALUE as usize].reverse_bits(),
-                GAMMA_TABLE[MAX_COLOR_VALUE as usize].reverse_bits(),
-                GAMMA_TABLE[MAX_COLOR_VALUE as usize].reverse_bits(),
-            ]; LED_COUNT],
+        let mut leds = WS2812 {
+            colors: [[0; 4]; N],
+            order,
+            brightness: 255,
             output,
             timer,
+        };
+        for i in 0..N {
+            leds.set_color(i, [255, 255, 255]);
         }
+        leds
     }
 
     pub fn set_color(&mut self, index: usize, color: [u8; 3]) {
-        assert!(index < LED_COUNT);
+        assert!(index < N);
         // apply maximum
+        let max = scale8(MAX_COLOR_VALUE, self.brightness);
         let max_corrected = [
-            unsafe {
-                (color[0] as f32 * (MAX_COLOR_VALUE as f32 / 255.0)).to_int_unchecked::<usize>()
-            },
-            unsafe {
-                (color[1] as f32 * (MAX_COLOR_VALUE as f32 / 255.0)).to_int_unchecked::<usize>()
-            },
-            unsafe {
-                (color[2] as f32 * (MAX_COLOR_VALUE as f32 / 255.0)).to_int_unchecked::<usize>()
-            },
+            scale8(color[0], max),
+            scale8(color[1], max),
+            scale8(color[2], max),
         ];
         let gamma_corrected = [
-            GAMMA_TABLE[max_corrected[0]],
-            GAMMA_TABLE[max_corrected[1]],
-            GAMMA_TABLE[max_corrected[2]],
+            GAMMA_TABLE[max_corrected[0] as usize],
+            GAMMA_TABLE[max_corrected[1] as usize],
+            GAMMA_TABLE[max_corrected[2] as usize],
         ];
-        // set to format understood by the WS2812 (GBR with reversed bits)
+        // set to the byte order the strip expects, with reversed bits
+        let arranged = self.order.arrange(gamma_corrected);
         self.colors[index] = [
-            gamma_corrected[1].reverse_bits(),
-            gamma_corrected[0].reverse_bits(),
-            gamma_corrected[2].reverse_bits(),
+            arranged[0].reverse_bits(),
+            arranged[1].reverse_bits(),
+            arranged[2].reverse_bits(),
+            arranged[3].reverse_bits(),
         ];
     }
 
+    /// Takes effect for colours set from now on.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
     pub fn show(&mut self) {
+        let channels = self.order.channels();
         for led in self.colors {
-            for color in led {
+            for color in led.into_iter().take(channels) {
                 for i in 0..8 {
                     let high = color >> (7 - i) & 1;
                     self.output.set();